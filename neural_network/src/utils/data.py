@@ -9,6 +9,11 @@ from tqdm.auto import tqdm
 
 DATA_DIR = "games"
 
+# Must match TENSOR_ENCODING_VERSION in src/board.rs, the version of the flat encoding
+TENSOR_ENCODING_VERSION = 2
+# Samples written before the version was recorded share the encoding of version 1
+LEGACY_TENSOR_ENCODING_VERSION = 1
+
 def augment(states, policies, values):
     N = states.size(0)
 
@@ -38,6 +43,8 @@ def parse_game_data_from_json():
     policies = []
     values = []
     states = []
+    # Samples written with augment_symmetries already hold every symmetry of their position
+    is_augmented = []
 
     filenames = [os.path.join(DATA_DIR, f) for f in os.listdir(DATA_DIR)]
     for filename in tqdm(filenames):
@@ -45,6 +52,17 @@ def parse_game_data_from_json():
             data = json.load(f)
 
         for game_data in data:
+            version = game_data.get("metadata", {}).get("version", LEGACY_TENSOR_ENCODING_VERSION)
+            if version != TENSOR_ENCODING_VERSION:
+                raise ValueError(
+                    f"{filename} uses tensor encoding version {version}, expected {TENSOR_ENCODING_VERSION}."
+                )
+
+            if game_data.get("metadata", {}).get("history_length", 0) != 0:
+                raise ValueError(f"{filename} uses the history encoding, which is not supported yet.")
+
+            is_augmented.append(game_data.get("metadata", {}).get("symmetry") is not None)
+
             states.append(game_data["state"])
             policies.append(game_data["policy"])
             values.append(game_data["value"])
@@ -54,7 +72,10 @@ def parse_game_data_from_json():
     values = torch.FloatTensor(values).unsqueeze(1)
 
     # Data augmentation
-    states, policies, values = augment(states, policies, values)
+    if any(is_augmented) and not all(is_augmented):
+        raise ValueError("Samples with and without symmetry augmentation cannot be mixed.")
+    if not any(is_augmented):
+        states, policies, values = augment(states, policies, values)
 
     torch.save((states, policies, values), "data_conv.pt")
 
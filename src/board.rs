@@ -4,9 +4,19 @@ use indexmap::IndexSet;
 extern crate ndarray;
 use ndarray::prelude::*;
 
+use itertools::Itertools;
+
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::renju;
+
+use serde::{Deserialize, Serialize};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Player {
     Black,
     White,
@@ -35,30 +45,241 @@ impl Player {
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Debug)]
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
 pub enum SquareState {
     Occupied(Player),
     Vacant,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Outcome {
     Winner(Player),
+    /// The board is full without a winner. This is the only kind of draw: every action
+    /// adds a stone and none is ever removed, so a position can never repeat and draws
+    /// by repetition cannot happen.
     Draw,
 }
 
+impl Outcome {
+    /// Returns 1.0 if `player` won, -1.0 if they lost and 0.0 for a draw.
+    pub fn score_for(&self, player: Player) -> f32 {
+        match self {
+            Outcome::Winner(winner) if *winner == player => 1.0,
+            Outcome::Winner(_) => -1.0,
+            Outcome::Draw => 0.0,
+        }
+    }
+}
+
+/// The threats of a player, by number of stones short of `n_in_a_row`: fours are one
+/// stone short, threes two and twos three, whatever `n_in_a_row` is.
+/// A threat is open when both of its ends are vacant, and closed when only one is.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ThreatsCount {
+    pub open_four: usize,
+    pub closed_four: usize,
+    pub open_three: usize,
+    pub closed_three: usize,
+    pub open_two: usize,
+}
+
+/// Version of the tensor encodings of the board, e.g. `to_array` and `to_flat_array`.
+/// It must be incremented whenever one of them changes, so that training samples
+/// written with an older encoding are not silently mixed with newer ones.
+///
+/// Version 2 encodes White's stones as -1.0 in `to_flat_array`, rather than 0.0 like
+/// the vacant squares, so that the position can be decoded from the flat encoding.
+/// The other encodings did not change, so their samples keep `HISTORY_ENCODING_VERSION`.
+pub const TENSOR_ENCODING_VERSION: u32 = 2;
+
+/// Version of `to_history_array`, which has not changed since the first version.
+pub const HISTORY_ENCODING_VERSION: u32 = 1;
+
+/// The rules the game is played with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RulesVariant {
+    /// Any `n_in_a_row` or more aligned stones win, for either player.
+    Standard,
+    /// Black may not make an overline, two fours or two open threes with a single move.
+    Renju,
+}
+
+/// The rotations and reflections of a board. The first 4 keep the number of rows and
+/// columns, while the last 4 swap them, so only square boards have all 8.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Symmetry {
+    Identity,
+    Rotate180,
+    /// Mirrors the columns, i.e. across the vertical axis.
+    FlipLeftRight,
+    /// Mirrors the rows, i.e. across the horizontal axis.
+    FlipUpDown,
+    /// Rotates clockwise by a quarter turn.
+    Rotate90,
+    /// Rotates counterclockwise by a quarter turn.
+    Rotate270,
+    /// Mirrors across the diagonal from the top left square.
+    Transpose,
+    /// Mirrors across the diagonal from the top right square.
+    AntiTranspose,
+}
+
+pub const SYMMETRIES: [Symmetry; 8] = [
+    Symmetry::Identity,
+    Symmetry::Rotate180,
+    Symmetry::FlipLeftRight,
+    Symmetry::FlipUpDown,
+    Symmetry::Rotate90,
+    Symmetry::Rotate270,
+    Symmetry::Transpose,
+    Symmetry::AntiTranspose,
+];
+
+impl Symmetry {
+    /// Maps `action` of a board with `rows` and `cols` to the transformed board.
+    pub fn transform_action(&self, action: Action, rows: usize, cols: usize) -> Action {
+        let [r, c] = action;
+        let [last_row, last_col] = [rows - 1, cols - 1];
+        match self {
+            Symmetry::Identity => [r, c],
+            Symmetry::Rotate180 => [last_row - r, last_col - c],
+            Symmetry::FlipLeftRight => [r, last_col - c],
+            Symmetry::FlipUpDown => [last_row - r, c],
+            Symmetry::Rotate90 => [c, last_row - r],
+            Symmetry::Rotate270 => [last_col - c, r],
+            Symmetry::Transpose => [c, r],
+            Symmetry::AntiTranspose => [last_col - c, last_row - r],
+        }
+    }
+
+    /// Returns the number of rows and columns of the transformed board.
+    pub fn transform_dims(&self, rows: usize, cols: usize) -> (usize, usize) {
+        match self {
+            Symmetry::Rotate90
+            | Symmetry::Rotate270
+            | Symmetry::Transpose
+            | Symmetry::AntiTranspose => (cols, rows),
+            _ => (rows, cols),
+        }
+    }
+
+    /// Maps a flat policy over the actions of a board with `rows` and `cols`
+    /// to the flat policy over the transformed actions.
+    pub fn transform_flat_policy(&self, policy: &[f32], rows: usize, cols: usize) -> Vec<f32> {
+        let (_, transformed_cols) = self.transform_dims(rows, cols);
+        let mut transformed_policy = vec![0f32; policy.len()];
+        for row_index in 0..rows {
+            for col_index in 0..cols {
+                let [r, c] = self.transform_action([row_index, col_index], rows, cols);
+                transformed_policy[r * transformed_cols + c] = policy[row_index * cols + col_index];
+            }
+        }
+
+        transformed_policy
+    }
+
+    pub fn inverse(&self) -> Symmetry {
+        match self {
+            Symmetry::Rotate90 => Symmetry::Rotate270,
+            Symmetry::Rotate270 => Symmetry::Rotate90,
+            symmetry => *symmetry,
+        }
+    }
+}
+
+/// Maps `action` of a square board of `size` to the same square of `Board::rotate_90`.
+/// Policies are transformed back by applying it 3 more times.
+pub fn rotate_90_action(action: Action, size: usize) -> Action {
+    Symmetry::Rotate90.transform_action(action, size, size)
+}
+
+/// Maps `action` of a square board of `size` to the same square of
+/// `Board::reflect_horizontal`, which is its own inverse.
+pub fn reflect_horizontal_action(action: Action, size: usize) -> Action {
+    Symmetry::FlipLeftRight.transform_action(action, size, size)
+}
+
+/// Broad categories of board sizes, used to pick size-dependent parameters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SizeClass {
+    /// Up to 5x5
+    Small,
+    /// Up to 9x9
+    Medium,
+    /// Up to 13x13
+    Large,
+    /// Larger than 13x13
+    VeryLarge,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BoardError {
+    /// The requested ply is past the `n_plies` actions played so far.
+    PlyOutOfRange { ply: usize, n_plies: usize },
+    /// The action is outside of the board.
+    OutOfBounds(Action),
+    /// The square of the action already has a stone.
+    Occupied(Action),
+    /// More than `MAX_HANDICAP_STONES` handicap stones were given.
+    TooManyHandicapStones { n_stones: usize },
+    /// The handicap stone placed on the action completes `n_in_a_row`.
+    HandicapWins(Action),
+    /// The action to undo is not the last action played.
+    NotLastAction(Action),
+    /// The action is forbidden to the player to move by the rules variant.
+    ForbiddenMove(Action),
+    /// The planes of an encoded board do not have a consistent shape,
+    /// or hold values other than 0.0 and 1.0.
+    InvalidEncoding,
+    /// The stones and turn of a position cannot be reached by alternating moves,
+    /// e.g. when both players have `n_in_a_row` or the turn does not match the stone counts.
+    UnreachablePosition,
+}
+
+/// Why a string could not be parsed as an Action.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The string does not start with the letter of a column of the board.
+    InvalidColumn(String),
+    /// The column letter is not followed by the number of a row of the board.
+    InvalidRow(String),
+    /// The string is not the `to_compact_string` of a reachable position.
+    InvalidCompactString(String),
+    /// A move of the list is not the color to play followed by a square in brackets,
+    /// as written by `moves_to_string`.
+    InvalidMoveList(String),
+}
+
+/// Traditional maximum number of handicap stones.
+pub const MAX_HANDICAP_STONES: usize = 9;
+
+/// The largest number of rows or cols, as columns are named by a single letter.
+pub const MAX_BOARD_SIZE: usize = 26;
+
 pub type Action = [usize; 2];
+
+/// Seed of the Zobrist keys, fixed so that boards of the same size hash alike.
+const ZOBRIST_SEED: u64 = 0x5EED_601D;
+
+/// Mixed into `Board::hash_stones` when White is to move.
+const ZOBRIST_WHITE_TO_MOVE_KEY: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Number of squares `is_draw_possible` may fill before giving up on an exact answer.
+const DRAW_SEARCH_BUDGET: usize = 100_000;
 type BaseBoardLocation = [usize; 2];
 
-#[derive(Debug, Clone)]
+/// One direction per kind of line: horizontal, vertical, diagonal and anti-diagonal.
+const LINE_DIRECTIONS: [[i32; 2]; 4] = [[0, 1], [1, 0], [1, 1], [1, -1]];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BaseBoard {
     data: Array<SquareState, Ix2>,
 }
 
 impl BaseBoard {
-    pub fn new(size: usize) -> Self {
+    pub fn new(rows: usize, cols: usize) -> Self {
         Self {
-            data: Array::<SquareState, Ix2>::from_elem((size, size), SquareState::Vacant),
+            data: Array::<SquareState, Ix2>::from_elem((rows, cols), SquareState::Vacant),
         }
     }
 
@@ -78,57 +299,196 @@ impl BaseBoard {
         *self.get(location) == SquareState::Occupied(player)
     }
 
+    pub fn clear(&mut self, location: BaseBoardLocation) {
+        self.data[location] = SquareState::Vacant;
+    }
+
     pub fn reset(&mut self) {
         self.data.fill(SquareState::Vacant);
     }
+
+    /// Returns the squares of row `row`, in base board coordinates, i.e. padding included.
+    pub fn get_row(&self, row: usize) -> &[SquareState] {
+        self.data
+            .row(row)
+            .to_slice()
+            .expect("The rows of a standard layout array are contiguous.")
+    }
+
+    /// Returns the squares of column `col`, in base board coordinates. Unlike rows,
+    /// columns are not contiguous in memory, so they are gathered into a Vec.
+    pub fn get_col(&self, col: usize) -> Vec<&SquareState> {
+        self.data.column(col).into_iter().collect()
+    }
+
+    /// Returns at most `len` squares from `start` included, stepping by `direction`
+    /// as [row step, col step], e.g. [1, 1] for a diagonal, until the edge of the board.
+    pub fn get_diagonal(
+        &self,
+        start: BaseBoardLocation,
+        direction: [i32; 2],
+        len: usize,
+    ) -> Vec<&SquareState> {
+        let (rows, cols) = self.data.dim();
+        let [mut row, mut col] = [start[0] as i64, start[1] as i64];
+        let mut squares = Vec::with_capacity(len);
+        while squares.len() < len
+            && (0..rows as i64).contains(&row)
+            && (0..cols as i64).contains(&col)
+        {
+            squares.push(self.get([row as usize, col as usize]));
+            row += direction[0] as i64;
+            col += direction[1] as i64;
+        }
+
+        squares
+    }
+
+    /// Counts the consecutive stones of `player` from `start` included, stepping by
+    /// `direction` as [row step, col step], up to `max_len`.
+    /// Stops at the edge of the board or at the first square not occupied by `player`.
+    pub fn count_consecutive(
+        &self,
+        start: BaseBoardLocation,
+        direction: [i32; 2],
+        player: Player,
+        max_len: usize,
+    ) -> usize {
+        let (rows, cols) = self.data.dim();
+        let [mut row, mut col] = [start[0] as i64, start[1] as i64];
+        let mut count = 0;
+        while count < max_len
+            && (0..rows as i64).contains(&row)
+            && (0..cols as i64).contains(&col)
+            && self.is_occupied_by([row as usize, col as usize], player)
+        {
+            count += 1;
+            row += direction[0] as i64;
+            col += direction[1] as i64;
+        }
+
+        count
+    }
 }
 
+/// Serializes the position and history. The lookup tables derived from the dimensions
+/// are skipped, and rebuilt by `from_json`.
+#[derive(Serialize, Deserialize)]
 pub struct Board {
-    pub size: usize,
+    pub rows: usize,
+    pub cols: usize,
     pub n_in_a_row: usize,
+    pub rules: RulesVariant,
+    /// Whether the rules treat both players alike, apart from Black moving first, so that
+    /// swapping the colors of a position gives an equally valid one, see `symmetries`.
+    #[serde(default)]
+    pub symmetric_rules: bool,
     pub turn: Player,
     pub base_board: BaseBoard,
     pub outcome: Option<Outcome>,
     pub num_stones_placed: usize,
+    black_stones: usize,
+    white_stones: usize,
+    action_history: Vec<Action>,
+    #[serde(skip)]
+    zobrist_keys: Vec<u64>,
+    hash: u64,
     legal_actions_indexset: IndexSet<Action>,
-    action_to_check_indices: HashMap<Action, Vec<Vec<BaseBoardLocation>>>,
+    /// The flat index of each of the legal actions, in the same order.
+    #[serde(skip)]
+    legal_flat_indices: Vec<usize>,
+    /// The locations to check for a win after each action, indexed by its flat index.
+    #[serde(skip)]
+    action_to_check_indices: Vec<Vec<Vec<BaseBoardLocation>>>,
 }
 
 impl Board {
     /// Creates a new instance of Board.
-    /// * `size` - The width and height of the board
+    /// * `rows` - The height of the board
+    /// * `cols` - The width of the board
     /// * `n_in_a_row` - The number of aligned pieces needed to win.
     ///
-    /// e.g. size=3 and n_in_a_row=3 is TicTacToe
-    /// e.g. size=15 and n_in_a_row=5 is Gomoku
-    pub fn new(size: usize, n_in_a_row: usize) -> Self {
-        assert!(size <= 26, "The maximum supported board size is 26.");
-        assert!(n_in_a_row <= size, "n_in_a_row cannot be larger than size.");
+    /// e.g. rows=3, cols=3 and n_in_a_row=3 is TicTacToe
+    /// e.g. rows=15, cols=15 and n_in_a_row=5 is Gomoku
+    pub fn new(rows: usize, cols: usize, n_in_a_row: usize) -> Self {
+        assert!(
+            rows <= MAX_BOARD_SIZE && cols <= MAX_BOARD_SIZE,
+            "The maximum supported board size is {MAX_BOARD_SIZE}."
+        );
+        assert!(
+            n_in_a_row <= rows.max(cols),
+            "n_in_a_row cannot be larger than both rows and cols."
+        );
         assert!(n_in_a_row > 1, "n_in_a_row must be at least 2.");
 
-        let base_board_size = size + (n_in_a_row - 1) * 2;
-        let base_board = BaseBoard::new(base_board_size);
+        let padding = (n_in_a_row - 1) * 2;
+        let base_board = BaseBoard::new(rows + padding, cols + padding);
 
-        let legal_actions_indexset = IndexSet::with_capacity(size * size);
-        let action_to_check_indices = HashMap::new();
+        let legal_actions_indexset = IndexSet::with_capacity(rows * cols);
+        let action_to_check_indices = Vec::with_capacity(rows * cols);
 
         let mut board = Self {
-            size,
+            rows,
+            cols,
             n_in_a_row,
+            rules: RulesVariant::Standard,
+            symmetric_rules: false,
             base_board,
             legal_actions_indexset,
+            legal_flat_indices: Vec::with_capacity(rows * cols),
             action_to_check_indices,
             turn: Player::Black,
             outcome: None,
             num_stones_placed: 0,
+            black_stones: 0,
+            white_stones: 0,
+            action_history: Vec::new(),
+            zobrist_keys: Vec::new(),
+            hash: 0,
         };
 
         board.initialize_legal_actions_indexset();
         board.initialize_action_to_check_locations();
+        board.initialize_zobrist_keys();
         board
     }
 
-    pub fn make_action(&mut self, action: Action) -> Result<Action, ()> {
+    /// Creates a new square Board of `size` by `size`.
+    pub fn new_square(size: usize, n_in_a_row: usize) -> Self {
+        Self::new(size, size, n_in_a_row)
+    }
+
+    /// Parses a board serialized by `to_json`.
+    pub fn from_json(s: &str) -> Result<Self, serde_json::Error> {
+        let mut board: Self = serde_json::from_str(s)?;
+        board.legal_flat_indices = board
+            .legal_actions_indexset
+            .iter()
+            .map(|action| board.action_to_flat_index(action))
+            .collect();
+        board.initialize_action_to_check_locations();
+        board.initialize_zobrist_keys();
+        Ok(board)
+    }
+
+    /// Serializes the board to JSON, to be parsed back by `from_json`.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Boards should always serialize.")
+    }
+
+    /// Returns the board with the game played under `rules`.
+    pub fn with_rules(mut self, rules: RulesVariant) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Returns the board with `symmetric_rules` set.
+    pub fn with_symmetric_rules(mut self, symmetric_rules: bool) -> Self {
+        self.symmetric_rules = symmetric_rules;
+        self
+    }
+
+    pub fn make_action(&mut self, action: Action) -> Result<Action, BoardError> {
         if self.is_game_over() {
             panic!("Cannot make action as the game is already over.");
         }
@@ -136,13 +496,60 @@ impl Board {
         let base_board_location = self.action_to_base_board_location(action);
         // Cannot place a stone on an occupied square
         if self.base_board.is_occupied(base_board_location) {
-            return Err(());
+            return Err(BoardError::Occupied(action));
+        }
+        if self.is_forbidden(action) {
+            return Err(BoardError::ForbiddenMove(action));
+        }
+
+        // SAFETY: Just checked that the square is vacant and not forbidden.
+        unsafe { self.make_action_unchecked(action) };
+        assert_eq!(
+            self.black_stones + self.white_stones,
+            self.num_stones_placed,
+            "The per-player stone counts should add up to the number of stones placed."
+        );
+
+        Ok(action)
+    }
+
+    /// Places a stone for the current player like `make_action`, but skips checking
+    /// that the square is vacant and not forbidden outside of debug builds.
+    /// Meant for hot loops such as rollouts, which pick from `legal_actions()`.
+    ///
+    /// Panics if the game is already over.
+    ///
+    /// # Safety
+    /// `action` must be in `legal_actions()` and must not be forbidden.
+    /// Otherwise the stones, legal actions and stone count become inconsistent.
+    pub unsafe fn make_action_unchecked(&mut self, action: Action) {
+        if self.is_game_over() {
+            panic!("Cannot make action as the game is already over.");
         }
+        debug_assert!(
+            self.is_action_legal(action),
+            "Cannot place a stone on an occupied square."
+        );
+        debug_assert!(!self.is_forbidden(action), "Cannot play a forbidden move.");
+
+        self.make_action_and_check_unchecked(action);
+
+        debug_assert_eq!(self.stone_count(Player::Black), self.black_stones);
+        debug_assert_eq!(self.stone_count(Player::White), self.white_stones);
+        debug_assert_eq!(self.total_stone_count(), self.num_stones_placed);
+    }
 
+    /// Places a stone for the current player and returns the resulting outcome,
+    /// skipping the game over and occupied square checks of `make_action`.
+    ///
+    /// # Safety
+    /// The game must not be over and `action` must be in `legal_actions()`,
+    /// e.g. an action of a child node expanded from this exact position.
+    /// Otherwise the stones, legal actions and stone count become inconsistent.
+    pub unsafe fn make_action_and_check_unchecked(&mut self, action: Action) -> Option<Outcome> {
         // Place stone
-        self.base_board.set(base_board_location, self.turn);
-        self.legal_actions_indexset.remove(&action);
-        self.num_stones_placed += 1;
+        self.place_stone_unchecked(action, self.turn);
+        self.action_history.push(action);
 
         // Check for an outcome
         // If no winner nor draw, switch the turn.
@@ -151,63 +558,623 @@ impl Board {
             self.turn = self.turn.opposite();
         }
 
-        Ok(action)
+        self.outcome
     }
 
-    pub fn parse_string_to_action(&self, string: &String) -> Result<Action, ()> {
-        if string.len() < 2 {
-            return Err(());
+    /// Places a stone of `player` on the vacant square `action` and updates the
+    /// legal actions and stone counts, without touching the turn, outcome or history.
+    fn place_stone_unchecked(&mut self, action: Action, player: Player) {
+        let base_board_location = self.action_to_base_board_location(action);
+        self.base_board.set(base_board_location, player);
+        // Both are swap removes, which keeps the flat indices in the order of the actions.
+        if let Some((index, _)) = self.legal_actions_indexset.swap_remove_full(&action) {
+            self.legal_flat_indices.swap_remove(index);
+        }
+        self.num_stones_placed += 1;
+        match player {
+            Player::Black => self.black_stones += 1,
+            Player::White => self.white_stones += 1,
         }
+        self.hash ^= self.zobrist_key(action, player);
+    }
 
-        let row_string = (string[1..]).to_string();
-        let col_string = (string[0..1]).to_string();
+    /// Creates a new Board where Black starts with the handicap `black_stones`
+    /// already placed, and White to move.
+    /// The handicap stones are not part of the action history.
+    ///
+    /// Returns `Err` if there are more than `MAX_HANDICAP_STONES` stones,
+    /// or if a stone is out of bounds, overlaps another or completes `n_in_a_row`.
+    pub fn from_handicap_positions(
+        size: usize,
+        n_in_a_row: usize,
+        black_stones: &[Action],
+    ) -> Result<Self, BoardError> {
+        if black_stones.len() > MAX_HANDICAP_STONES {
+            return Err(BoardError::TooManyHandicapStones {
+                n_stones: black_stones.len(),
+            });
+        }
 
-        let (row_names_hashmap, col_names_hashmap) = get_names_hashmaps(self.size);
-        let row_index = row_names_hashmap.get(&row_string);
-        let col_index = col_names_hashmap.get(&col_string);
+        let mut board = Self::new_square(size, n_in_a_row);
+        for &action in black_stones {
+            if !board.is_position_in_bounds(action) {
+                return Err(BoardError::OutOfBounds(action));
+            }
+            if *board.square_state(action) != SquareState::Vacant {
+                return Err(BoardError::Occupied(action));
+            }
+            if board.action_wins_for(action, Player::Black) {
+                return Err(BoardError::HandicapWins(action));
+            }
 
-        if row_index.is_none() || col_index.is_none() {
-            return Err(());
+            board.place_stone_unchecked(action, Player::Black);
         }
+        board.turn = Player::White;
+
+        Ok(board)
+    }
 
-        let row_index = row_index.expect("Just checked is_none().");
-        let col_index = col_index.expect("Just checked is_none().");
-        let action = [*row_index, *col_index] as Action;
+    /// Parses a move read from stdin, see `algebraic_to_action`.
+    pub fn parse_string_to_action(&self, string: &String) -> Result<Action, ()> {
+        self.algebraic_to_action(string).map_err(|_| ())
+    }
 
-        Ok(action)
+    /// Parses algebraic notation, e.g. "H8", into an Action. The column letter may be
+    /// lowercase, and the row number may not have leading zeros or a sign.
+    /// This is the inverse of `action_to_algebraic`.
+    pub fn algebraic_to_action(&self, string: &str) -> Result<Action, ParseError> {
+        parse_algebraic(string, self.rows, self.cols)
     }
 
     /// Creates and returns a HashSet of legal moves as strings, e.g. "A1".
     /// Can be used with `place_stone`.
     pub fn legal_moves_as_strings(&self) -> HashSet<String> {
-        let (row_names, col_names) = get_row_col_names(self.size);
         let mut legal_moves_hashset: HashSet<String> =
             HashSet::with_capacity(self.legal_actions_indexset.len());
 
         for action in self.legal_actions_indexset.iter() {
-            let [row_index, col_index] = *action;
-            let legal_move = col_names[col_index].clone() + &row_names[row_index];
-            legal_moves_hashset.insert(legal_move);
+            legal_moves_hashset.insert(self.action_to_algebraic(*action));
         }
         legal_moves_hashset
     }
 
+    /// Converts an Action to algebraic notation, e.g. "A1" for the bottom left square.
+    /// This is the inverse of `algebraic_to_action`.
+    pub fn action_to_algebraic(&self, action: Action) -> String {
+        format_algebraic(action, self.rows)
+    }
+
+    #[deprecated(note = "Use `action_to_algebraic`.")]
+    pub fn action_to_notation(&self, action: Action) -> String {
+        self.action_to_algebraic(action)
+    }
+
+    /// Returns the actions played so far as space-separated notation, e.g. "B2 A1 C3".
+    pub fn serialize_action_history(&self) -> String {
+        self.action_history
+            .iter()
+            .map(|action| self.action_to_algebraic(*action))
+            .join(" ")
+    }
+
+    /// Parses space-separated notation, as produced by `serialize_action_history`,
+    /// into Actions for a board of this size.
+    pub fn deserialize_action_history(&self, string: &str) -> Result<Vec<Action>, ()> {
+        string
+            .split_whitespace()
+            .map(|move_string| self.parse_string_to_action(&move_string.to_string()))
+            .collect()
+    }
+
+    /// Formats `history` as a numbered move list for logging games, e.g.
+    /// "1. B[H8] W[I9] 2. B[J7]", in the algebraic notation of a `size` x `size` board.
+    pub fn moves_to_string(history: &[Action], size: usize) -> String {
+        history
+            .chunks(2)
+            .enumerate()
+            .map(|(move_index, actions)| {
+                let mut string = format!(
+                    "{}. B[{}]",
+                    move_index + 1,
+                    format_algebraic(actions[0], size)
+                );
+                if let Some(&white_action) = actions.get(1) {
+                    string += &format!(" W[{}]", format_algebraic(white_action, size));
+                }
+                string
+            })
+            .join(" ")
+    }
+
+    /// Parses a move list written by `moves_to_string` back into the history of a
+    /// `size` x `size` board, to be replayed with `apply_actions`.
+    pub fn moves_from_string(string: &str, size: usize) -> Result<Vec<Action>, ParseError> {
+        string
+            .split_whitespace()
+            .filter(|token| !token.ends_with('.'))
+            .enumerate()
+            .map(|(index, token)| {
+                let color = if index % 2 == 0 { "B" } else { "W" };
+                token
+                    .strip_prefix(color)
+                    .and_then(|token| token.strip_prefix('['))
+                    .and_then(|token| token.strip_suffix(']'))
+                    .ok_or_else(|| ParseError::InvalidMoveList(token.to_string()))
+                    .and_then(|algebraic| parse_algebraic(algebraic, size, size))
+            })
+            .collect()
+    }
+
+    /// Creates a new Board and plays `actions` on it in order.
+    /// Returns `Err` if any of the actions cannot be played.
+    pub fn from_actions(
+        rows: usize,
+        cols: usize,
+        n_in_a_row: usize,
+        actions: &[Action],
+    ) -> Result<Self, ()> {
+        let mut board = Self::new(rows, cols, n_in_a_row);
+        board.apply_actions(actions).map_err(|_| ())?;
+
+        Ok(board)
+    }
+
+    /// Plays `actions` in order from the current position, the inverse of `move_history`.
+    /// Stops at the first action that cannot be played, because it is illegal or the game
+    /// is already over, and returns `Err` with its index. The actions before it stay played.
+    pub fn apply_actions(&mut self, actions: &[Action]) -> Result<(), usize> {
+        for (index, &action) in actions.iter().enumerate() {
+            if self.is_game_over() || self.make_action(action).is_err() {
+                return Err(index);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Returns a reference to `self.legal_moves_indices_indexset`
     pub fn legal_actions(&self) -> &IndexSet<Action> {
         &self.legal_actions_indexset
     }
 
+    /// Returns the `action_to_flat_index` of each of the `legal_actions`, in the same
+    /// order, kept up to date as actions are made and undone rather than recomputed.
+    pub fn legal_actions_as_flat_indices(&self) -> &[usize] {
+        &self.legal_flat_indices
+    }
+
+    /// Returns whether `action` is on a vacant square, in constant time.
+    /// Under renju rules, a legal action can still be forbidden, see `is_forbidden`.
+    pub fn is_action_legal(&self, action: Action) -> bool {
+        self.legal_actions_indexset.contains(&action)
+    }
+
+    /// Returns the Euclidean distance from `action` to the center of the board, which
+    /// falls between squares on boards of even size.
+    pub fn distance_to_center(&self, action: Action) -> f32 {
+        let center_row = (self.rows - 1) as f32 / 2.0;
+        let center_col = (self.cols - 1) as f32 / 2.0;
+        (action[0] as f32 - center_row).hypot(action[1] as f32 - center_col)
+    }
+
+    /// Returns whether the row and col of `action` are within the board.
+    pub fn is_position_in_bounds(&self, action: Action) -> bool {
+        let [row, col] = action;
+        row < self.rows && col < self.cols
+    }
+
+    /// Returns the state of the square at `action`.
+    pub fn square_state(&self, action: Action) -> &SquareState {
+        self.base_board
+            .get(self.action_to_base_board_location(action))
+    }
+
+    /// Returns every row, column and diagonal of the board as a list of Actions,
+    /// each ordered from one edge of the board to the other.
+    /// Diagonals shorter than `n_in_a_row` cannot contain a win and are skipped.
+    pub fn lines(&self) -> Vec<Vec<Action>> {
+        let rows = self.rows as i32;
+        let cols = self.cols as i32;
+
+        let mut starts_and_directions: Vec<([i32; 2], [i32; 2])> = Vec::new();
+        for i in 0..rows {
+            starts_and_directions.push(([i, 0], [0, 1]));
+            starts_and_directions.push(([i, 0], [1, 1]));
+            if i > 0 {
+                starts_and_directions.push(([i, cols - 1], [1, -1]));
+            }
+        }
+        for j in 0..cols {
+            starts_and_directions.push(([0, j], [1, 0]));
+            starts_and_directions.push(([0, j], [1, -1]));
+            if j > 0 {
+                starts_and_directions.push(([0, j], [1, 1]));
+            }
+        }
+
+        let mut lines = Vec::new();
+        for ([mut row_index, mut col_index], [row_step, col_step]) in starts_and_directions {
+            let mut line = Vec::new();
+            while (0..rows).contains(&row_index) && (0..cols).contains(&col_index) {
+                line.push([row_index as usize, col_index as usize] as Action);
+                row_index += row_step;
+                col_index += col_step;
+            }
+
+            if line.len() >= self.n_in_a_row {
+                lines.push(line);
+            }
+        }
+
+        lines
+    }
+
+    /// Counts the threats of `player` along every row, column and diagonal, as features
+    /// for hand-crafted evaluations, e.g. when no model is available.
+    ///
+    /// A threat is a run of consecutive stones of `player`, with at least one vacant end
+    /// and enough squares free of the opponent's stones around it to reach `n_in_a_row`.
+    /// Runs with a gap, e.g. "X.XX", are not counted.
+    pub fn count_threats(&self, player: Player) -> ThreatsCount {
+        let mut threats = ThreatsCount::default();
+        let is_player = |state: &SquareState| *state == SquareState::Occupied(player);
+
+        for line in self.lines() {
+            let squares: Vec<&SquareState> = line
+                .iter()
+                .map(|action| self.square_state(*action))
+                .collect();
+
+            let mut start = 0;
+            while start < squares.len() {
+                if !is_player(squares[start]) {
+                    start += 1;
+                    continue;
+                }
+
+                let mut end = start;
+                while end + 1 < squares.len() && is_player(squares[end + 1]) {
+                    end += 1;
+                }
+
+                // The squares around the run that are not the opponent's, up to the edges.
+                let mut room_start = start;
+                while room_start > 0
+                    && *squares[room_start - 1] != SquareState::Occupied(player.opposite())
+                {
+                    room_start -= 1;
+                }
+                let mut room_end = end;
+                while room_end + 1 < squares.len()
+                    && *squares[room_end + 1] != SquareState::Occupied(player.opposite())
+                {
+                    room_end += 1;
+                }
+
+                let n_open_ends = [start.checked_sub(1), Some(end + 1)]
+                    .iter()
+                    .flatten()
+                    .filter(|&&index| {
+                        index < squares.len() && *squares[index] == SquareState::Vacant
+                    })
+                    .count();
+                let n_stones = end - start + 1;
+
+                if n_open_ends > 0 && room_end - room_start + 1 >= self.n_in_a_row {
+                    let is_open = n_open_ends == 2;
+                    match self.n_in_a_row.checked_sub(n_stones) {
+                        Some(1) if is_open => threats.open_four += 1,
+                        Some(1) => threats.closed_four += 1,
+                        Some(2) if is_open => threats.open_three += 1,
+                        Some(2) => threats.closed_three += 1,
+                        Some(3) if is_open => threats.open_two += 1,
+                        _ => (),
+                    }
+                }
+
+                start = end + 1;
+            }
+        }
+
+        threats
+    }
+
+    /// Returns the number of stones `player` has on the board, in O(1).
+    pub fn num_stones_placed_by(&self, player: Player) -> usize {
+        match player {
+            Player::Black => self.black_stones,
+            Player::White => self.white_stones,
+        }
+    }
+
+    /// Counts the stones of `player` by scanning the board. `num_stones_placed_by`
+    /// gives the same count in O(1), from counters kept up to date by every move.
+    pub fn stone_count(&self, player: Player) -> usize {
+        self.base_board
+            .data
+            .iter()
+            .filter(|square_state| **square_state == SquareState::Occupied(player))
+            .count()
+    }
+
+    /// Counts the stones of both players by scanning the board,
+    /// which should match `num_stones_placed`.
+    pub fn total_stone_count(&self) -> usize {
+        self.stone_count(Player::Black) + self.stone_count(Player::White)
+    }
+
+    /// Returns the number of squares without a stone.
+    pub fn num_vacant_cells(&self) -> usize {
+        self.rows * self.cols - self.num_stones_placed
+    }
+
+    /// Returns whether the game can still end in a draw, i.e. whether the vacant squares
+    /// can be filled with the remaining stones of both players without either of them
+    /// getting `n_in_a_row`.
+    ///
+    /// The search is exact for small boards or nearly full ones. When it exceeds
+    /// `DRAW_SEARCH_BUDGET` squares filled, it gives up and assumes a draw is possible.
+    pub fn is_draw_possible(&self) -> bool {
+        if self.is_game_over() {
+            return false;
+        }
+
+        let vacant_actions: Vec<Action> = self.legal_actions_indexset.iter().copied().collect();
+        let n_stones_to_move = (vacant_actions.len() + 1) / 2;
+        let mut n_remaining_stones = [n_stones_to_move, vacant_actions.len() - n_stones_to_move];
+        let mut budget = DRAW_SEARCH_BUDGET;
+
+        self.clone()
+            .search_drawn_completion(
+                &vacant_actions,
+                [self.turn, self.turn.opposite()],
+                &mut n_remaining_stones,
+                &mut budget,
+            )
+            .unwrap_or(true)
+    }
+
+    /// Depth-first search for a way to fill `vacant_actions` with the remaining stones of
+    /// `players` such that no one gets `n_in_a_row`.
+    /// Returns `None` if the budget ran out before the answer was known.
+    fn search_drawn_completion(
+        &mut self,
+        vacant_actions: &[Action],
+        players: [Player; 2],
+        n_remaining_stones: &mut [usize; 2],
+        budget: &mut usize,
+    ) -> Option<bool> {
+        let (action, other_vacant_actions) = match vacant_actions.split_first() {
+            Some((action, other_vacant_actions)) => (*action, other_vacant_actions),
+            None => return Some(true),
+        };
+
+        let mut is_exhausted = false;
+        for (i, player) in players.into_iter().enumerate() {
+            if n_remaining_stones[i] == 0 || self.action_wins_for(action, player) {
+                continue;
+            }
+            if *budget == 0 {
+                return None;
+            }
+            *budget -= 1;
+
+            let base_board_location = self.action_to_base_board_location(action);
+            self.base_board.set(base_board_location, player);
+            n_remaining_stones[i] -= 1;
+
+            let result = self.search_drawn_completion(
+                other_vacant_actions,
+                players,
+                n_remaining_stones,
+                budget,
+            );
+
+            n_remaining_stones[i] += 1;
+            self.base_board.clear(base_board_location);
+
+            match result {
+                Some(true) => return Some(true),
+                Some(false) => (),
+                None => is_exhausted = true,
+            }
+        }
+
+        if is_exhausted {
+            None
+        } else {
+            Some(false)
+        }
+    }
+
+    /// Categorizes the board by its longest side.
+    pub fn size_class(&self) -> SizeClass {
+        match self.rows.max(self.cols) {
+            0..=5 => SizeClass::Small,
+            6..=9 => SizeClass::Medium,
+            10..=13 => SizeClass::Large,
+            _ => SizeClass::VeryLarge,
+        }
+    }
+
     /// Returns whether the game has ended, based on `self.outcome`.
     pub fn is_game_over(&self) -> bool {
         self.outcome.is_some()
     }
 
+    /// Same as `is_game_over`, named as in search algorithms.
+    pub fn is_terminal(&self) -> bool {
+        self.is_game_over()
+    }
+
+    /// Returns the `Outcome::score_for` of `player` if the game is over, or `None`.
+    pub fn game_result_for(&self, player: Player) -> Option<f32> {
+        self.outcome.map(|outcome| outcome.score_for(player))
+    }
+
+    /// Returns a legal action that would immediately win the game for `player`, if any.
+    pub fn winning_move_for(&self, player: Player) -> Option<Action> {
+        if self.is_game_over() {
+            return None;
+        }
+
+        self.legal_actions_indexset
+            .iter()
+            .find(|action| {
+                self.action_wins_for(**action, player) && !self.is_forbidden_for(**action, player)
+            })
+            .copied()
+    }
+
+    /// Returns the first legal action after which `check_outcome` would report
+    /// `Outcome::Winner(player)`, without playing it. Cheap enough to be called before
+    /// every search to catch immediate wins and threats.
+    pub fn check_immediate_win(&self, player: Player) -> Option<Action> {
+        self.winning_move_for(player)
+    }
+
+    /// Returns all the legal actions that would immediately win the game for `player`.
+    pub fn winning_moves_for(&self, player: Player) -> Vec<Action> {
+        if self.is_game_over() {
+            return Vec::new();
+        }
+
+        self.legal_actions_indexset
+            .iter()
+            .filter(|action| {
+                self.action_wins_for(**action, player) && !self.is_forbidden_for(**action, player)
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Returns the actions that decide the game on the next move: the winning moves of
+    /// `player` if there are any, otherwise the moves blocking the opponent's winning moves.
+    /// Empty if neither player threatens to win.
+    pub fn critical_actions_for(&self, player: Player) -> Vec<Action> {
+        let winning_moves = self.winning_moves_for(player);
+        if !winning_moves.is_empty() {
+            return winning_moves;
+        }
+
+        self.winning_moves_for(player.opposite())
+    }
+
+    /// Returns a copy of the board with `action` played, leaving this board untouched.
+    pub fn simulate_action(&self, action: Action) -> Result<Board, ()> {
+        if self.is_game_over() {
+            return Err(());
+        }
+
+        let mut board = self.clone();
+        board.make_action(action).map_err(|_| ())?;
+        Ok(board)
+    }
+
+    /// Checks whether the rules forbid the player to move from playing `action`.
+    /// Only Black has forbidden moves, under Renju rules.
+    pub fn is_forbidden(&self, action: Action) -> bool {
+        self.is_forbidden_for(action, self.turn)
+    }
+
+    fn is_forbidden_for(&self, action: Action, player: Player) -> bool {
+        self.rules == RulesVariant::Renju
+            && player == Player::Black
+            && renju::is_forbidden_for_black(self, action)
+    }
+
+    /// Determines with a 2-ply lookahead whether `player` can force a win.
+    /// * `Some(true)` - `player` wins, either on the spot or because the opponent
+    /// has to move while `player` threatens to win in two different places.
+    /// * `Some(false)` - The opponent wins in the same way, or the game ended without
+    /// `player` winning.
+    /// * `None` - The result cannot be determined within 2 plies.
+    pub fn can_player_force_win(&self, player: Player) -> Option<bool> {
+        if let Some(outcome) = self.outcome {
+            return match outcome {
+                Outcome::Winner(winner) => Some(winner == player),
+                Outcome::Draw => Some(false),
+            };
+        }
+
+        // The player to move wins if they have a winning move.
+        if self.winning_move_for(self.turn).is_some() {
+            return Some(self.turn == player);
+        }
+
+        // Otherwise, two distinct winning moves for the other player cannot both be blocked.
+        let waiting_player = self.turn.opposite();
+        if self.winning_moves_for(waiting_player).len() >= 2 {
+            return Some(waiting_player == player);
+        }
+
+        None
+    }
+
+    /// Checks whether placing a stone of `player` on `action` would complete
+    /// `n_in_a_row`, without modifying the board.
+    fn action_wins_for(&self, action: Action, player: Player) -> bool {
+        let [row, col] = self.action_to_base_board_location(action);
+        let needed = self.n_in_a_row - 1;
+
+        // Counts the stones on both sides of `action`, which the stone would join.
+        LINE_DIRECTIONS.iter().any(|&[row_step, col_step]| {
+            let count_from = |row_step: i32, col_step: i32| {
+                let neighbor = [
+                    (row as i64 + row_step as i64) as usize,
+                    (col as i64 + col_step as i64) as usize,
+                ];
+                self.base_board
+                    .count_consecutive(neighbor, [row_step, col_step], player, needed)
+            };
+            count_from(row_step, col_step) + count_from(-row_step, -col_step) >= needed
+        })
+    }
+
+    /// Returns the `n_in_a_row` actions forming the winning line, ordered along the line,
+    /// or `None` if the game has not been won.
+    /// The line is found again from the last action, which is the one that won, or from
+    /// every stone of the winner for positions without a history, e.g. `new_from_position`.
+    pub fn winning_line(&self) -> Option<Vec<Action>> {
+        let winner = match self.outcome {
+            Some(Outcome::Winner(winner)) => winner,
+            _ => return None,
+        };
+
+        self.last_action()
+            .and_then(|last_action| self.winning_line_through(last_action, winner))
+            .or_else(|| {
+                (0..self.rows)
+                    .flat_map(|row_index| {
+                        (0..self.cols).map(move |col_index| [row_index, col_index])
+                    })
+                    .filter(|&action| *self.square_state(action) == SquareState::Occupied(winner))
+                    .find_map(|action| self.winning_line_through(action, winner))
+            })
+    }
+
+    /// Returns the `n_in_a_row` stones of `player` through `action`, if any.
+    fn winning_line_through(&self, action: Action, player: Player) -> Option<Vec<Action>> {
+        let check_locations = &self.action_to_check_indices[self.action_to_flat_index(&action)];
+
+        check_locations.iter().find_map(|locations| {
+            locations
+                .windows(self.n_in_a_row)
+                .find(|w| {
+                    w.iter()
+                        .all(|location| self.base_board.is_occupied_by(*location, player))
+                })
+                .map(|w| {
+                    w.iter()
+                        .map(|location| self.base_board_location_to_action(*location))
+                        .collect()
+                })
+        })
+    }
+
     /// Checks whether the action made resulted in an Outcome.
     fn check_outcome(&self, action: Action) -> Option<Outcome> {
-        let check_locations = self
-            .action_to_check_indices
-            .get(&action)
-            .expect("These should be pre-computed.");
+        let check_locations = &self.action_to_check_indices[self.action_to_flat_index(&action)];
 
         if check_locations
             .iter()
@@ -216,7 +1183,7 @@ impl Board {
             return Some(Outcome::Winner(self.turn));
         }
 
-        if self.num_stones_placed == self.size * self.size {
+        if self.num_stones_placed == self.rows * self.cols {
             return Some(Outcome::Draw);
         }
 
@@ -233,12 +1200,6 @@ impl Board {
         })
     }
 
-    /// Returns the size of the base board,
-    /// which is the `size` with padding on either side.
-    fn base_board_size(&self) -> usize {
-        self.size + self.base_board_padding() * 2
-    }
-
     /// Returns the padding on either side of the base board.
     fn base_board_padding(&self) -> usize {
         self.n_in_a_row - 1
@@ -254,7 +1215,7 @@ impl Board {
 
     /// Converts an Action to a flat index
     pub fn action_to_flat_index(&self, action: &Action) -> usize {
-        action[0] * self.size + action[1]
+        action[0] * self.cols + action[1]
     }
 
     /// Converts a BaseBoardLocation to an Action
@@ -271,30 +1232,297 @@ impl Board {
         self.turn = Player::Black;
         self.outcome = None;
         self.num_stones_placed = 0;
+        self.black_stones = 0;
+        self.white_stones = 0;
+        self.action_history.clear();
+        self.hash = 0;
         self.initialize_legal_actions_indexset();
     }
 
-    /// Initializes an IndexSet containing all legal moves
-    /// by iterating through pairs of `row_index` and `col_index`,
+    /// Reverses `action`, which must be the last action played, restoring the board
+    /// to its previous state without having to clone it beforehand.
+    pub fn undo_action(&mut self, action: Action) -> Result<(), BoardError> {
+        if self.action_history.last() != Some(&action) {
+            return Err(BoardError::NotLastAction(action));
+        }
+        self.action_history.pop();
+
+        // The turn is only switched after an action that did not end the game.
+        let player = if self.is_game_over() {
+            self.turn
+        } else {
+            self.turn.opposite()
+        };
+
+        // Remove stone
+        let base_board_location = self.action_to_base_board_location(action);
+        self.base_board.clear(base_board_location);
+        if self.legal_actions_indexset.insert(action) {
+            self.legal_flat_indices
+                .push(self.action_to_flat_index(&action));
+        }
+        self.num_stones_placed -= 1;
+        match player {
+            Player::Black => self.black_stones -= 1,
+            Player::White => self.white_stones -= 1,
+        }
+        self.hash ^= self.zobrist_key(action, player);
+
+        self.turn = player;
+        self.outcome = None;
+
+        Ok(())
+    }
+
+    /// Returns the Zobrist hash of the stones on the board, which is updated incrementally
+    /// as actions are made and undone. Boards of the same size share the same keys,
+    /// so their hashes can be compared.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Returns a Zobrist fingerprint of the stones on the board and the player to move,
+    /// computed by scanning every square rather than incrementally like `hash`.
+    /// Positions reached through different move orders share the same fingerprint,
+    /// which makes it suitable for finding duplicate positions in training data.
+    pub fn hash_stones(&self) -> u64 {
+        let stones_hash =
+            (0..self.rows)
+                .cartesian_product(0..self.cols)
+                .fold(0, |hash, (row, col)| match self.square_state([row, col]) {
+                    SquareState::Occupied(player) => hash ^ self.zobrist_key([row, col], *player),
+                    SquareState::Vacant => hash,
+                });
+
+        match self.turn {
+            Player::Black => stones_hash,
+            Player::White => stones_hash ^ ZOBRIST_WHITE_TO_MOVE_KEY,
+        }
+    }
+
+    /// Returns the Zobrist key of a stone of `player` on the square `action`.
+    fn zobrist_key(&self, action: Action, player: Player) -> u64 {
+        let player_index = match player {
+            Player::Black => 0,
+            Player::White => 1,
+        };
+        self.zobrist_keys[self.action_to_flat_index(&action) * 2 + player_index]
+    }
+
+    /// Initializes a random key for every pair of square and player.
+    fn initialize_zobrist_keys(&mut self) {
+        let mut rng = StdRng::seed_from_u64(ZOBRIST_SEED);
+        self.zobrist_keys = (0..self.rows * self.cols * 2).map(|_| rng.gen()).collect();
+    }
+
+    /// Returns the actions played so far, in order.
+    pub fn move_history(&self) -> &[Action] {
+        &self.action_history
+    }
+
+    /// Returns the action played last, or `None` if no action was played,
+    /// e.g. on a board created from a position.
+    pub fn last_action(&self) -> Option<Action> {
+        self.action_history.last().copied()
+    }
+
+    /// Counts the positions reached after exactly `depth` actions from this one.
+    /// Games that end before `depth` actions do not count.
+    pub fn perft(&self, depth: usize) -> u64 {
+        self.clone().count_leaves(depth)
+    }
+
+    fn count_leaves(&mut self, depth: usize) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        if self.is_game_over() {
+            return 0;
+        }
+
+        let actions: Vec<Action> = self.legal_actions().iter().copied().collect();
+        let mut n_leaves = 0;
+        for action in actions {
+            // Skip the actions forbidden by the rules variant.
+            if self.make_action(action).is_err() {
+                continue;
+            }
+            n_leaves += self.count_leaves(depth - 1);
+            self.undo_action(action).expect("The action was just made.");
+        }
+
+        n_leaves
+    }
+
+    /// Restores the board to its state after the first `ply` actions of the history,
+    /// by undoing the later ones. Stones placed outside of the history, e.g. handicap
+    /// stones or those of `new_from_position`, are kept.
+    pub fn undo_to_ply(&mut self, ply: usize) -> Result<(), BoardError> {
+        if ply > self.action_history.len() {
+            return Err(BoardError::PlyOutOfRange {
+                ply,
+                n_plies: self.action_history.len(),
+            });
+        }
+
+        while self.action_history.len() > ply {
+            let action = *self.action_history.last().expect("Longer than `ply`.");
+            self.undo_action(action)
+                .expect("The last action of the history can be undone.");
+        }
+
+        Ok(())
+    }
+
+    /// Enumerates the positions reachable from this one within `depth` actions that are
+    /// not forbidden, level by level, for building an opening book.
+    /// Positions equivalent under a rotation or reflection of the board are only kept once,
+    /// and the enumeration stops once `max_variations` positions have been collected.
+    pub fn generate_opening_variations(&self, depth: usize, max_variations: usize) -> Vec<Board> {
+        let mut variations: Vec<Board> = Vec::new();
+        let mut seen_keys = HashSet::from([self.symmetry_key()]);
+        let mut parents = vec![self.clone()];
+
+        for _ in 0..depth {
+            let level_start = variations.len();
+
+            for parent in parents.iter().filter(|board| !board.is_game_over()) {
+                for &action in parent.legal_actions() {
+                    if variations.len() >= max_variations {
+                        return variations;
+                    }
+                    if parent.is_forbidden(action) {
+                        continue;
+                    }
+
+                    let mut board = parent.clone();
+                    board.make_action(action).expect(
+                        "Legal actions that are not forbidden should not result in an error.",
+                    );
+                    if seen_keys.insert(board.symmetry_key()) {
+                        variations.push(board);
+                    }
+                }
+            }
+
+            parents = variations[level_start..].to_vec();
+        }
+
+        variations
+    }
+
+    /// Returns the board transformed by each of the 8 `SYMMETRIES`, along with the symmetry,
+    /// which maps the actions of this board to those of the transformed one.
+    /// The stones, the action history, the turn and the outcome are all transformed.
+    /// On rectangular boards, the last 4 transformed boards have their dimensions swapped.
+    ///
+    /// With `symmetric_rules`, each transformed board is followed by its `flip_colors`,
+    /// for 16 boards in all. Those can be told apart by their turn, which is flipped.
+    pub fn symmetries(&self) -> Vec<(Board, Symmetry)> {
+        let mut symmetries = Vec::with_capacity(2 * SYMMETRIES.len());
+        for symmetry in SYMMETRIES {
+            let transformed = self.transformed(symmetry);
+            let flipped = self.symmetric_rules.then(|| transformed.flip_colors());
+            symmetries.push((transformed, symmetry));
+            if let Some(flipped) = flipped {
+                symmetries.push((flipped, symmetry));
+            }
+        }
+
+        symmetries
+    }
+
+    /// Returns the board transformed by `symmetry`, as in `symmetries`.
+    pub fn transformed(&self, symmetry: Symmetry) -> Board {
+        let (rows, cols) = symmetry.transform_dims(self.rows, self.cols);
+        let mut board = Board::new(rows, cols, self.n_in_a_row)
+            .with_rules(self.rules)
+            .with_symmetric_rules(self.symmetric_rules);
+
+        for row_index in 0..self.rows {
+            for col_index in 0..self.cols {
+                let action = [row_index, col_index];
+                if let SquareState::Occupied(player) = self.square_state(action) {
+                    let transformed_action =
+                        symmetry.transform_action(action, self.rows, self.cols);
+                    board.place_stone_unchecked(transformed_action, *player);
+                }
+            }
+        }
+
+        board.action_history = self
+            .action_history
+            .iter()
+            .map(|action| symmetry.transform_action(*action, self.rows, self.cols))
+            .collect();
+        board.turn = self.turn;
+        board.outcome = self.outcome;
+
+        board
+    }
+
+    /// Returns the board rotated clockwise by a quarter turn. Together with
+    /// `reflect_horizontal`, it generates all 8 symmetries of a square board.
+    pub fn rotate_90(&self) -> Board {
+        self.transformed(Symmetry::Rotate90)
+    }
+
+    /// Returns the board mirrored left to right.
+    pub fn reflect_horizontal(&self) -> Board {
+        self.transformed(Symmetry::FlipLeftRight)
+    }
+
+    /// Returns a key that is identical for positions equivalent under any of the
+    /// rotations and reflections of the board, i.e. the smallest encoding among them.
+    /// Square boards have 8 such symmetries, rectangular boards only the first 4.
+    fn symmetry_key(&self) -> Vec<u8> {
+        let n_symmetries = if self.rows == self.cols { 8 } else { 4 };
+
+        SYMMETRIES[..n_symmetries]
+            .iter()
+            .map(|symmetry| {
+                let mut key = vec![0u8; self.rows * self.cols + 1];
+                for row_index in 0..self.rows {
+                    for col_index in 0..self.cols {
+                        let [r, c] =
+                            symmetry.transform_action([row_index, col_index], self.rows, self.cols);
+                        key[r * self.cols + c] = match self.square_state([row_index, col_index]) {
+                            SquareState::Occupied(Player::Black) => 1,
+                            SquareState::Occupied(Player::White) => 2,
+                            SquareState::Vacant => 0,
+                        };
+                    }
+                }
+                key[self.rows * self.cols] = self.turn.to_bool() as u8;
+                key
+            })
+            .min()
+            .expect("There are at least 4 symmetries.")
+    }
+
+    /// Initializes an IndexSet containing all legal moves
+    /// by iterating through pairs of `row_index` and `col_index`,
     /// then converting them to an Action.
     fn initialize_legal_actions_indexset(&mut self) {
         self.legal_actions_indexset.clear();
-        for row_index in 0..self.size {
-            for col_index in 0..self.size {
-                self.legal_actions_indexset
-                    .insert([row_index, col_index] as Action);
+        self.legal_flat_indices.clear();
+        for row_index in 0..self.rows {
+            for col_index in 0..self.cols {
+                let action = [row_index, col_index] as Action;
+                self.legal_actions_indexset.insert(action);
+                self.legal_flat_indices
+                    .push(self.action_to_flat_index(&action));
             }
         }
     }
 
     /// Initializes the BaseBoardLocations to be checked for a winning condition for an action.
     fn initialize_action_to_check_locations(&mut self) {
-        self.action_to_check_indices = HashMap::new();
-
-        for row_index in 0..self.size {
-            for col_index in 0..self.size {
-                let action = [row_index, col_index] as Action;
+        self.action_to_check_indices = Vec::with_capacity(self.rows * self.cols);
 
+        // Actions are visited in the order of their flat index.
+        for row_index in 0..self.rows {
+            for col_index in 0..self.cols {
                 let mut horizontal: Vec<BaseBoardLocation> = Vec::new();
                 let mut vertical: Vec<BaseBoardLocation> = Vec::new();
                 let mut forward_slash: Vec<BaseBoardLocation> = Vec::new();
@@ -332,18 +1560,55 @@ impl Board {
                 check_indices.push(vertical);
                 check_indices.push(forward_slash);
                 check_indices.push(backward_slash);
-                self.action_to_check_indices.insert(action, check_indices);
+                self.action_to_check_indices.push(check_indices);
             }
         }
     }
 
+    /// Returns the position from the perspective of the player to move, as if they were
+    /// Black: when White is to move, the colors of the stones, the turn and the winner
+    /// are swapped. The action history is not kept, as the swapped position cannot be
+    /// reached with Black moving first.
+    pub fn canonical_form(&self) -> Board {
+        if self.turn == Player::Black {
+            return self.clone();
+        }
+
+        self.flip_colors()
+    }
+
+    /// Returns a copy of the position with the colors of the stones, the turn and the winner
+    /// swapped, and an empty action history. Under rules that favor Black, the flipped
+    /// position is not equivalent to this one, see `symmetric_rules`.
+    pub fn flip_colors(&self) -> Board {
+        let mut board = Board::new(self.rows, self.cols, self.n_in_a_row)
+            .with_rules(self.rules)
+            .with_symmetric_rules(self.symmetric_rules);
+        for row_index in 0..self.rows {
+            for col_index in 0..self.cols {
+                let action = [row_index, col_index];
+                if let SquareState::Occupied(player) = self.square_state(action) {
+                    board.place_stone_unchecked(action, player.opposite());
+                }
+            }
+        }
+
+        board.turn = self.turn.opposite();
+        board.outcome = match self.outcome {
+            Some(Outcome::Winner(winner)) => Some(Outcome::Winner(winner.opposite())),
+            outcome => outcome,
+        };
+
+        board
+    }
+
     pub fn to_vec(&self) -> Vec<Vec<Vec<f32>>> {
         let board_slice = self.base_board.data.slice(s![
-            self.n_in_a_row - 1..self.size + self.base_board_padding(),
-            self.n_in_a_row - 1..self.size + self.base_board_padding()
+            self.n_in_a_row - 1..self.rows + self.base_board_padding(),
+            self.n_in_a_row - 1..self.cols + self.base_board_padding()
         ]);
 
-        let mut board_vec = vec![vec![vec![0f32; self.size]; self.size]; 2];
+        let mut board_vec = vec![vec![vec![0f32; self.cols]; self.rows]; 2];
 
         // Set the pieces
         for ((row_index, col_index), square_state) in board_slice.indexed_iter() {
@@ -357,19 +1622,317 @@ impl Board {
         }
 
         // Set the turn
-        let turn_plane = vec![vec![self.turn.to_f32(); self.size]; self.size];
+        let turn_plane = vec![vec![self.turn.to_f32(); self.cols]; self.rows];
         board_vec.push(turn_plane);
 
         board_vec
     }
 
-    pub fn to_array(&self) -> Array3<f32> {
+    /// Reconstructs a board from the planes of `to_vec`: the Black stones, the White stones
+    /// and the turn. The size is inferred from the planes, and the outcome is found by
+    /// checking for a winner. The order of the actions is unknown, so the action history
+    /// of the returned board is empty.
+    pub fn from_vec(data: Vec<Vec<Vec<f32>>>, n_in_a_row: usize) -> Result<Board, BoardError> {
+        let rows = data.first().map_or(0, |plane| plane.len());
+        let cols = data
+            .first()
+            .and_then(|plane| plane.first())
+            .map_or(0, |row| row.len());
+        let is_binary = |value: &f32| *value == 0.0 || *value == 1.0;
+        if data.len() != 3
+            || rows == 0
+            || cols == 0
+            || data
+                .iter()
+                .any(|plane| plane.len() != rows || plane.iter().any(|row| row.len() != cols))
+            || data
+                .iter()
+                .flatten()
+                .flatten()
+                .any(|value| !is_binary(value))
+        {
+            return Err(BoardError::InvalidEncoding);
+        }
+
+        let turn_value = data[2][0][0];
+        if data[2].iter().flatten().any(|value| *value != turn_value) {
+            return Err(BoardError::InvalidEncoding);
+        }
+        let turn = if turn_value == Player::Black.to_f32() {
+            Player::Black
+        } else {
+            Player::White
+        };
+
+        let mut board = Board::new(rows, cols, n_in_a_row);
+        for row_index in 0..rows {
+            for col_index in 0..cols {
+                let action = [row_index, col_index];
+                match (data[0][row_index][col_index], data[1][row_index][col_index]) {
+                    (1.0, 1.0) => return Err(BoardError::InvalidEncoding),
+                    (1.0, _) => board.place_stone_unchecked(action, Player::Black),
+                    (_, 1.0) => board.place_stone_unchecked(action, Player::White),
+                    _ => (),
+                }
+            }
+        }
+
+        let (expected_turn, outcome) = board.infer_turn_and_outcome()?;
+        if turn != expected_turn {
+            return Err(BoardError::UnreachablePosition);
+        }
+        board.turn = turn;
+        board.outcome = outcome;
+
+        Ok(board)
+    }
+
+    /// Reconstructs a board of `rows` by `cols` from `to_flat_vec`, like `from_vec`:
+    /// the outcome is found by checking for a winner, and the action history is empty.
+    pub fn from_flat_vec(
+        data: &[f32],
+        rows: usize,
+        cols: usize,
+        n_in_a_row: usize,
+    ) -> Result<Board, BoardError> {
+        let n_squares = rows * cols;
+        if cols == 0 || data.len() != n_squares + 1 {
+            return Err(BoardError::InvalidEncoding);
+        }
+
+        let plane_of = |value: f32| -> Vec<Vec<f32>> {
+            data[..n_squares]
+                .chunks(cols)
+                .map(|row| row.iter().map(|v| (*v == value) as u8 as f32).collect())
+                .collect()
+        };
+        if data[..n_squares]
+            .iter()
+            .any(|value| ![1.0, -1.0, 0.0].contains(value))
+        {
+            return Err(BoardError::InvalidEncoding);
+        }
+        let planes = vec![
+            plane_of(1.0),
+            plane_of(-1.0),
+            vec![vec![data[n_squares]; cols]; rows],
+        ];
+
+        Board::from_vec(planes, n_in_a_row)
+    }
+
+    /// Creates a new Board with the `black_stones` and `white_stones` already placed,
+    /// as if they had been played in some order. The turn and the outcome are inferred
+    /// from the stones, and the order of the actions is unknown, so the action history
+    /// of the returned board is empty.
+    ///
+    /// Returns `Err` if a stone is out of bounds or overlaps another, or if the position
+    /// is unreachable, e.g. when White has more stones than Black.
+    pub fn new_from_position(
+        size: usize,
+        n_in_a_row: usize,
+        black_stones: &[Action],
+        white_stones: &[Action],
+    ) -> Result<Board, BoardError> {
+        let mut board = Board::new_square(size, n_in_a_row);
+        let stones = black_stones
+            .iter()
+            .map(|action| (*action, Player::Black))
+            .chain(white_stones.iter().map(|action| (*action, Player::White)));
+        for (action, player) in stones {
+            if !board.is_position_in_bounds(action) {
+                return Err(BoardError::OutOfBounds(action));
+            }
+            if *board.square_state(action) != SquareState::Vacant {
+                return Err(BoardError::Occupied(action));
+            }
+            board.place_stone_unchecked(action, player);
+        }
+
+        let (turn, outcome) = board.infer_turn_and_outcome()?;
+        board.turn = turn;
+        board.outcome = outcome;
+
+        Ok(board)
+    }
+
+    /// Encodes the position as a compact string, similar to FEN in chess: the rows from
+    /// top to bottom separated by '/', with X for Black, O for White and runs of vacant
+    /// squares as their length, followed by `_b` or `_w` for the player to move and
+    /// `_n` for `n_in_a_row`, e.g. "3/1X1/O2_b_3".
+    /// The action history is not encoded.
+    pub fn to_compact_string(&self) -> String {
+        let mut rows = Vec::with_capacity(self.rows);
+        for row_index in 0..self.rows {
+            let mut row = String::new();
+            let mut n_vacant = 0;
+            for col_index in 0..self.cols {
+                let stone = match self.square_state([row_index, col_index]) {
+                    SquareState::Vacant => {
+                        n_vacant += 1;
+                        continue;
+                    }
+                    SquareState::Occupied(Player::Black) => 'X',
+                    SquareState::Occupied(Player::White) => 'O',
+                };
+                if n_vacant > 0 {
+                    row.push_str(&n_vacant.to_string());
+                    n_vacant = 0;
+                }
+                row.push(stone);
+            }
+            if n_vacant > 0 {
+                row.push_str(&n_vacant.to_string());
+            }
+            rows.push(row);
+        }
+
+        let turn = match self.turn {
+            Player::Black => 'b',
+            Player::White => 'w',
+        };
+        format!("{}_{}_{}", rows.join("/"), turn, self.n_in_a_row)
+    }
+
+    /// Decodes a position encoded by `to_compact_string`, where a '.' is also accepted
+    /// for a single vacant square. As in `from_vec`, the outcome is found by checking
+    /// for a winner, and the action history of the returned board is empty.
+    pub fn from_compact_string(string: &str) -> Result<Board, ParseError> {
+        let error = || ParseError::InvalidCompactString(string.to_string());
+
+        let mut parts = string.trim().split('_');
+        let (Some(rows_string), Some(turn_string), Some(n_in_a_row_string), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(error());
+        };
+        let turn = match turn_string {
+            "b" => Player::Black,
+            "w" => Player::White,
+            _ => return Err(error()),
+        };
+        let n_in_a_row: usize = n_in_a_row_string.parse().map_err(|_| error())?;
+        // Rejects a run of vacant squares past the widest board before allocating it.
+        let extend_vacant = |row: &mut Vec<SquareState>, n_vacant: &str| {
+            let n: usize = n_vacant.parse().map_err(|_| error())?;
+            if row.len() + n > MAX_BOARD_SIZE {
+                return Err(error());
+            }
+            row.extend(std::iter::repeat(SquareState::Vacant).take(n));
+            Ok(())
+        };
+
+        let mut squares: Vec<Vec<SquareState>> = Vec::new();
+        for row_string in rows_string.split('/') {
+            let mut row = Vec::new();
+            let mut n_vacant = String::new();
+            for character in row_string.chars() {
+                if character.is_ascii_digit() {
+                    n_vacant.push(character);
+                    continue;
+                }
+                if !n_vacant.is_empty() {
+                    extend_vacant(&mut row, &n_vacant)?;
+                    n_vacant.clear();
+                }
+                match character {
+                    'X' => row.push(SquareState::Occupied(Player::Black)),
+                    'O' => row.push(SquareState::Occupied(Player::White)),
+                    '.' => row.push(SquareState::Vacant),
+                    _ => return Err(error()),
+                }
+            }
+            if !n_vacant.is_empty() {
+                extend_vacant(&mut row, &n_vacant)?;
+            }
+            squares.push(row);
+        }
+
+        let rows = squares.len();
+        let cols = squares[0].len();
+        if cols == 0
+            || rows.max(cols) > MAX_BOARD_SIZE
+            || squares.iter().any(|row| row.len() != cols)
+            || n_in_a_row < 2
+            || n_in_a_row > rows.max(cols)
+        {
+            return Err(error());
+        }
+
+        let mut board = Board::new(rows, cols, n_in_a_row);
+        for (row_index, row) in squares.iter().enumerate() {
+            for (col_index, square) in row.iter().enumerate() {
+                if let SquareState::Occupied(player) = square {
+                    board.place_stone_unchecked([row_index, col_index], *player);
+                }
+            }
+        }
+
+        let (expected_turn, outcome) = board.infer_turn_and_outcome().map_err(|_| error())?;
+        if turn != expected_turn {
+            return Err(error());
+        }
+        board.turn = turn;
+        board.outcome = outcome;
+
+        Ok(board)
+    }
+
+    /// Returns the player to move and the outcome of the stones on the board,
+    /// or `Err` if they cannot be reached by alternating moves.
+    fn infer_turn_and_outcome(&self) -> Result<(Player, Option<Outcome>), BoardError> {
+        // Black moves first, so it has one more stone than White after its moves.
+        let n_black = self.num_stones_placed_by(Player::Black);
+        let n_white = self.num_stones_placed_by(Player::White);
+        let last_player = if n_black == n_white + 1 {
+            Player::Black
+        } else if n_black == n_white {
+            Player::White
+        } else {
+            return Err(BoardError::UnreachablePosition);
+        };
+
+        let has_won = |player: Player| {
+            (0..self.rows)
+                .flat_map(|row_index| (0..self.cols).map(move |col_index| [row_index, col_index]))
+                .any(|action| {
+                    *self.square_state(action) == SquareState::Occupied(player)
+                        && self.action_wins_for(action, player)
+                })
+        };
+        // The game ends with the winning move, which must have been the last one,
+        // and the turn is not switched after the last move.
+        let (outcome, turn) = match (has_won(Player::Black), has_won(Player::White)) {
+            (true, true) => return Err(BoardError::UnreachablePosition),
+            (true, false) => (Some(Outcome::Winner(Player::Black)), Player::Black),
+            (false, true) => (Some(Outcome::Winner(Player::White)), Player::White),
+            (false, false) if self.legal_actions().is_empty() => (Some(Outcome::Draw), last_player),
+            (false, false) => (None, last_player.opposite()),
+        };
+        let is_winner_last = match outcome {
+            Some(Outcome::Winner(winner)) => winner == last_player,
+            _ => true,
+        };
+        if !is_winner_last {
+            return Err(BoardError::UnreachablePosition);
+        }
+
+        Ok((turn, outcome))
+    }
+
+    /// Encodes the board as 3 planes: Black's stones, White's stones and the turn.
+    /// If `canonical`, the board is first replaced by its `canonical_form`.
+    pub fn to_array(&self, canonical: bool) -> Array3<f32> {
+        if canonical && self.turn == Player::White {
+            return self.canonical_form().to_array(false);
+        }
+
         let board_slice = self.base_board.data.slice(s![
-            self.n_in_a_row - 1..self.size + self.base_board_padding(),
-            self.n_in_a_row - 1..self.size + self.base_board_padding()
+            self.n_in_a_row - 1..self.rows + self.base_board_padding(),
+            self.n_in_a_row - 1..self.cols + self.base_board_padding()
         ]);
 
-        let mut board_array = Array3::<f32>::zeros((3, self.size, self.size));
+        let mut board_array = Array3::<f32>::zeros((3, self.rows, self.cols));
 
         // Set the pieces
         for ((row_index, col_index), square_state) in board_slice.indexed_iter() {
@@ -390,49 +1953,183 @@ impl Board {
         board_array
     }
 
-    pub fn to_flat_array(&self) -> Array1<f32> {
+    /// Stacks the last `t` positions into `2 * t + 1` planes, as in AlphaZero:
+    /// Black's stones in each of the `t` positions, from the current one backwards,
+    /// then White's stones in the same order, then a plane filled with the turn.
+    ///
+    /// `history` holds the positions before this one, oldest first. Positions
+    /// from before the start of the game are left as empty planes.
+    pub fn to_history_array(&self, history: &[Board], t: usize) -> Array3<f32> {
+        let mut history_array = Array3::<f32>::zeros((2 * t + 1, self.rows, self.cols));
+
+        let positions = std::iter::once(self).chain(history.iter().rev()).take(t);
+        for (position_index, board) in positions.enumerate() {
+            for row_index in 0..self.rows {
+                for col_index in 0..self.cols {
+                    match board.square_state([row_index, col_index]) {
+                        SquareState::Occupied(Player::Black) => {
+                            history_array[[position_index, row_index, col_index]] = 1.0
+                        }
+                        SquareState::Occupied(Player::White) => {
+                            history_array[[t + position_index, row_index, col_index]] = 1.0
+                        }
+                        SquareState::Vacant => (),
+                    }
+                }
+            }
+        }
+
+        // Set the turn
+        history_array
+            .slice_mut(s![2 * t, .., ..])
+            .fill(self.turn.to_f32());
+
+        history_array
+    }
+
+    /// Encodes the board as a square per entry, 1.0 for Black's stones, -1.0 for White's
+    /// and 0.0 for the vacant squares, followed by the turn.
+    /// If `canonical`, the board is first replaced by its `canonical_form`.
+    pub fn to_flat_array(&self, canonical: bool) -> Array1<f32> {
+        if canonical && self.turn == Player::White {
+            return self.canonical_form().to_flat_array(false);
+        }
+
         let board_slice = self.base_board.data.slice(s![
-            self.n_in_a_row - 1..self.size + self.base_board_padding(),
-            self.n_in_a_row - 1..self.size + self.base_board_padding()
+            self.n_in_a_row - 1..self.rows + self.base_board_padding(),
+            self.n_in_a_row - 1..self.cols + self.base_board_padding()
         ]);
 
-        let mut board_flat_array = Array1::<f32>::zeros(self.size * self.size + 1);
+        let mut board_flat_array = Array1::<f32>::zeros(self.rows * self.cols + 1);
 
         // Set the pieces
         for ((row_index, col_index), square_state) in board_slice.indexed_iter() {
-            let index = row_index * self.size + col_index;
+            let index = row_index * self.cols + col_index;
             match square_state {
-                SquareState::Occupied(player) => board_flat_array[index] = player.to_f32(),
+                SquareState::Occupied(Player::Black) => board_flat_array[index] = 1.0,
+                SquareState::Occupied(Player::White) => board_flat_array[index] = -1.0,
                 _ => (),
             }
         }
 
-        board_flat_array[self.size * self.size] = self.turn.to_f32();
+        board_flat_array[self.rows * self.cols] = self.turn.to_f32();
 
         board_flat_array
     }
 
+    /// Draws the board like its `Display` implementation, except that the stone
+    /// on `last_action` is drawn in lowercase, i.e. x or o, to make it stand out.
+    pub fn display_with_last_move(&self, last_action: Option<Action>) -> String {
+        let mut board_string = String::new();
+        let (row_names, col_names) = get_row_col_names(self.rows, self.cols);
+
+        let padded_row_names: Vec<String> = row_names
+            .iter()
+            .rev()
+            .map(|n| {
+                let mut padded_name = String::from(if n.len() == 1 { " " } else { "" });
+                padded_name.push_str(n);
+                padded_name
+            })
+            .collect();
+
+        for row_index in 0..self.rows {
+            let mut row_string = padded_row_names[row_index].clone();
+            row_string.push_str(" ");
+
+            for col_index in 0..self.cols {
+                let action = self.action_to_base_board_location([row_index, col_index] as Action);
+
+                let is_last_action = last_action == Some([row_index, col_index]);
+                match (self.base_board.get(action), is_last_action) {
+                    (SquareState::Occupied(Player::Black), false) => row_string.push_str("X "),
+                    (SquareState::Occupied(Player::White), false) => row_string.push_str("O "),
+                    (SquareState::Occupied(Player::Black), true) => row_string.push_str("x "),
+                    (SquareState::Occupied(Player::White), true) => row_string.push_str("o "),
+                    (SquareState::Vacant, _) => row_string.push_str(". "),
+                }
+            }
+            board_string.push_str(&row_string);
+            board_string.push_str("\n");
+        }
+
+        board_string.push_str("   ");
+        board_string.push_str(&col_names.join(" "));
+        board_string.push_str("\n");
+
+        board_string
+    }
+
+    /// Returns the version of the tensor encodings, i.e. `TENSOR_ENCODING_VERSION`.
+    pub fn tensor_encoding_version() -> u32 {
+        TENSOR_ENCODING_VERSION
+    }
+
     pub fn to_flat_vec(&self) -> Vec<f32> {
-        let board_flat_array = self.to_flat_array();
+        let board_flat_array = self.to_flat_array(false);
         board_flat_array.iter().map(|i| *i).collect()
     }
 
+    /// Encodes the board for Python without parsing: a header of four little-endian `u32`,
+    /// i.e. `rows`, `cols`, `n_in_a_row` and the turn as in `Player::to_f32`, followed by
+    /// `to_flat_array` as little-endian `f32`, which `np.frombuffer(data, "<f4", offset=16)`
+    /// reads directly.
+    pub fn to_numpy_compatible_bytes(&self) -> Vec<u8> {
+        let header = [
+            self.rows as u32,
+            self.cols as u32,
+            self.n_in_a_row as u32,
+            self.turn.to_f32() as u32,
+        ];
+
+        header
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .chain(Self::policy_to_bytes(&self.to_flat_vec()))
+            .collect()
+    }
+
+    /// Encodes a policy as little-endian `f32`, for `np.frombuffer(data, "<f4")`.
+    pub fn policy_to_bytes(policy: &[f32]) -> Vec<u8> {
+        policy
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    /// Returns `to_flat_vec` as a comma-separated line, terminated by a newline.
+    /// The columns match those of `csv_header`.
+    pub fn to_csv_row(&self) -> String {
+        let mut csv_row = self.to_flat_vec().iter().join(",");
+        csv_row.push('\n');
+        csv_row
+    }
+
+    /// Returns the CSV header line matching `to_csv_row`, i.e. `c0,c1,...,cN,turn`.
+    /// The columns only depend on `rows` and `cols`; `n_in_a_row` is taken so that
+    /// the header can be built from the same parameters as `Board::new`.
+    pub fn csv_header(rows: usize, cols: usize, _n_in_a_row: usize) -> String {
+        let mut csv_header = (0..rows * cols).map(|i| format!("c{i}")).join(",");
+        csv_header.push_str(",turn\n");
+        csv_header
+    }
+
     pub fn to_tensor(&self) -> tch::Tensor {
-        let board_array = self.to_array();
+        let board_array = self.to_array(false);
         let board_tensor = tch::Tensor::try_from(board_array)
             .unwrap()
             // .to_device(tch::Device::Cuda(0))
-            .reshape(&[1, 3, self.size as i64, self.size as i64]);
+            .reshape(&[1, 3, self.rows as i64, self.cols as i64]);
 
         board_tensor
     }
 
     pub fn to_flat_tensor(&self) -> tch::Tensor {
-        let board_flat_array = self.to_flat_array();
+        let board_flat_array = self.to_flat_array(false);
         let board_tensor = tch::Tensor::try_from(board_flat_array)
             .unwrap()
             // .to_device(tch::Device::Cuda(0))
-            .reshape(&[1, (self.size * self.size + 1) as i64]);
+            .reshape(&[1, (self.rows * self.cols + 1) as i64]);
 
         board_tensor
     }
@@ -441,77 +2138,686 @@ impl Board {
 impl Clone for Board {
     fn clone(&self) -> Self {
         Self {
-            size: self.size,
+            rows: self.rows,
+            cols: self.cols,
             n_in_a_row: self.n_in_a_row,
+            rules: self.rules,
+            symmetric_rules: self.symmetric_rules,
             base_board: self.base_board.clone(),
             legal_actions_indexset: self.legal_actions_indexset.clone(),
+            legal_flat_indices: self.legal_flat_indices.clone(),
             action_to_check_indices: self.action_to_check_indices.clone(),
             turn: self.turn,
             outcome: self.outcome,
             num_stones_placed: self.num_stones_placed,
+            black_stones: self.black_stones,
+            white_stones: self.white_stones,
+            action_history: self.action_history.clone(),
+            zobrist_keys: self.zobrist_keys.clone(),
+            hash: self.hash,
         }
     }
 }
 
-fn get_row_col_names(size: usize) -> (Vec<String>, Vec<String>) {
-    let row_names: Vec<String> = (1..=size as u32).map(|c| c.to_string()).collect();
+/// Boards are equal when they hold the same position, regardless of the order
+/// in which the actions were played.
+impl PartialEq for Board {
+    fn eq(&self, other: &Self) -> bool {
+        self.rows == other.rows
+            && self.cols == other.cols
+            && self.n_in_a_row == other.n_in_a_row
+            && self.rules == other.rules
+            && self.symmetric_rules == other.symmetric_rules
+            && self.turn == other.turn
+            && self.base_board == other.base_board
+            && self.outcome == other.outcome
+            && self.num_stones_placed == other.num_stones_placed
+            && self.legal_actions_indexset == other.legal_actions_indexset
+    }
+}
+
+/// Parses algebraic notation for a board of `rows` by `cols`, see `Board::algebraic_to_action`.
+fn parse_algebraic(string: &str, rows: usize, cols: usize) -> Result<Action, ParseError> {
+    let string = string.trim();
+    let mut chars = string.chars();
+
+    let col_index = chars
+        .next()
+        .map(|letter| letter.to_ascii_uppercase())
+        .filter(char::is_ascii_uppercase)
+        .map(|letter| (letter as u8 - b'A') as usize)
+        .filter(|&col_index| col_index < cols)
+        .ok_or_else(|| ParseError::InvalidColumn(string.to_string()))?;
+
+    let row_string = chars.as_str();
+    let row_number = Some(row_string)
+        .filter(|row_string| {
+            !row_string.starts_with('0') && row_string.chars().all(|c| c.is_ascii_digit())
+        })
+        .and_then(|row_string| row_string.parse::<usize>().ok())
+        .filter(|&row_number| 1 <= row_number && row_number <= rows)
+        .ok_or_else(|| ParseError::InvalidRow(string.to_string()))?;
+
+    // Rows are numbered from the bottom up, while row indices go from the top down.
+    Ok([rows - row_number, col_index])
+}
+
+/// Formats an Action of a board with `rows` rows in algebraic notation, e.g. "A1".
+fn format_algebraic(action: Action, rows: usize) -> String {
+    let [row_index, col_index] = action;
+
+    // Rows are named from the bottom up, while row indices go from the top down.
+    format!("{}{}", (b'A' + col_index as u8) as char, rows - row_index)
+}
+
+fn get_row_col_names(rows: usize, cols: usize) -> (Vec<String>, Vec<String>) {
+    let row_names: Vec<String> = (1..=rows as u32).map(|c| c.to_string()).collect();
     let col_names: Vec<String> = (b'A'..=b'Z')
-        .filter(|c| c - b'A' < size as u8)
+        .filter(|c| c - b'A' < cols as u8)
         .map(|c| (c as char).to_string())
         .collect();
 
     (row_names, col_names)
 }
 
-fn get_names_hashmaps(size: usize) -> (HashMap<String, usize>, HashMap<String, usize>) {
-    let (row_names, col_names) = get_row_col_names(size);
-    let mut row_names_hashmap = HashMap::with_capacity(size);
-    let mut col_names_hashmap = HashMap::with_capacity(size);
+/// Draws the board with X for Black, O for White and . for vacant squares,
+/// labelling the rows and columns with their notation.
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.display_with_last_move(None))
+    }
+}
+
+/// Prints the board with the stone of its `last_action` highlighted.
+pub fn show(board: &Board) {
+    println!("{}", board.display_with_last_move(board.last_action()));
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use proptest::test_runner::TestCaseError;
+    use rand::Rng;
+
+    use super::*;
+    use crate::sgf::{export_sgf, import_sgf};
+
+    /// A board size, an `n_in_a_row` that fits it, and the choices of a random game,
+    /// each picking one of the legal actions of its turn.
+    fn size_and_choices() -> impl Strategy<Value = (usize, usize, Vec<prop::sample::Index>)> {
+        (2..=15usize).prop_flat_map(|size| {
+            (
+                Just(size),
+                2..=size,
+                prop::collection::vec(any::<prop::sample::Index>(), 0..=size * size),
+            )
+        })
+    }
+
+    proptest! {
+        /// Plays random sequences of legal actions on boards of random size and `n_in_a_row`,
+        /// checking the invariants of the board after every action. Failing games are shrunk
+        /// to a minimal one by proptest before being reported.
+        #[test]
+        fn test_board_invariants((size, n_in_a_row, choices) in size_and_choices()) {
+            let mut board = Board::new_square(size, n_in_a_row);
+            check_invariants(&board)?;
+
+            for choice in choices {
+                if board.is_game_over() {
+                    break;
+                }
+                let action = *board
+                    .legal_actions()
+                    .get_index(choice.index(board.legal_actions().len()))
+                    .unwrap();
+                board
+                    .make_action(action)
+                    .map_err(|error| TestCaseError::fail(format!("{error:?}")))?;
+                prop_assert_eq!(board.last_action(), Some(action));
+                check_invariants(&board)?;
+            }
 
-    for (i, n) in row_names.iter().rev().enumerate() {
-        row_names_hashmap.insert(n.clone(), i);
+            let initial_board = Board::new_square(size, n_in_a_row);
+            board.reset();
+            prop_assert!(
+                board == initial_board,
+                "Reset should restore the initial state."
+            );
+            prop_assert_eq!(board.hash(), initial_board.hash());
+            prop_assert!(board.move_history().is_empty());
+            prop_assert_eq!(board.last_action(), None);
+            prop_assert_eq!(board.black_stones + board.white_stones, 0);
+        }
     }
-    for (i, n) in col_names.iter().enumerate() {
-        col_names_hashmap.insert(n.clone(), i);
+
+    proptest! {
+        /// Checks that `algebraic_to_action` is the inverse of `action_to_algebraic`
+        /// on every square of boards of random shape.
+        #[test]
+        fn test_algebraic_round_trips(
+            (rows, cols, row, col) in (2..=26usize, 2..=26usize)
+                .prop_flat_map(|(rows, cols)| (Just(rows), Just(cols), 0..rows, 0..cols))
+        ) {
+            let board = Board::new(rows, cols, 2);
+            let algebraic = board.action_to_algebraic([row, col]);
+            prop_assert_eq!(board.algebraic_to_action(&algebraic), Ok([row, col]));
+            prop_assert_eq!(
+                board.algebraic_to_action(&algebraic.to_lowercase()),
+                Ok([row, col])
+            );
+        }
     }
 
-    (row_names_hashmap, col_names_hashmap)
-}
+    /// Checks the notation of a few known squares and move lists, and that malformed
+    /// notation is rejected.
+    #[test]
+    fn test_algebraic_notation() {
+        let board = Board::new_square(15, 5);
+        assert_eq!(board.algebraic_to_action("H8"), Ok([7, 7]));
+        let history = [[7, 7], [6, 8], [8, 9]];
+        let moves = Board::moves_to_string(&history, 15);
+        assert_eq!(moves, "1. B[H8] W[I9] 2. B[J7]");
+        assert_eq!(Board::moves_from_string(&moves, 15), Ok(history.to_vec()));
+        assert_eq!(
+            Board::moves_from_string("1. W[H8]", 15),
+            Err(ParseError::InvalidMoveList(String::from("W[H8]")))
+        );
+        assert_eq!(board.action_to_algebraic([14, 0]), "A1");
+        for malformed in ["", "H", "8", "P8", "H0", "H16", "H08", "H+8", "HH"] {
+            assert!(
+                board.algebraic_to_action(malformed).is_err(),
+                "{malformed} should not parse"
+            );
+        }
+    }
 
-pub fn show(board: &Board) {
-    let mut board_string = String::new();
-    let (row_names, col_names) = get_row_col_names(board.size);
-
-    let padded_row_names: Vec<String> = row_names
-        .iter()
-        .rev()
-        .map(|n| {
-            let mut padded_name = String::from(if n.len() == 1 { " " } else { "" });
-            padded_name.push_str(n);
-            padded_name
+    fn check_invariants(board: &Board) -> Result<(), TestCaseError> {
+        let mut n_occupied = 0;
+        for row in 0..board.rows {
+            for col in 0..board.cols {
+                let is_vacant = *board.square_state([row, col]) == SquareState::Vacant;
+                if !is_vacant {
+                    n_occupied += 1;
+                }
+                prop_assert_eq!(board.is_action_legal([row, col]), is_vacant);
+            }
+        }
+        prop_assert_eq!(board.num_stones_placed, n_occupied);
+        prop_assert_eq!(
+            board.legal_actions().len(),
+            board.rows * board.cols - n_occupied
+        );
+        let flat_indices: Vec<usize> = board
+            .legal_actions()
+            .iter()
+            .map(|action| board.action_to_flat_index(action))
+            .collect();
+        prop_assert_eq!(board.legal_actions_as_flat_indices(), &flat_indices[..]);
+
+        if let Some(Outcome::Winner(winner)) = board.outcome {
+            let winning_line = board.winning_line();
+            prop_assert!(
+                winning_line.is_some(),
+                "A won game should have a winning line."
+            );
+            let winning_line = winning_line.unwrap();
+            prop_assert_eq!(winning_line.len(), board.n_in_a_row);
+
+            for action in &winning_line {
+                prop_assert_eq!(board.square_state(*action), &SquareState::Occupied(winner));
+            }
+            // Consecutive squares along a row, column or diagonal.
+            let step = |a: Action, b: Action| {
+                (b[0] as isize - a[0] as isize, b[1] as isize - a[1] as isize)
+            };
+            let direction = step(winning_line[0], winning_line[1]);
+            prop_assert!(direction.0.abs() <= 1 && direction.1.abs() <= 1 && direction != (0, 0));
+            for squares in winning_line.windows(2) {
+                prop_assert_eq!(step(squares[0], squares[1]), direction);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Known perft values, as (rows, cols, n_in_a_row, depth, n_leaves).
+    const PERFT_TABLE: [(usize, usize, usize, usize, u64); 13] = [
+        (3, 3, 3, 1, 9),
+        (3, 3, 3, 2, 72),
+        (3, 3, 3, 3, 504),
+        (3, 3, 3, 4, 3_024),
+        (3, 3, 3, 5, 15_120),
+        (3, 3, 3, 6, 54_720),
+        (3, 3, 3, 7, 148_176),
+        (3, 3, 3, 8, 200_448),
+        (3, 3, 3, 9, 127_872),
+        (5, 5, 4, 1, 25),
+        (5, 5, 4, 2, 600),
+        (5, 5, 4, 3, 13_800),
+        (5, 5, 4, 4, 303_600),
+    ];
+
+    /// The shape, `n_in_a_row` and choices of a random game, see `play_choices`.
+    fn game_params() -> impl Strategy<Value = (usize, usize, usize, Vec<prop::sample::Index>)> {
+        (3..=10usize, 3..=10usize).prop_flat_map(|(rows, cols)| {
+            (
+                Just(rows),
+                Just(cols),
+                2..=rows.max(cols),
+                prop::collection::vec(any::<prop::sample::Index>(), 0..=rows * cols),
+            )
         })
-        .collect();
+    }
 
-    for row_index in 0..board.size {
-        let mut row_string = padded_row_names[row_index].clone();
-        row_string.push_str(" ");
+    /// Plays a game on a new board, each choice picking one of the legal actions of its
+    /// turn, until the choices run out or the game is over.
+    fn play_choices(
+        rows: usize,
+        cols: usize,
+        n_in_a_row: usize,
+        choices: &[prop::sample::Index],
+    ) -> Board {
+        let mut board = Board::new(rows, cols, n_in_a_row);
+        for choice in choices {
+            if board.is_game_over() {
+                break;
+            }
+            let legal_actions = board.legal_actions();
+            let action = legal_actions[choice.index(legal_actions.len())];
+            board
+                .make_action(action)
+                .expect("Legal actions should not result in an error.");
+        }
 
-        for col_index in 0..board.size {
-            let action = board.action_to_base_board_location([row_index, col_index] as Action);
+        board
+    }
 
-            match board.base_board.get(action) {
-                SquareState::Occupied(Player::Black) => row_string.push_str("X "),
-                SquareState::Occupied(Player::White) => row_string.push_str("O "),
-                SquareState::Vacant => row_string.push_str(". "),
+    proptest! {
+        /// Checks on random boards that every encoding decodes back to the same board.
+        /// The compact string plays the part of FEN.
+        #[test]
+        fn test_round_trips((rows, cols, n_in_a_row, choices) in game_params()) {
+            let board = play_choices(rows, cols, n_in_a_row, &choices);
+            let history_string = board.serialize_action_history();
+
+            let actions = board
+                .deserialize_action_history(&history_string)
+                .expect("The serialized action history should parse.");
+            let decoded_board =
+                Board::from_actions(board.rows, board.cols, board.n_in_a_row, &actions)
+                    .expect("The action history should be playable.");
+            prop_assert!(
+                decoded_board == board,
+                "Action history round trip failed for {}",
+                history_string
+            );
+
+            let json = board.to_json();
+            let decoded_board = Board::from_json(&json).expect("The JSON should parse.");
+            prop_assert!(decoded_board == board, "JSON round trip failed for {}", json);
+            prop_assert_eq!(decoded_board.hash(), board.hash());
+            prop_assert_eq!(decoded_board.move_history(), board.move_history());
+
+            let mut replayed_board = Board::new(board.rows, board.cols, board.n_in_a_row);
+            replayed_board
+                .apply_actions(board.move_history())
+                .expect("The move history should be playable.");
+            prop_assert!(replayed_board == board, "Replay failed for {}", history_string);
+            if let Some(&action) = board.move_history().first() {
+                prop_assert_eq!(
+                    replayed_board.apply_actions(&[action]),
+                    Err(0),
+                    "Replaying onto a played board should fail at the first action."
+                );
+            }
+
+            // The planes and the flat encoding do not record the order of the actions.
+            let decoded_board = Board::from_vec(board.to_vec(), board.n_in_a_row)
+                .expect("The planes of a played board should be reachable.");
+            prop_assert_eq!(decoded_board.to_vec(), board.to_vec());
+            prop_assert_eq!(decoded_board.outcome, board.outcome);
+            prop_assert_eq!(decoded_board.hash(), board.hash());
+
+            let decoded_board =
+                Board::from_flat_vec(&board.to_flat_vec(), board.rows, board.cols, board.n_in_a_row)
+                    .expect("The flat encoding of a played board should be reachable.");
+            prop_assert_eq!(decoded_board.to_flat_vec(), board.to_flat_vec());
+            prop_assert_eq!(decoded_board.turn, board.turn);
+            prop_assert_eq!(decoded_board.outcome, board.outcome);
+            prop_assert_eq!(decoded_board.hash(), board.hash());
+
+            let compact_string = board.to_compact_string();
+            let decoded_board = Board::from_compact_string(&compact_string)
+                .expect("The compact string of a played board should parse.");
+            prop_assert!(
+                decoded_board == board,
+                "Compact string round trip failed for {}",
+                compact_string
+            );
+            prop_assert_eq!(decoded_board.to_compact_string(), compact_string);
+
+            // SGF only records square boards.
+            if board.rows == board.cols {
+                let sgf = export_sgf(board.move_history(), board.rows, board.n_in_a_row);
+                let actions = import_sgf(&sgf).expect("The exported SGF should parse.");
+                prop_assert_eq!(&actions[..], board.move_history());
+                let decoded_board =
+                    Board::from_actions(board.rows, board.cols, board.n_in_a_row, &actions)
+                        .expect("The moves of the SGF should be playable.");
+                prop_assert!(decoded_board == board, "SGF round trip failed for {}", sgf);
+            }
+        }
+    }
+
+    #[test]
+    fn test_undo_to_ply_keeps_handicap_stones() {
+        let handicap_board = Board::from_handicap_positions(9, 5, &[[4, 4], [2, 2]]).unwrap();
+        let mut board = handicap_board.clone();
+        for action in [[0, 0], [4, 5], [8, 8], [3, 3]] {
+            board.make_action(action).unwrap();
+        }
+        let mut expected_board = handicap_board.clone();
+        expected_board.make_action([0, 0]).unwrap();
+
+        board.undo_to_ply(1).unwrap();
+        assert!(board == expected_board);
+        assert_eq!(board.move_history(), &[[0, 0]]);
+        assert_eq!(board.turn, Player::Black);
+        assert_eq!(board.hash(), expected_board.hash());
+
+        board.undo_to_ply(0).unwrap();
+        assert!(board == handicap_board);
+        assert_eq!(board.turn, Player::White);
+        assert_eq!(
+            board.undo_to_ply(1),
+            Err(BoardError::PlyOutOfRange { ply: 1, n_plies: 0 })
+        );
+    }
+
+    /// Checks a few known compact strings and positions, and that malformed ones
+    /// are rejected.
+    #[test]
+    fn test_positions() {
+        let board = Board::from_compact_string("X3O2._b_3").unwrap();
+        assert_eq!(board.to_compact_string(), "X3O3_b_3");
+        for malformed in [
+            "",
+            "3/3/3_b",
+            "3/3/3_x_3",
+            "3/3/2_b_3",
+            "3/3/3_b_4",
+            "3/3/OO1_w_3",
+            "27_b_5",
+            "99999999999X_b_5",
+        ] {
+            assert!(
+                Board::from_compact_string(malformed).is_err(),
+                "{malformed} should not parse"
+            );
+        }
+
+        let board = Board::new_from_position(3, 3, &[[1, 1], [0, 2]], &[[1, 0]]).unwrap();
+        assert_eq!(board.turn, Player::White);
+        assert_eq!(board.outcome, None);
+        assert_eq!(board.game_result_for(Player::Black), None);
+        assert_eq!(board.legal_actions().len(), 6);
+        let board =
+            Board::new_from_position(3, 3, &[[0, 0], [1, 1], [2, 2]], &[[0, 1], [0, 2]]).unwrap();
+        assert_eq!(board.outcome, Some(Outcome::Winner(Player::Black)));
+        assert_eq!(board.game_result_for(Player::Black), Some(1.0));
+        assert_eq!(board.game_result_for(Player::White), Some(-1.0));
+        assert_eq!(board.last_action(), None);
+        assert_eq!(board.winning_line(), Some(vec![[2, 2], [1, 1], [0, 0]]));
+        assert_eq!(
+            Board::new_from_position(3, 3, &[[0, 0]], &[[1, 1], [2, 2]]).err(),
+            Some(BoardError::UnreachablePosition)
+        );
+        assert_eq!(
+            Board::new_from_position(3, 3, &[[0, 0]], &[[0, 0]]).err(),
+            Some(BoardError::Occupied([0, 0]))
+        );
+    }
+
+    #[test]
+    fn test_generate_opening_variations() {
+        let board = Board::new_square(3, 3);
+        // The 9 first actions fall into 3 classes of symmetric positions: corner, edge and center.
+        assert_eq!(board.generate_opening_variations(1, 100).len(), 3);
+        assert_eq!(board.generate_opening_variations(2, 2).len(), 2);
+
+        // Black making six in a row at [4, 3] is an overline, forbidden under Renju rules.
+        let board = Board::from_actions(
+            9,
+            9,
+            5,
+            &[
+                [4, 0],
+                [0, 0],
+                [4, 1],
+                [0, 2],
+                [4, 2],
+                [0, 4],
+                [4, 4],
+                [8, 8],
+                [4, 5],
+                [8, 6],
+            ],
+        )
+        .unwrap()
+        .with_rules(RulesVariant::Renju);
+        assert!(board.is_forbidden([4, 3]));
+        let variations = board.generate_opening_variations(1, usize::MAX);
+        assert_eq!(variations.len(), board.legal_actions().len() - 1);
+        assert!(variations
+            .iter()
+            .all(|variation| variation.last_action() != Some([4, 3])));
+    }
+
+    /// Checks that replaying a random game through each of the symmetries
+    /// reaches the transformed position, with the same outcome.
+    #[test]
+    fn test_symmetries() {
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..100 {
+            let size = rng.gen_range(3..=10);
+            let n_in_a_row = rng.gen_range(3..=size.min(5));
+            let mut board = Board::new_square(size, n_in_a_row);
+            while !board.is_game_over() {
+                let legal_actions = board.legal_actions();
+                let action = legal_actions[rng.gen_range(0..legal_actions.len())];
+                board
+                    .make_action(action)
+                    .expect("Legal actions should not result in an error.");
+            }
+
+            for (transformed_board, symmetry) in board.symmetries() {
+                let actions: Vec<Action> = board
+                    .move_history()
+                    .iter()
+                    .map(|action| symmetry.transform_action(*action, size, size))
+                    .collect();
+                let replayed_board = Board::from_actions(size, size, n_in_a_row, &actions)
+                    .expect("The transformed actions should be playable.");
+
+                assert_eq!(replayed_board.outcome, board.outcome, "{:?}", symmetry);
+                assert!(replayed_board == transformed_board, "{:?}", symmetry);
+                assert_eq!(replayed_board.hash(), transformed_board.hash());
+
+                let action = board.move_history()[0];
+                let round_trip = symmetry.inverse().transform_action(
+                    symmetry.transform_action(action, size, size),
+                    size,
+                    size,
+                );
+                assert_eq!(round_trip, action);
+            }
+
+            let rotated_board = board.rotate_90();
+            assert!(rotated_board == board.transformed(Symmetry::Rotate90));
+            assert!(rotated_board.rotate_90().rotate_90().rotate_90() == board);
+            assert!(board.reflect_horizontal().reflect_horizontal() == board);
+            for action in board.move_history() {
+                let rotated_action = rotate_90_action(*action, size);
+                assert_eq!(
+                    rotated_board.square_state(rotated_action),
+                    board.square_state(*action)
+                );
+                assert_eq!(
+                    reflect_horizontal_action(reflect_horizontal_action(*action, size), size),
+                    *action
+                );
+            }
+            assert_eq!(
+                rotated_board.legal_actions().len(),
+                board.legal_actions().len()
+            );
+
+            let flipped_board = board.flip_colors();
+            assert_eq!(flipped_board.turn, board.turn.opposite());
+            let flipped_outcome = match board.outcome {
+                Some(Outcome::Winner(winner)) => Some(Outcome::Winner(winner.opposite())),
+                outcome => outcome,
+            };
+            assert_eq!(flipped_board.outcome, flipped_outcome);
+            assert!(flipped_board.flip_colors() == board);
+            assert_eq!(board.symmetries().len(), 8);
+            let symmetric_board = board.clone().with_symmetric_rules(true);
+            let symmetries = symmetric_board.symmetries();
+            assert_eq!(symmetries.len(), 16);
+            for (pair, symmetry) in symmetries.chunks(2).zip(SYMMETRIES) {
+                assert_eq!((pair[0].1, pair[1].1), (symmetry, symmetry));
+                assert!(pair[0].0 == symmetric_board.transformed(symmetry));
+                assert!(pair[1].0 == pair[0].0.flip_colors());
+                assert_eq!(pair[1].0.turn, board.turn.opposite());
             }
         }
-        board_string.push_str(&row_string);
-        board_string.push_str("\n");
     }
 
-    board_string.push_str("   ");
-    board_string.push_str(&col_names.join(" "));
-    board_string.push_str("\n");
+    #[test]
+    fn test_count_consecutive() {
+        let mut base_board = BaseBoard::new(5, 5);
+        for location in [[0, 0], [1, 1], [2, 2], [4, 4]] {
+            base_board.set(location, Player::Black);
+        }
+        base_board.set([3, 3], Player::White);
+
+        assert_eq!(
+            base_board.count_consecutive([0, 0], [1, 1], Player::Black, 5),
+            3
+        );
+        assert_eq!(
+            base_board.count_consecutive([0, 0], [1, 1], Player::Black, 2),
+            2
+        );
+        assert_eq!(
+            base_board.count_consecutive([2, 2], [-1, -1], Player::Black, 5),
+            3
+        );
+        assert_eq!(
+            base_board.count_consecutive([3, 3], [1, 1], Player::White, 5),
+            1
+        );
+        assert_eq!(
+            base_board.count_consecutive([4, 4], [1, 1], Player::Black, 5),
+            1
+        );
+        assert_eq!(
+            base_board.count_consecutive([0, 1], [0, 1], Player::Black, 5),
+            0
+        );
+    }
+
+    #[test]
+    fn test_base_board_lines() {
+        let mut base_board = BaseBoard::new(5, 5);
+        for location in [[0, 0], [1, 1], [2, 2], [4, 4]] {
+            base_board.set(location, Player::Black);
+        }
+        base_board.set([3, 3], Player::White);
+
+        let black = SquareState::Occupied(Player::Black);
+        let white = SquareState::Occupied(Player::White);
+        assert_eq!(base_board.get_row(1).len(), 5);
+        assert_eq!(base_board.get_row(1)[1], black);
+        assert_eq!(base_board.get_col(3)[3], &white);
+        assert_eq!(
+            base_board.get_diagonal([1, 1], [1, 1], 10),
+            vec![&black, &black, &white, &black]
+        );
+        assert_eq!(base_board.get_diagonal([4, 0], [-1, 1], 2).len(), 2);
+    }
 
-    println!("{board_string}");
+    /// Checks the number of positions reached from an empty board against `PERFT_TABLE`.
+    #[test]
+    fn test_perft() {
+        for (rows, cols, n_in_a_row, depth, n_leaves) in PERFT_TABLE {
+            let board = Board::new(rows, cols, n_in_a_row);
+            assert_eq!(
+                board.perft(depth),
+                n_leaves,
+                "perft({depth}) on a {rows}x{cols} board with n_in_a_row={n_in_a_row}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_distance_to_center() {
+        let board = Board::new_square(15, 5);
+        assert_eq!(board.distance_to_center([7, 7]), 0.0);
+        assert_eq!(board.distance_to_center([7, 10]), 3.0);
+        assert_eq!(board.distance_to_center([3, 4]), 5.0);
+
+        // The center of an even board falls between its four central squares.
+        let even_board = Board::new_square(4, 3);
+        assert_eq!(even_board.distance_to_center([1, 1]), 0.5f32.hypot(0.5));
+        assert_eq!(even_board.distance_to_center([2, 2]), 0.5f32.hypot(0.5));
+    }
+
+    #[test]
+    fn test_hash_stones() {
+        let board = Board::from_actions(5, 5, 4, &[[2, 2], [1, 1], [2, 3]]).unwrap();
+        let transposed = Board::from_actions(5, 5, 4, &[[2, 3], [1, 1], [2, 2]]).unwrap();
+        assert_eq!(board.hash_stones(), transposed.hash_stones());
+
+        // The same stones with the other player to move, though unreachable, are another position.
+        let black_to_move = Board::from_actions(5, 5, 4, &[[2, 2], [1, 1]]).unwrap();
+        let mut white_to_move = black_to_move.clone();
+        white_to_move.turn = Player::White;
+        assert_ne!(black_to_move.hash_stones(), white_to_move.hash_stones());
+        assert_ne!(board.hash_stones(), Board::new(5, 5, 4).hash_stones());
+
+        let mut undone = board.clone();
+        undone.undo_action([2, 3]).unwrap();
+        assert_eq!(
+            undone.hash_stones(),
+            Board::from_actions(5, 5, 4, &[[2, 2], [1, 1]])
+                .unwrap()
+                .hash_stones()
+        );
+    }
+
+    #[test]
+    fn test_numpy_compatible_bytes() {
+        let board = Board::from_actions(3, 4, 3, &[[0, 0], [2, 3]]).unwrap();
+        let bytes = board.to_numpy_compatible_bytes();
+        assert_eq!(bytes.len(), 4 * 4 + 4 * (3 * 4 + 1));
+
+        let header: Vec<u32> = bytes[..16]
+            .chunks(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        assert_eq!(header, vec![3, 4, 3, 1]);
+        let values: Vec<f32> = bytes[16..]
+            .chunks(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, board.to_flat_vec());
+
+        assert_eq!(
+            Board::policy_to_bytes(&[0.25, -1.0]),
+            [0.25f32.to_le_bytes(), (-1.0f32).to_le_bytes()].concat()
+        );
+    }
 }
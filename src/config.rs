@@ -0,0 +1,262 @@
+use std::fmt;
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::board::{Board, MAX_BOARD_SIZE};
+use crate::game::{OutputFormat, SelfPlayConfig};
+use crate::mcts::MctsConfig;
+use crate::opening_book::OpeningBook;
+
+/// Every hyperparameter of the engine, read from a TOML file such as `config.toml`,
+/// so that they can be changed without recompiling. Missing keys keep their defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub size: usize,
+    pub n_in_a_row: usize,
+    pub n_mcts_simulations: usize,
+    /// The number of games played by `game::self_play`.
+    pub n_games: usize,
+    pub model_path: String,
+    /// The directory self-play games are written to.
+    pub output_dir: String,
+    pub output_format: OutputFormat,
+    pub history_length: usize,
+    pub policy_temperature: f32,
+    pub canonical_states: bool,
+    pub augment_symmetries: bool,
+    /// The number of random moves played before each self-play game.
+    pub n_opening_moves: usize,
+    /// The number of search workers per self-play game sharing a model server,
+    /// see `SelfPlayConfig::n_search_threads`.
+    pub n_search_threads: usize,
+    pub c_base: f32,
+    /// If unset, picked from the size of the board by `MctsConfig::default_for_board`.
+    pub c_init: Option<f32>,
+    /// If unset, scheduled from the number of legal actions by `mcts::dirichlet_alpha`.
+    pub dirichlet_alpha: Option<f32>,
+    pub dirichlet_epsilon: f32,
+    /// If set, only this many children with the highest priors are expanded per node.
+    pub max_children: Option<usize>,
+    /// The number of leaves the model evaluates together during a search.
+    pub mcts_batch_size: usize,
+    /// Whether the whole tree is kept after a search, see `MctsConfig::tree_reuse`.
+    pub tree_reuse: bool,
+    /// If set, the engine searches for this many milliseconds per move when playing
+    /// against a human, instead of for `n_mcts_simulations` iterations.
+    pub time_limit_ms: Option<u64>,
+    /// If set, the JSON `OpeningBook` the searches play their first moves from.
+    pub opening_book_path: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file cannot be read.
+    Io(std::io::Error),
+    /// The file is not valid TOML, or a value has the wrong type.
+    Parse(toml::de::Error),
+    /// A value is out of range, or inconsistent with another one.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(error) => write!(f, "cannot read the config file: {error}"),
+            ConfigError::Parse(error) => write!(f, "invalid config file: {error}"),
+            ConfigError::Invalid(reason) => write!(f, "invalid configuration: {reason}"),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let self_play_config = SelfPlayConfig::default();
+        let mcts_config = MctsConfig::default_for_board(&Board::new_square(
+            self_play_config.size,
+            self_play_config.n_in_a_row,
+        ));
+
+        Self {
+            size: self_play_config.size,
+            n_in_a_row: self_play_config.n_in_a_row,
+            n_mcts_simulations: self_play_config.n_mcts_simulations,
+            n_games: 5_000,
+            model_path: self_play_config.model_path,
+            output_dir: self_play_config.output_dir,
+            output_format: self_play_config.output_format,
+            history_length: self_play_config.history_length,
+            policy_temperature: self_play_config.policy_temperature,
+            canonical_states: self_play_config.canonical_states,
+            augment_symmetries: self_play_config.augment_symmetries,
+            n_opening_moves: self_play_config.n_opening_moves,
+            n_search_threads: self_play_config.n_search_threads,
+            c_base: mcts_config.c_base,
+            c_init: None,
+            dirichlet_alpha: None,
+            dirichlet_epsilon: mcts_config.dirichlet_epsilon,
+            max_children: None,
+            mcts_batch_size: mcts_config.batch_size,
+            tree_reuse: mcts_config.tree_reuse,
+            time_limit_ms: None,
+            opening_book_path: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads and validates the configuration of the TOML file at `path`.
+    pub fn from_file(path: &str) -> Result<Config, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        let config: Config = toml::from_str(&contents).map_err(ConfigError::Parse)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that the values are within range and consistent with each other,
+    /// so that they cannot make a board panic or a search never end.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: String| Err(ConfigError::Invalid(reason));
+
+        if !(2..=MAX_BOARD_SIZE).contains(&self.size) {
+            return invalid(format!(
+                "size must be between 2 and {MAX_BOARD_SIZE}, not {}",
+                self.size
+            ));
+        }
+        if !(2..=self.size).contains(&self.n_in_a_row) {
+            return invalid(format!(
+                "n_in_a_row must be between 2 and the size {}, not {}",
+                self.size, self.n_in_a_row
+            ));
+        }
+        if self.n_mcts_simulations == 0 {
+            return invalid(String::from("n_mcts_simulations must be at least 1"));
+        }
+        if self.mcts_batch_size == 0 {
+            return invalid(String::from("mcts_batch_size must be at least 1"));
+        }
+        if self.max_children == Some(0) {
+            return invalid(String::from("max_children must be at least 1 when set"));
+        }
+        if self.policy_temperature <= 0.0 || self.policy_temperature.is_nan() {
+            return invalid(format!(
+                "policy_temperature must be positive, not {}",
+                self.policy_temperature
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.dirichlet_epsilon) {
+            return invalid(format!(
+                "dirichlet_epsilon must be between 0 and 1, not {}",
+                self.dirichlet_epsilon
+            ));
+        }
+        if let Some(dirichlet_alpha) = self
+            .dirichlet_alpha
+            .filter(|alpha| *alpha <= 0.0 || alpha.is_nan())
+        {
+            return invalid(format!(
+                "dirichlet_alpha must be positive, not {dirichlet_alpha}"
+            ));
+        }
+        if let Some(opening_book_path) = &self.opening_book_path {
+            let json = match fs::read_to_string(opening_book_path) {
+                Ok(json) => json,
+                Err(error) => {
+                    return invalid(format!("cannot read {opening_book_path}: {error}"));
+                }
+            };
+            if let Err(error) = OpeningBook::from_json(&json) {
+                return invalid(format!("invalid opening book {opening_book_path}: {error}"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the search parameters for `board`, where those left unset
+    /// are suited to the size of the board. The opening book is read from its file.
+    pub fn mcts_config(&self, board: &Board) -> MctsConfig {
+        let default_config = MctsConfig::default_for_board(board);
+
+        MctsConfig {
+            c_base: self.c_base,
+            c_init: self.c_init.unwrap_or(default_config.c_init),
+            dirichlet_alpha: self.dirichlet_alpha,
+            dirichlet_epsilon: self.dirichlet_epsilon,
+            policy_temperature: self.policy_temperature,
+            max_children: self.max_children,
+            n_iterations: self.n_mcts_simulations,
+            batch_size: self.mcts_batch_size,
+            tree_reuse: self.tree_reuse,
+            opening_book: self.opening_book_path.as_deref().map(OpeningBook::load),
+        }
+    }
+
+    pub fn self_play_config(&self) -> SelfPlayConfig {
+        let board = Board::new_square(self.size, self.n_in_a_row);
+
+        SelfPlayConfig {
+            size: self.size,
+            n_in_a_row: self.n_in_a_row,
+            n_mcts_simulations: self.n_mcts_simulations,
+            model_path: self.model_path.clone(),
+            output_dir: self.output_dir.clone(),
+            output_format: self.output_format,
+            history_length: self.history_length,
+            policy_temperature: self.policy_temperature,
+            canonical_states: self.canonical_states,
+            augment_symmetries: self.augment_symmetries,
+            mcts_config: Some(self.mcts_config(&board)),
+            n_opening_moves: self.n_opening_moves,
+            n_search_threads: self.n_search_threads,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(Config::default().validate().is_ok());
+        assert!(Config::from_file("config.toml").is_ok());
+
+        let invalid_configs = [
+            Config {
+                size: MAX_BOARD_SIZE + 1,
+                ..Config::default()
+            },
+            Config {
+                n_in_a_row: Config::default().size + 1,
+                ..Config::default()
+            },
+            Config {
+                mcts_batch_size: 0,
+                ..Config::default()
+            },
+            Config {
+                max_children: Some(0),
+                ..Config::default()
+            },
+            Config {
+                dirichlet_epsilon: 1.5,
+                ..Config::default()
+            },
+            Config {
+                opening_book_path: Some(String::from("missing.json")),
+                ..Config::default()
+            },
+        ];
+        for config in invalid_configs {
+            assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+        }
+
+        assert!(matches!(
+            Config::from_file("missing.toml"),
+            Err(ConfigError::Io(_))
+        ));
+    }
+}
@@ -1,7 +1,18 @@
-use std::io::{self, Write};
-use std::time::Instant;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::iter::zip;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use indexmap::IndexSet;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use itertools::Itertools;
 
 extern crate serde_json;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
 extern crate uuid;
@@ -10,40 +21,463 @@ use uuid::Uuid;
 extern crate rayon;
 use rayon::prelude::*;
 
-use crate::board::{show, Action, Board, Outcome, Player};
-use crate::mcts::MCTS;
-use crate::utils::{get_random_action, get_torchjit_model};
+use crate::board::{
+    show, Action, Board, BoardError, Outcome, Player, Symmetry, HISTORY_ENCODING_VERSION,
+    TENSOR_ENCODING_VERSION,
+};
+use crate::config::Config;
+use crate::mcts::{rollout, tau_schedule, MctsConfig, ParallelMCTS, MCTS};
+use crate::model_server::{ModelClient, ModelServer};
+use crate::rating::{EloRater, INITIAL_ELO};
+use crate::storage::{DataStore, GameStep};
+use crate::utils::{get_random_action, get_torchjit_model, policy_entropy};
 
-pub fn play_game() {
-    let mut board = Board::new(3, 3);
-    show(&board);
+/// The file format self-play games are written in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// One JSON array of `{state, policy, value}` objects per game.
+    Json,
+    /// One CSV file per game, with the columns of `Board::csv_header`
+    /// followed by the policy and the value.
+    Csv,
+    /// One JSON Lines file per game, written while the game is played:
+    /// a `{state, policy, metadata}` line per move, then a final `{value}` line.
+    JsonLines,
+    /// Rows of the `games` table of a `DataStore` in the output directory,
+    /// with all the positions of a game inserted at once when it is over.
+    Sqlite,
+    /// One binary file per game, far smaller than JSON: a header of three little-endian
+    /// `u32`, i.e. the number of samples and the lengths of the state and the policy,
+    /// followed by a row of little-endian `f32` per sample: the state, the policy and
+    /// the value. NumPy reads it with `np.frombuffer(data, "<f4", offset=12)` and a reshape.
+    Binary,
+}
 
-    while !board.is_game_over() {
-        let mut square_string = String::new();
-        loop {
-            square_string.clear();
-            println!("{:?}", board.legal_moves_as_strings());
-
-            print!("\nYour move: ");
-            io::stdout().flush().unwrap();
-            io::stdin()
-                .read_line(&mut square_string)
-                .expect("Failed to read line");
-            square_string = square_string.trim().to_string();
-
-            let action = board.parse_string_to_action(&square_string);
-            if action.is_ok() && board.make_action(action.unwrap()).is_ok() {
-                break;
-            } else {
-                println!("{square_string} is not a valid move.");
+/// The name of the database of `OutputFormat::Sqlite`, in the output directory.
+pub const DATA_STORE_FILE_NAME: &str = "games.sqlite";
+
+/// The name of the JSON Lines file in the output directory that `self_play` appends
+/// the aggregate statistics of each run to.
+pub const SELF_PLAY_STATS_FILE_NAME: &str = "self_play_stats.jsonl";
+
+/// Encoding version assumed for samples written before the version was recorded.
+/// Their encoding is the same as the first versioned one.
+const LEGACY_TENSOR_ENCODING_VERSION: u32 = 1;
+
+/// The longest a `ModelServer` shared by self-play games waits to fill a batch, in microseconds.
+const MODEL_SERVER_TIMEOUT_US: u64 = 1_000;
+
+/// Number of random games `generate_opening_positions` may play per position it returns,
+/// before giving up on finding enough distinct positions.
+const OPENING_ATTEMPTS_PER_POSITION: usize = 10;
+
+/// Returns the version of the encoding of the states stacking `history_length` positions,
+/// i.e. `HISTORY_ENCODING_VERSION`, or `TENSOR_ENCODING_VERSION` for the flat encoding.
+fn encoding_version(history_length: usize) -> u32 {
+    if history_length == 0 {
+        TENSOR_ENCODING_VERSION
+    } else {
+        HISTORY_ENCODING_VERSION
+    }
+}
+
+#[derive(Debug)]
+pub enum SampleError {
+    /// The file of the samples cannot be read.
+    Io(io::Error),
+    /// A field is missing or has the wrong type.
+    Malformed(&'static str),
+    /// The sample was encoded with another version than that of its encoding,
+    /// see `encoding_version`.
+    VersionMismatch { found: u32, expected: u32 },
+}
+
+impl fmt::Display for SampleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SampleError::Io(error) => write!(f, "cannot read the samples: {error}"),
+            SampleError::Malformed(field) => write!(f, "malformed sample: invalid {field}"),
+            SampleError::VersionMismatch { found, expected } => write!(
+                f,
+                "the sample uses version {found} of its tensor encoding, expected {expected}"
+            ),
+        }
+    }
+}
+
+pub struct SampleMetadata {
+    /// The version the state was encoded with, see `encoding_version`.
+    pub version: u32,
+    /// The number of positions stacked by `Board::to_history_array` in the state,
+    /// or 0 if the state is the flat encoding of a single position.
+    pub history_length: usize,
+    /// Whether the state is in the `Board::canonical_form`, in which case the value is
+    /// from the perspective of the player to move rather than Black's.
+    pub canonical: bool,
+    /// The symmetry the state and policy were transformed by, if the sample was augmented.
+    pub symmetry: Option<Symmetry>,
+}
+
+/// A position of a self-play game along with its training targets.
+pub struct TrainingSample {
+    pub state: Vec<f32>,
+    pub policy: Vec<f32>,
+    pub value: f32,
+    pub metadata: SampleMetadata,
+}
+
+impl TrainingSample {
+    pub fn new(state: Vec<f32>, policy: Vec<f32>, value: f32) -> Self {
+        Self {
+            state,
+            policy,
+            value,
+            metadata: SampleMetadata {
+                version: TENSOR_ENCODING_VERSION,
+                history_length: 0,
+                canonical: false,
+                symmetry: None,
+            },
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "state": self.state,
+            "policy": self.policy,
+            "value": self.value,
+            "metadata": {
+                "version": self.metadata.version,
+                "history_length": self.metadata.history_length,
+                "canonical": self.metadata.canonical,
+                "symmetry": self.metadata.symmetry
             }
+        })
+    }
+
+    /// Parses a sample written by `to_json`.
+    /// Samples without metadata are decoded with the legacy encoding, while samples
+    /// of any other version than the `encoding_version` of their encoding are rejected.
+    pub fn from_json(sample_json: &Value) -> Result<Self, SampleError> {
+        let history_length = sample_json
+            .get("metadata")
+            .and_then(|metadata| metadata.get("history_length"))
+            .map_or(Some(0), |history_length| history_length.as_u64())
+            .ok_or(SampleError::Malformed("metadata.history_length"))?
+            as usize;
+        let canonical = sample_json
+            .get("metadata")
+            .and_then(|metadata| metadata.get("canonical"))
+            .map_or(Some(false), |canonical| canonical.as_bool())
+            .ok_or(SampleError::Malformed("metadata.canonical"))?;
+        let symmetry = match sample_json
+            .get("metadata")
+            .and_then(|metadata| metadata.get("symmetry"))
+        {
+            Some(symmetry) => serde_json::from_value(symmetry.clone())
+                .map_err(|_| SampleError::Malformed("metadata.symmetry"))?,
+            None => None,
+        };
+        let version = match sample_json.get("metadata") {
+            Some(metadata) => metadata["version"]
+                .as_u64()
+                .ok_or(SampleError::Malformed("metadata.version"))?
+                as u32,
+            None => LEGACY_TENSOR_ENCODING_VERSION,
+        };
+        if version != encoding_version(history_length) {
+            return Err(SampleError::VersionMismatch {
+                found: version,
+                expected: encoding_version(history_length),
+            });
+        }
+
+        let parse_floats = |field: &'static str| -> Result<Vec<f32>, SampleError> {
+            sample_json[field]
+                .as_array()
+                .ok_or(SampleError::Malformed(field))?
+                .iter()
+                .map(|v| v.as_f64().map(|v| v as f32))
+                .collect::<Option<Vec<f32>>>()
+                .ok_or(SampleError::Malformed(field))
+        };
+
+        Ok(Self {
+            state: parse_floats("state")?,
+            policy: parse_floats("policy")?,
+            value: sample_json["value"]
+                .as_f64()
+                .ok_or(SampleError::Malformed("value"))? as f32,
+            metadata: SampleMetadata {
+                version,
+                history_length,
+                canonical,
+                symmetry,
+            },
+        })
+    }
+}
+
+/// A player that picks the action to make in a position.
+pub trait Agent {
+    /// Returns a legal action for the player to move on `board`.
+    fn get_action(&mut self, board: &Board) -> Action;
+
+    /// The name the agent is reported under, e.g. in a tournament leaderboard.
+    fn name(&self) -> String;
+}
+
+/// Plays uniformly random legal actions that are not forbidden.
+pub struct RandomAgent;
+
+impl Agent for RandomAgent {
+    fn get_action(&mut self, board: &Board) -> Action {
+        let actions: IndexSet<Action> = board
+            .legal_actions()
+            .iter()
+            .copied()
+            .filter(|&action| !board.is_forbidden(action))
+            .collect();
+
+        // Every legal action may be forbidden, in which case the game is lost anyway.
+        if actions.is_empty() {
+            get_random_action(board.legal_actions())
+        } else {
+            get_random_action(&actions)
+        }
+    }
+
+    fn name(&self) -> String {
+        String::from("Random")
+    }
+}
+
+/// Plays the most visited action of a new search with `n_mcts_simulations`.
+pub struct MctsAgent {
+    pub name: String,
+    pub model: tch::CModule,
+    pub n_mcts_simulations: usize,
+    /// The search parameters, or `None` for those suited to the size of the board.
+    pub mcts_config: Option<MctsConfig>,
+}
+
+impl MctsAgent {
+    /// Creates an agent searching with the model saved at `model_path`, named after it.
+    pub fn new(model_path: &str, n_mcts_simulations: usize) -> Self {
+        Self {
+            name: format!("{model_path} ({n_mcts_simulations} simulations)"),
+            model: get_torchjit_model(model_path),
+            n_mcts_simulations,
+            mcts_config: None,
+        }
+    }
+
+    /// Creates an agent searching with the model saved at `model_path`, with the
+    /// number of simulations and search parameters of `config`.
+    pub fn from_config(model_path: &str, config: &Config) -> Self {
+        let board = Board::new_square(config.size, config.n_in_a_row);
+        Self {
+            mcts_config: Some(config.mcts_config(&board)),
+            ..Self::new(model_path, config.n_mcts_simulations)
+        }
+    }
+}
+
+impl Agent for MctsAgent {
+    fn get_action(&mut self, board: &Board) -> Action {
+        let mut mcts = match &self.mcts_config {
+            Some(mcts_config) => MCTS::with_config(board, mcts_config),
+            None => MCTS::new(board, self.n_mcts_simulations),
+        };
+        mcts.get_best_action_with_temperature(&self.model, 0.0)
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+/// Prompts the human at the terminal for each action.
+pub struct HumanAgent;
+
+impl Agent for HumanAgent {
+    fn get_action(&mut self, board: &Board) -> Action {
+        prompt_player_action(board, &format!("{:?} to move", board.turn))
+    }
+
+    fn name(&self) -> String {
+        String::from("Human")
+    }
+}
+
+/// A game played under a `Referee`.
+#[derive(Debug, Clone)]
+pub struct GameRecord {
+    pub moves: Vec<Action>,
+    pub outcome: Outcome,
+    /// The time each agent took to pick each of the `moves`.
+    pub move_durations: Vec<Duration>,
+}
+
+#[derive(Debug)]
+pub enum RefereeError {
+    /// The agent playing `player` returned an action that cannot be played.
+    IllegalAction { player: Player, error: BoardError },
+}
+
+/// Plays games between a Black and a White agent, checking that every action
+/// they return is legal and timing their decisions.
+pub struct Referee {
+    pub black: Box<dyn Agent>,
+    pub white: Box<dyn Agent>,
+    pub size: usize,
+    pub n_in_a_row: usize,
+    /// Whether to print the board after every move.
+    pub verbose: bool,
+}
+
+impl Referee {
+    pub fn new(
+        black: Box<dyn Agent>,
+        white: Box<dyn Agent>,
+        size: usize,
+        n_in_a_row: usize,
+    ) -> Self {
+        Self {
+            black,
+            white,
+            size,
+            n_in_a_row,
+            verbose: false,
+        }
+    }
+
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Swaps the colors of the agents, e.g. between the games of a match.
+    pub fn swap_colors(&mut self) {
+        std::mem::swap(&mut self.black, &mut self.white);
+    }
+
+    /// Plays a game from the empty board until it is over.
+    pub fn play(&mut self) -> Result<GameRecord, RefereeError> {
+        Self::play_between(
+            self.black.as_mut(),
+            self.white.as_mut(),
+            self.size,
+            self.n_in_a_row,
+            self.verbose,
+        )
+    }
+
+    /// Same as `play`, for agents that are not owned by a `Referee`.
+    pub fn play_between(
+        black: &mut dyn Agent,
+        white: &mut dyn Agent,
+        size: usize,
+        n_in_a_row: usize,
+        verbose: bool,
+    ) -> Result<GameRecord, RefereeError> {
+        let mut board = Board::new_square(size, n_in_a_row);
+        let mut move_durations = Vec::new();
+        if verbose {
+            show(&board);
+        }
+
+        while !board.is_game_over() {
+            let now = Instant::now();
+            let action = match board.turn {
+                Player::Black => black.get_action(&board),
+                Player::White => white.get_action(&board),
+            };
+            move_durations.push(now.elapsed());
+
+            let player = board.turn;
+            board
+                .make_action(action)
+                .map_err(|error| RefereeError::IllegalAction { player, error })?;
+            if verbose {
+                println!("{}", board.display_with_last_move(Some(action)));
+            }
+        }
+
+        Ok(GameRecord {
+            moves: board.move_history().to_vec(),
+            outcome: board.outcome.expect("Game over should have an outcome."),
+            move_durations,
+        })
+    }
+}
+
+pub struct SelfPlayConfig {
+    pub size: usize,
+    pub n_in_a_row: usize,
+    pub n_mcts_simulations: usize,
+    pub model_path: String,
+    /// The directory the games are written to, which must exist.
+    pub output_dir: String,
+    pub output_format: OutputFormat,
+    /// The number of past positions to encode the states with, using
+    /// `Board::to_history_array`. If 0, states use the flat single-position encoding.
+    pub history_length: usize,
+    /// The temperature of the softmax of the policy logits. Above 1.0, the search
+    /// explores more varied positions, which diversifies the training data.
+    pub policy_temperature: f32,
+    /// Whether to store the states in their `Board::canonical_form`, with the values from
+    /// the perspective of the player to move. The search still evaluates the positions
+    /// as they are, so this is only suited to models trained on canonical states.
+    /// With `OutputFormat::JsonLines`, the final value stays Black's, as the player
+    /// to move alternates from Black on the first line.
+    pub canonical_states: bool,
+    /// Whether to write each position once per symmetry of the board, i.e. 8 times,
+    /// with the state and policy transformed alike. The JSON Lines output is not
+    /// augmented.
+    pub augment_symmetries: bool,
+    /// The search parameters, or `None` for those suited to the size of the board.
+    pub mcts_config: Option<MctsConfig>,
+    /// The number of random moves played before each game, from the positions of
+    /// `generate_opening_positions`, so that early training sees varied middle games.
+    /// If 0, the games start from the empty board.
+    pub n_opening_moves: usize,
+    /// If above 0, each game of `self_play` searches with a `ParallelMCTS` of this many
+    /// workers, whose leaves are evaluated by a single `ModelServer` shared by all of the
+    /// games. Otherwise, each game loads the model and evaluates its own leaves.
+    pub n_search_threads: usize,
+}
+
+impl Default for SelfPlayConfig {
+    fn default() -> Self {
+        Self {
+            size: 8,
+            n_in_a_row: 5,
+            n_mcts_simulations: 400,
+            model_path: String::from("test.pt"),
+            output_dir: String::from("games"),
+            output_format: OutputFormat::Json,
+            history_length: 0,
+            policy_temperature: 1.0,
+            canonical_states: false,
+            augment_symmetries: true,
+            mcts_config: None,
+            n_opening_moves: 0,
+            n_search_threads: 0,
         }
-        show(&board);
     }
 }
 
+pub fn play_game() {
+    Referee::new(Box::new(HumanAgent), Box::new(HumanAgent), 3, 3)
+        .with_verbose(true)
+        .play()
+        .expect("Human actions are checked when prompted.");
+}
+
 pub fn play_random_game() {
-    let mut board = Board::new(3, 3);
+    let mut board = Board::new_square(3, 3);
     while !board.is_game_over() {
         let random_action = get_random_action(&board.legal_actions());
         board.make_action(random_action).expect(
@@ -56,7 +490,7 @@ pub fn play_random_game() {
 
 pub fn benchmark() {
     let n_games = 1_000;
-    let mut board = Board::new(15, 5);
+    let mut board = Board::new_square(15, 5);
     let now = Instant::now();
     for _ in 0..n_games {
         board.reset();
@@ -73,37 +507,44 @@ pub fn benchmark() {
         "Games per second: {}",
         (n_games as f32 / elapsed_s) as usize
     );
+
+    let now = Instant::now();
+    for _ in 0..n_games {
+        board.reset();
+        while !board.is_game_over() {
+            let random_action = get_random_action(&board.legal_actions());
+            // SAFETY: The action is one of the legal actions, under the default rules.
+            unsafe { board.make_action_unchecked(random_action) };
+        }
+    }
+
+    let elapsed_s = now.elapsed().as_secs_f32();
+    println!(
+        "Games per second without legality checks: {}",
+        (n_games as f32 / elapsed_s) as usize
+    );
 }
 
 pub fn random_against_random() {
     let n_games = 10_000;
-    let mut board = Board::new(3, 3);
+    let mut referee = Referee::new(Box::new(RandomAgent), Box::new(RandomAgent), 3, 3);
 
     let mut black_wins = 0;
     let mut white_wins = 0;
     let mut draws = 0;
     let mut num_stones_placed = 0;
     for _ in 0..n_games {
-        board.reset();
-        while !board.is_game_over() {
-            let random_action = get_random_action(&board.legal_actions());
-            board.make_action(random_action).expect(
-                "Randomly selected move from the legal moves should not result in an error.",
-            );
-        }
+        let game_record = referee
+            .play()
+            .expect("Random actions are picked from the legal actions.");
 
-        match board.outcome {
-            Some(outcome) => match outcome {
-                Outcome::Winner(player) => match player {
-                    Player::Black => black_wins += 1,
-                    Player::White => white_wins += 1,
-                },
-                Outcome::Draw => draws += 1,
-            },
-            None => panic!("The game has ended and should have an outcome."),
+        match game_record.outcome {
+            Outcome::Winner(Player::Black) => black_wins += 1,
+            Outcome::Winner(Player::White) => white_wins += 1,
+            Outcome::Draw => draws += 1,
         }
 
-        num_stones_placed += board.num_stones_placed;
+        num_stones_placed += game_record.moves.len();
     }
 
     println!(
@@ -118,13 +559,148 @@ pub fn random_against_random() {
     println!("Stones placed: {:}", num_stones_placed / n_games);
 }
 
+/// The results for Black of the random games played after an opening move.
+#[derive(Debug, Copy, Clone)]
+pub struct OpeningStats {
+    pub action: Action,
+    pub black_wins: usize,
+    pub white_wins: usize,
+    pub draws: usize,
+}
+
+impl OpeningStats {
+    pub fn n_games(&self) -> usize {
+        self.black_wins + self.white_wins + self.draws
+    }
+
+    pub fn black_win_rate(&self) -> f32 {
+        self.black_wins as f32 / self.n_games().max(1) as f32
+    }
+}
+
+/// Plays `n_games_per_opening` random games after each possible first move of Black,
+/// and prints the win rate of Black as a heatmap of the board, followed by a table
+/// of the openings from the best to the worst for Black, which it returns.
+pub fn compute_win_rates_by_opening(
+    n_games_per_opening: usize,
+    size: usize,
+    n_in_a_row: usize,
+) -> Vec<OpeningStats> {
+    let empty_board = Board::new_square(size, n_in_a_row);
+    let openings: Vec<Action> = empty_board.legal_actions().iter().copied().collect();
+
+    let mut opening_stats: Vec<OpeningStats> = openings
+        .par_iter()
+        .map(|&action| {
+            let mut stats = OpeningStats {
+                action,
+                black_wins: 0,
+                white_wins: 0,
+                draws: 0,
+            };
+            for _ in 0..n_games_per_opening {
+                let mut board = empty_board.clone();
+                board
+                    .make_action(action)
+                    .expect("The openings are legal actions of the empty board.");
+                match rollout(&mut board) {
+                    Outcome::Winner(Player::Black) => stats.black_wins += 1,
+                    Outcome::Winner(Player::White) => stats.white_wins += 1,
+                    Outcome::Draw => stats.draws += 1,
+                }
+            }
+            stats
+        })
+        .collect();
+
+    println!("Black win rate (%) by opening:");
+    for row_index in 0..size {
+        let row_name = &empty_board.action_to_algebraic([row_index, 0])[1..];
+        print!("{:>2} ", row_name);
+        for col_index in 0..size {
+            let stats = opening_stats
+                .iter()
+                .find(|stats| stats.action == [row_index, col_index])
+                .expect("Every square is an opening.");
+            print!("{:>4.0}", stats.black_win_rate() * 100.0);
+        }
+        println!();
+    }
+    print!("   ");
+    for col_index in 0..size {
+        print!(
+            "{:>4}",
+            &empty_board.action_to_algebraic([0, col_index])[..1]
+        );
+    }
+    println!();
+
+    opening_stats.sort_by(|a, b| b.black_win_rate().total_cmp(&a.black_win_rate()));
+    println!("\nOpening  Black wins  White wins  Draws");
+    for stats in &opening_stats {
+        println!(
+            "{:<7}  {:>9.1}%  {:>9.1}%  {:>4.1}%",
+            empty_board.action_to_algebraic(stats.action),
+            stats.black_win_rate() * 100.0,
+            stats.white_wins as f32 / stats.n_games().max(1) as f32 * 100.0,
+            stats.draws as f32 / stats.n_games().max(1) as f32 * 100.0,
+        );
+    }
+
+    opening_stats
+}
+
+/// Plays `n_games` random games on each square board from `min_size` to `max_size`, and
+/// prints a table of the draw rates, which should fall quickly as the boards grow.
+/// Sizes smaller than `n_in_a_row` are skipped. Returns the draw rate of each size.
+pub fn compute_draw_rate_vs_board_size(
+    min_size: usize,
+    max_size: usize,
+    n_in_a_row: usize,
+    n_games: usize,
+) -> Vec<(usize, f32)> {
+    println!("Size  Draws  Black wins  White wins");
+    let mut draw_rates = Vec::new();
+    for size in min_size.max(n_in_a_row)..=max_size {
+        let empty_board = Board::new_square(size, n_in_a_row);
+        let outcomes: Vec<Outcome> = (0..n_games)
+            .into_par_iter()
+            .map(|_| rollout(&mut empty_board.clone()))
+            .collect();
+        let rate = |expected: Outcome| {
+            outcomes
+                .iter()
+                .filter(|&&outcome| outcome == expected)
+                .count() as f32
+                / n_games.max(1) as f32
+        };
+
+        let draw_rate = rate(Outcome::Draw);
+        println!(
+            "{:>4}  {:>4.1}%  {:>9.1}%  {:>9.1}%",
+            size,
+            draw_rate * 100.0,
+            rate(Outcome::Winner(Player::Black)) * 100.0,
+            rate(Outcome::Winner(Player::White)) * 100.0,
+        );
+        draw_rates.push((size, draw_rate));
+    }
+
+    draw_rates
+}
+
 pub fn get_player_action(board: &Board) -> Action {
+    prompt_player_action(board, "Your move")
+}
+
+/// Reads actions from stdin with `prompt` until one of them is legal.
+fn prompt_player_action(board: &Board, prompt: &str) -> Action {
     let mut square_string = String::new();
     loop {
         square_string.clear();
         println!("{:?}", board.legal_moves_as_strings());
 
-        print!("\nYour move: ");
+        print!("\n{prompt}: ");
         io::stdout().flush().unwrap();
         io::stdin()
             .read_line(&mut square_string)
@@ -132,7 +708,10 @@ pub fn get_player_action(board: &Board) -> Action {
         square_string = square_string.trim().to_string();
 
         let action = board.parse_string_to_action(&square_string);
-        if action.is_ok() && board.legal_actions().contains(&action.unwrap()) {
+        if action.is_ok()
+            && board.is_action_legal(action.unwrap())
+            && !board.is_forbidden(action.unwrap())
+        {
             return action.unwrap();
         } else {
             println!("{square_string} is not a valid move.");
@@ -140,24 +719,96 @@ pub fn get_player_action(board: &Board) -> Action {
     }
 }
 
-pub fn play_game_against_mcts() {
-    let model = get_torchjit_model("old.pt");
-    let mut board = Board::new(3, 3);
+/// The maximum number of iterations searched while waiting for the player,
+/// which bounds the size of the tree.
+const PONDER_MAX_ITERATIONS: usize = 10_000;
+
+/// Prompts the player for an action, while searching the position with `mcts`
+/// in the meantime so that the following search starts from a larger tree.
+fn ponder_player_action(board: &Board, mcts: &mut MCTS, model: &tch::CModule) -> Action {
+    thread::scope(|scope| {
+        let prompt = scope.spawn(|| get_player_action(board));
+
+        let mut n_iterations = 0;
+        while !prompt.is_finished() && n_iterations < PONDER_MAX_ITERATIONS {
+            n_iterations += mcts.run_iterations_batch(model, mcts.config.batch_size);
+        }
+
+        prompt.join().expect("The prompt should not panic.")
+    })
+}
+
+pub fn play_game_against_mcts(config: &Config) {
+    let model = get_torchjit_model(&config.model_path);
+    let mut board = Board::new_square(config.size, config.n_in_a_row);
     show(&board);
 
+    // The tree is kept between moves and searched while the player thinks,
+    // and only rebuilt when the player makes an action it has not explored.
+    let mut mcts = MCTS::with_config(&board, &config.mcts_config(&board));
+    mcts.verbose = true;
     while !board.is_game_over() {
         let action: Action;
         if board.turn == Player::White {
-            action = get_player_action(&mut board);
+            action = ponder_player_action(&board, &mut mcts, &model);
         } else {
-            let mut mcts = MCTS::new(&board, 400);
-            action = mcts.get_best_action(&model, false);
+            action = match config.time_limit_ms {
+                Some(time_limit_ms) => mcts.get_best_action_timed(&model, time_limit_ms),
+                None => mcts.get_best_action_with_temperature(&model, 0.0),
+            };
+
+            let principal_variation: Vec<String> = mcts
+                .get_principal_variation()
+                .iter()
+                .map(|action| board.action_to_algebraic(*action))
+                .collect();
+            println!("Principal variation: {}", principal_variation.join(" "));
         }
         board.make_action(action).ok();
+        mcts.set_root_from_board(&board);
         show(&board);
     }
 
     dbg!(&board.outcome);
+    if let Some(winning_line) = board.winning_line() {
+        let notations: Vec<String> = winning_line
+            .iter()
+            .map(|action| board.action_to_algebraic(*action))
+            .collect();
+        println!("Winning line: {}", notations.join(" "));
+    }
+    println!(
+        "Moves: {}",
+        Board::moves_to_string(board.move_history(), config.size)
+    );
+}
+
+/// Plays a game between two humans at the same terminal, prompting each of them in turn.
+pub fn play_game_human_vs_human(size: usize, n_in_a_row: usize) {
+    let game_record = Referee::new(Box::new(HumanAgent), Box::new(HumanAgent), size, n_in_a_row)
+        .with_verbose(true)
+        .play()
+        .expect("Human actions are checked when prompted.");
+
+    let result = match game_record.outcome {
+        Outcome::Winner(winner) => format!("{:?} wins!", winner),
+        Outcome::Draw => String::from("It's a draw!"),
+    };
+    println!(
+        "\n{}\n{}\n{}",
+        "=".repeat(result.len()),
+        result,
+        "=".repeat(result.len())
+    );
+    let board = Board::from_actions(size, size, n_in_a_row, &game_record.moves)
+        .expect("The moves of the game should be playable.");
+    if let Some(winning_line) = board.winning_line() {
+        let notations: Vec<String> = winning_line
+            .iter()
+            .map(|action| board.action_to_algebraic(*action))
+            .collect();
+        println!("Winning line: {}", notations.join(" "));
+    }
 }
 
 // pub fn random_against_mcts() {
@@ -168,7 +819,7 @@ pub fn play_game_against_mcts() {
 //     let mut draws = 0;
 //     for i in 0..n_games {
 //         println!("{}", i);
-//         let mut board = Board::new(10, 5);
+//         let mut board = Board::new_square(10, 5);
 
 //         while !board.is_game_over() {
 //             let action: Action;
@@ -206,130 +857,1086 @@ pub fn play_game_against_mcts() {
 //     println!("Draws: {:.1}%", draws as f32 / n_games as f32 * 100.0);
 // }
 
-pub fn self_play_single_game(size: usize, n_in_a_row: usize, n_mcts_simulations: usize) {
-    let model = get_torchjit_model("test.pt");
-    let mut board = Board::new(size, n_in_a_row);
+/// Encodes the state of a self-play sample, as configured by `history_length`
+/// and `canonical_states`. `history` holds the positions before `board`, oldest first.
+fn encode_state(board: &Board, history: &[Board], config: &SelfPlayConfig) -> Vec<f32> {
+    if config.history_length == 0 {
+        return board
+            .to_flat_array(config.canonical_states)
+            .iter()
+            .copied()
+            .collect();
+    }
+
+    if config.canonical_states && board.turn == Player::White {
+        let swapped_history: Vec<Board> = history.iter().map(|b| b.flip_colors()).collect();
+        board
+            .canonical_form()
+            .to_history_array(&swapped_history, config.history_length)
+            .into_raw_vec()
+    } else {
+        board
+            .to_history_array(history, config.history_length)
+            .into_raw_vec()
+    }
+}
+
+/// Statistics of a self-play game, to track the progress of training.
+#[derive(Debug, Clone, Serialize)]
+pub struct GameStats {
+    pub n_moves: usize,
+    /// The winner, or `None` for a draw.
+    pub winner: Option<Player>,
+    /// The seconds spent picking each move.
+    pub move_seconds: Vec<f32>,
+    /// The visit count of the root of the search of each move.
+    pub root_visit_counts: Vec<usize>,
+    /// The entropy of the search policy of each move, in nats.
+    pub policy_entropies: Vec<f32>,
+    /// The entropy of the policy of the network at the root of each move, in nats,
+    /// which falls as the network learns to focus on the promising moves.
+    pub network_policy_entropies: Vec<f32>,
+}
+
+impl GameStats {
+    pub fn mean_move_seconds(&self) -> f32 {
+        mean(&self.move_seconds)
+    }
+
+    pub fn mean_policy_entropy(&self) -> f32 {
+        mean(&self.policy_entropies)
+    }
+
+    pub fn mean_network_policy_entropy(&self) -> f32 {
+        mean(&self.network_policy_entropies)
+    }
+}
+
+/// Summary statistics of a set of values.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub mean: f32,
+    pub min: f32,
+    pub max: f32,
+    pub p10: f32,
+    pub median: f32,
+    pub p90: f32,
+}
+
+impl Summary {
+    /// Summarizes `values`, or returns `None` if there are none.
+    pub fn of(values: &[f32]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        // Nearest-rank percentile.
+        let percentile = |p: f32| {
+            let rank = (p / 100.0 * sorted.len() as f32).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        };
+
+        Some(Self {
+            mean: mean(&sorted),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            p10: percentile(10.0),
+            median: percentile(50.0),
+            p90: percentile(90.0),
+        })
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "mean {:.3}, min {:.3}, p10 {:.3}, median {:.3}, p90 {:.3}, max {:.3}",
+            self.mean, self.min, self.p10, self.median, self.p90, self.max
+        )
+    }
+}
+
+fn mean(values: &[f32]) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+
+    values.iter().sum::<f32>() / values.len() as f32
+}
+
+/// The aggregate statistics of the games of a `self_play` run.
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfPlayStats {
+    /// The end of the run, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub n_games: usize,
+    pub black_wins: usize,
+    pub white_wins: usize,
+    pub draws: usize,
+    /// The number of moves of each game.
+    pub game_length: Option<Summary>,
+    /// The seconds spent picking every move of every game.
+    pub move_seconds: Option<Summary>,
+    pub root_visit_count: Option<Summary>,
+    pub policy_entropy: Option<Summary>,
+    pub network_policy_entropy: Option<Summary>,
+}
+
+impl SelfPlayStats {
+    pub fn from_games(game_stats: &[GameStats]) -> Self {
+        let count_winner = |winner: Option<Player>| {
+            game_stats
+                .iter()
+                .filter(|stats| stats.winner == winner)
+                .count()
+        };
+        let summarize_moves = |values: fn(&GameStats) -> Vec<f32>| {
+            Summary::of(&game_stats.iter().flat_map(values).collect::<Vec<f32>>())
+        };
+
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+            n_games: game_stats.len(),
+            black_wins: count_winner(Some(Player::Black)),
+            white_wins: count_winner(Some(Player::White)),
+            draws: count_winner(None),
+            game_length: Summary::of(
+                &game_stats
+                    .iter()
+                    .map(|stats| stats.n_moves as f32)
+                    .collect::<Vec<f32>>(),
+            ),
+            move_seconds: summarize_moves(|stats| stats.move_seconds.clone()),
+            root_visit_count: summarize_moves(|stats| {
+                stats.root_visit_counts.iter().map(|&n| n as f32).collect()
+            }),
+            policy_entropy: summarize_moves(|stats| stats.policy_entropies.clone()),
+            network_policy_entropy: summarize_moves(|stats| stats.network_policy_entropies.clone()),
+        }
+    }
+
+    pub fn print(&self) {
+        println!(
+            "Black wins: {}, White wins: {}, draws: {}",
+            self.black_wins, self.white_wins, self.draws
+        );
+        for (name, summary) in [
+            ("Game length", &self.game_length),
+            ("Seconds per move", &self.move_seconds),
+            ("Root visit count", &self.root_visit_count),
+            ("Policy entropy", &self.policy_entropy),
+            ("Network policy entropy", &self.network_policy_entropy),
+        ] {
+            if let Some(summary) = summary {
+                println!("{name}: {summary}");
+            }
+        }
+    }
+}
+
+/// Returns `n_positions` distinct positions reached by playing `n_moves` random moves from
+/// the empty board, none of which is over, to start self-play games from.
+/// Fewer positions are returned if not enough distinct ones are found, e.g. on small boards.
+pub fn generate_opening_positions(
+    size: usize,
+    n_in_a_row: usize,
+    n_moves: usize,
+    n_positions: usize,
+) -> Vec<Board> {
+    let empty_board = Board::new_square(size, n_in_a_row);
+    let mut positions = Vec::with_capacity(n_positions);
+    let mut seen_hashes = HashSet::with_capacity(n_positions);
+
+    for _ in 0..n_positions * OPENING_ATTEMPTS_PER_POSITION {
+        if positions.len() == n_positions {
+            break;
+        }
+
+        let mut board = empty_board.clone();
+        for _ in 0..n_moves {
+            if board.is_game_over() {
+                break;
+            }
+            board
+                .make_action(get_random_action(board.legal_actions()))
+                .expect("Random actions are picked from the legal actions.");
+        }
+
+        if !board.is_game_over() && seen_hashes.insert(board.hash()) {
+            positions.push(board);
+        }
+    }
+
+    positions
+}
+
+/// Plays a game against itself and writes its positions in `config.output_format`.
+/// With `OutputFormat::Sqlite`, they are inserted into `data_store`, which must be `Some`.
+/// The positions are evaluated by the server of `model_client` if given, see
+/// `SelfPlayConfig::n_search_threads`.
+pub fn self_play_single_game(
+    config: &SelfPlayConfig,
+    data_store: Option<&Mutex<DataStore>>,
+    model_client: Option<&ModelClient>,
+) -> GameStats {
+    let empty_board = Board::new_square(config.size, config.n_in_a_row);
+    self_play_from_position(&empty_board, config, data_store, model_client)
+}
+
+/// Same as `self_play_single_game`, continuing the game from `position`, e.g. one of
+/// `generate_opening_positions`. Only the positions searched are written, not the
+/// ones before `position`.
+pub fn self_play_from_position(
+    position: &Board,
+    config: &SelfPlayConfig,
+    data_store: Option<&Mutex<DataStore>>,
+    model_client: Option<&ModelClient>,
+) -> GameStats {
+    self_play_game(position, config, data_store, model_client, None)
+}
+
+/// Plays a self-play game from `position`. Its positions are written in
+/// `config.output_format`, unless `on_game_complete` is given, in which case they are
+/// passed to it instead once the game is over.
+///
+/// With a `model_client`, the searches are `ParallelMCTS`s of `config.n_search_threads`
+/// workers evaluating their leaves with its server, rather than with a model of their own.
+fn self_play_game(
+    position: &Board,
+    config: &SelfPlayConfig,
+    data_store: Option<&Mutex<DataStore>>,
+    model_client: Option<&ModelClient>,
+    on_game_complete: Option<&(dyn Fn(Vec<GameStep>) + Sync)>,
+) -> GameStats {
+    // No output format when the positions go to the callback.
+    let output_format = match on_game_complete {
+        Some(_) => None,
+        None => Some(config.output_format),
+    };
+    let model = match model_client {
+        Some(_) => None,
+        None => Some(get_torchjit_model(&config.model_path)),
+    };
+    let mut board = position.clone();
 
     let mut policies = Vec::new();
     let mut board_vecs = Vec::new();
+    let mut csv_rows = Vec::new();
+    // The move number and symmetry of each sample of the JSON, CSV and SQLite outputs,
+    // and whether its colors are flipped, see `Board::symmetries`.
+    let mut sample_moves: Vec<(usize, Option<Symmetry>, bool)> = Vec::new();
+    // The positions before the current one, at most as many as the history encoding uses.
+    let mut history: VecDeque<Board> =
+        VecDeque::with_capacity(config.history_length.saturating_sub(1));
+
+    let mut jsonl_writer = match output_format {
+        Some(OutputFormat::JsonLines) => Some(BufWriter::new(
+            File::create(format!("{}/{}.jsonl", config.output_dir, Uuid::new_v4())).unwrap(),
+        )),
+        _ => None,
+    };
+
+    let mut move_seconds = Vec::new();
+    let mut root_visit_counts = Vec::new();
+    let mut policy_entropies = Vec::new();
+    let mut network_policy_entropies = Vec::new();
 
     while !board.is_game_over() {
-        let mut mcts = MCTS::new(&board, n_mcts_simulations);
-        let action = mcts.get_best_action(&model, true);
+        let now = Instant::now();
+        let temperature = tau_schedule(board.move_history().len());
+        let (action, policy) = match (model_client, &model) {
+            (Some(model_client), _) => {
+                let mut mcts =
+                    ParallelMCTS::new(&board, config.n_mcts_simulations, config.n_search_threads);
+                if let Some(mcts_config) = &config.mcts_config {
+                    mcts.n_iterations = mcts_config.n_iterations;
+                    mcts.config = mcts_config.clone();
+                }
+                let action = mcts.get_best_action_from_server(model_client, temperature);
+                root_visit_counts.push(mcts.root_visit_count());
+                (action, mcts.get_flat_policy())
+            }
+            (None, Some(model)) => {
+                let mut mcts = match &config.mcts_config {
+                    Some(mcts_config) => MCTS::with_config(&board, mcts_config),
+                    None => MCTS::new(&board, config.n_mcts_simulations),
+                };
+                mcts.config.policy_temperature = config.policy_temperature;
+                let action = mcts.get_best_action_with_temperature(model, temperature);
+                root_visit_counts.push(mcts.root_visit_count());
+                // The root is evaluated by the network unless the action was known without
+                // searching.
+                if let Some((network_policy, _)) = mcts.transposition_table.peek(board.hash()) {
+                    network_policy_entropies.push(policy_entropy(network_policy));
+                }
+                (action, mcts.get_flat_policy_normalized())
+            }
+            (None, None) => unreachable!("The model is loaded without a model client."),
+        };
+        move_seconds.push(now.elapsed().as_secs_f32());
+        policy_entropies.push(policy_entropy(&policy));
 
-        policies.push(mcts.get_flat_policy());
-        board_vecs.push(board.to_flat_vec());
+        // The JSON Lines output is written as the game goes, and the steps passed to
+        // `on_game_complete` do not record their symmetry, so neither is augmented.
+        let is_augmented = config.augment_symmetries
+            && output_format.is_some_and(|format| format != OutputFormat::JsonLines);
+        let variants: Vec<(Board, Option<Symmetry>)> = if is_augmented {
+            board
+                .symmetries()
+                .into_iter()
+                .map(|(board, symmetry)| (board, Some(symmetry)))
+                .collect()
+        } else {
+            vec![(board.clone(), None)]
+        };
 
+        for (variant, symmetry) in variants {
+            let colors_flipped = variant.turn != board.turn;
+            let (state, policy) = match symmetry {
+                Some(symmetry) => {
+                    let transformed_history: Vec<Board> = history
+                        .iter()
+                        .map(|b| {
+                            let transformed = b.transformed(symmetry);
+                            if colors_flipped {
+                                transformed.flip_colors()
+                            } else {
+                                transformed
+                            }
+                        })
+                        .collect();
+                    (
+                        encode_state(&variant, &transformed_history, config),
+                        symmetry.transform_flat_policy(&policy, board.rows, board.cols),
+                    )
+                }
+                None => (
+                    encode_state(&variant, history.make_contiguous(), config),
+                    policy.clone(),
+                ),
+            };
+
+            match output_format {
+                Some(OutputFormat::Json | OutputFormat::Sqlite | OutputFormat::Binary) | None => {
+                    board_vecs.push(state)
+                }
+                Some(OutputFormat::Csv) => csv_rows.push(state.iter().join(",")),
+                Some(OutputFormat::JsonLines) => {
+                    let writer = jsonl_writer.as_mut().expect("Opened before the game.");
+                    write_json_line(
+                        writer,
+                        &json!({
+                            "state": state,
+                            "policy": policy,
+                            "metadata": {
+                                "version": encoding_version(config.history_length),
+                                "history_length": config.history_length,
+                                "canonical": config.canonical_states
+                            }
+                        }),
+                    );
+                }
+            }
+            if output_format != Some(OutputFormat::JsonLines) {
+                policies.push(policy);
+                sample_moves.push((board.move_history().len(), symmetry, colors_flipped));
+            }
+        }
+
+        if config.history_length > 1 {
+            if history.len() == config.history_length - 1 {
+                history.pop_front();
+            }
+            history.push_back(board.clone());
+        }
         board.make_action(action).ok();
     }
 
-    // Create values
-    let value = match board
+    let outcome = board
         .outcome
-        .expect("The game has ended and should have an outcome.")
-    {
-        Outcome::Winner(winner) => match winner {
-            Player::Black => 1.0,
-            Player::White => -1.0,
+        .expect("The game has ended and should have an outcome.");
+    let game_stats = GameStats {
+        n_moves: board.move_history().len(),
+        winner: match outcome {
+            Outcome::Winner(winner) => Some(winner),
+            Outcome::Draw => None,
         },
-        Outcome::Draw => 0.0,
+        move_seconds,
+        root_visit_counts,
+        policy_entropies,
+        network_policy_entropies,
+    };
+
+    // Create values
+    let value = outcome.score_for(Player::Black);
+    // Canonical states are valued from the perspective of the player to move,
+    // who alternates from Black on the first move. Flipping the colors of a sample
+    // flips its value for Black, but not for the player to move.
+    let value_of_move = |move_index: usize, colors_flipped: bool| {
+        let flipped = if config.canonical_states {
+            move_index % 2 == 1
+        } else {
+            colors_flipped
+        };
+        if flipped {
+            -value
+        } else {
+            value
+        }
+    };
+
+    let into_steps = |board_vecs: Vec<Vec<f32>>, policies: Vec<Vec<f32>>| -> Vec<GameStep> {
+        let game_id = Uuid::new_v4().to_string();
+        zip(zip(board_vecs, policies), &sample_moves)
+            .map(
+                |((state, policy), (move_index, _, colors_flipped))| GameStep {
+                    game_id: game_id.clone(),
+                    state,
+                    policy,
+                    value: value_of_move(*move_index, *colors_flipped),
+                    move_number: *move_index,
+                },
+            )
+            .collect()
     };
 
-    // JSON
-    let mut game_json: Vec<Value> = vec![];
-    for (board_vec, policy) in board_vecs.iter().zip(policies.iter()) {
-        game_json.push(json!({
-            "state": board_vec,
-            "policy": policy,
-            "value": value
-        }));
+    let Some(output_format) = output_format else {
+        let on_game_complete = on_game_complete.expect("The positions go to the callback.");
+        on_game_complete(into_steps(board_vecs, policies));
+        return game_stats;
+    };
+
+    match output_format {
+        OutputFormat::Json => {
+            let mut game_json: Vec<Value> = vec![];
+            for ((board_vec, policy), (move_index, symmetry, colors_flipped)) in
+                zip(zip(board_vecs, policies), sample_moves)
+            {
+                let value = value_of_move(move_index, colors_flipped);
+                let mut sample = TrainingSample::new(board_vec, policy, value);
+                sample.metadata.version = encoding_version(config.history_length);
+                sample.metadata.history_length = config.history_length;
+                sample.metadata.canonical = config.canonical_states;
+                sample.metadata.symmetry = symmetry;
+                game_json.push(sample.to_json());
+            }
+            std::fs::write(
+                format!("{}/{}.json", config.output_dir, Uuid::new_v4()),
+                serde_json::to_string_pretty(&game_json).unwrap(),
+            )
+            .unwrap();
+        }
+        OutputFormat::Csv => {
+            let policy_header = (0..config.size * config.size)
+                .map(|i| format!("p{i}"))
+                .join(",");
+            let state_header = if config.history_length > 0 {
+                let n_planes = 2 * config.history_length + 1;
+                (0..n_planes * config.size * config.size)
+                    .map(|i| format!("h{i}"))
+                    .join(",")
+            } else {
+                Board::csv_header(config.size, config.size, config.n_in_a_row)
+                    .trim_end()
+                    .to_string()
+            };
+            let mut game_csv = format!("{},{},value\n", state_header, policy_header);
+            for ((csv_row, policy), (move_index, _, colors_flipped)) in
+                zip(zip(csv_rows, policies), sample_moves)
+            {
+                game_csv.push_str(&format!(
+                    "{},{},{}\n",
+                    csv_row,
+                    policy.iter().join(","),
+                    value_of_move(move_index, colors_flipped)
+                ));
+            }
+            std::fs::write(
+                format!("{}/{}.csv", config.output_dir, Uuid::new_v4()),
+                game_csv,
+            )
+            .unwrap();
+        }
+        OutputFormat::JsonLines => {
+            let writer = jsonl_writer.as_mut().expect("Opened before the game.");
+            write_json_line(writer, &json!({ "value": value }));
+        }
+        OutputFormat::Binary => {
+            let header = [
+                board_vecs.len() as u32,
+                board_vecs.first().map_or(0, |state| state.len()) as u32,
+                policies.first().map_or(0, |policy| policy.len()) as u32,
+            ];
+            let mut game_bytes: Vec<u8> = header
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+            for ((state, policy), (move_index, _, colors_flipped)) in
+                zip(zip(board_vecs, policies), sample_moves)
+            {
+                game_bytes.extend(Board::policy_to_bytes(&state));
+                game_bytes.extend(Board::policy_to_bytes(&policy));
+                game_bytes.extend(value_of_move(move_index, colors_flipped).to_le_bytes());
+            }
+            std::fs::write(
+                format!("{}/{}.bin", config.output_dir, Uuid::new_v4()),
+                game_bytes,
+            )
+            .unwrap();
+        }
+        OutputFormat::Sqlite => {
+            let steps = into_steps(board_vecs, policies);
+            data_store
+                .expect("The SQLite output needs a data store.")
+                .lock()
+                .unwrap()
+                .insert_game(&steps)
+                .expect("Should be able to insert the game.");
+        }
     }
-    std::fs::write(
-        format!("games/{}.json", Uuid::new_v4()),
-        serde_json::to_string_pretty(&game_json).unwrap(),
-    )
-    .unwrap();
+
+    game_stats
 }
 
-pub fn self_play(n_games: usize) {
-    let size: usize = 8;
-    let n_in_a_row: usize = 5;
-    let n_mcts_simulations = 400;
+/// Plays `n_games` self-play games in parallel like `self_play`, passing the positions
+/// of each game to `on_game_complete` once it is over instead of writing them, so that
+/// they can be stored in any backend. Returns the stats of the games.
+///
+/// A `GameStep` does not record a symmetry, so the positions are not augmented whatever
+/// `config.augment_symmetries` is: each move gives a single step.
+pub fn self_play_with_callback<F>(
+    n_games: usize,
+    config: &SelfPlayConfig,
+    on_game_complete: F,
+) -> Vec<GameStats>
+where
+    F: Fn(Vec<GameStep>) + Sync,
+{
+    let empty_board = Board::new_square(config.size, config.n_in_a_row);
+
+    (0..n_games)
+        .into_par_iter()
+        .map(|_| self_play_game(&empty_board, config, None, None, Some(&on_game_complete)))
+        .collect()
+}
+
+/// Writes `value` on its own line and flushes it,
+/// so that the file can be followed with `tail -f` while the game is played.
+fn write_json_line(writer: &mut impl Write, value: &Value) {
+    serde_json::to_writer(&mut *writer, value).unwrap();
+    writeln!(writer).unwrap();
+    writer.flush().unwrap();
+}
+
+pub fn self_play(n_games: usize, config: &SelfPlayConfig) {
+    let data_store = match config.output_format {
+        OutputFormat::Sqlite => Some(Mutex::new(
+            DataStore::open(&format!("{}/{}", config.output_dir, DATA_STORE_FILE_NAME))
+                .expect("Should be able to open the data store."),
+        )),
+        _ => None,
+    };
+
+    // An overall bar, and below it a spinner per rayon thread showing its current game.
+    let progress = MultiProgress::new();
+    let games_bar = progress.add(ProgressBar::new(n_games as u64));
+    games_bar.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {wide_bar} {pos}/{len} games ({per_sec}, ETA {eta})",
+        )
+        .unwrap(),
+    );
+    let thread_bars: Vec<ProgressBar> = (0..rayon::current_num_threads())
+        .map(|thread_index| {
+            let thread_bar = progress.add(ProgressBar::new_spinner());
+            thread_bar.set_style(
+                ProgressStyle::with_template("  {spinner} thread {prefix}: {msg} ({elapsed})")
+                    .unwrap(),
+            );
+            thread_bar.set_prefix(thread_index.to_string());
+            thread_bar.set_message("idle");
+            thread_bar.enable_steady_tick(Duration::from_millis(200));
+            thread_bar
+        })
+        .collect();
+
+    let opening_positions = if config.n_opening_moves > 0 {
+        generate_opening_positions(
+            config.size,
+            config.n_in_a_row,
+            config.n_opening_moves,
+            n_games,
+        )
+    } else {
+        Vec::new()
+    };
 
+    // Every worker of every game may be waiting on an evaluation at once.
+    let model_server = (config.n_search_threads > 0).then(|| {
+        ModelServer::spawn(
+            get_torchjit_model(&config.model_path),
+            rayon::current_num_threads() * config.n_search_threads,
+            MODEL_SERVER_TIMEOUT_US,
+            config.policy_temperature,
+        )
+    });
+    let model_client = model_server.as_ref().map(ModelServer::client);
+
+    let game_stats: Mutex<Vec<GameStats>> = Mutex::new(Vec::with_capacity(n_games));
     let total_elapsed_s: f32 = (0..n_games)
         .collect::<Vec<usize>>()
         .par_iter()
         .map(|i| {
+            let thread_bar = rayon::current_thread_index().and_then(|index| thread_bars.get(index));
+            if let Some(thread_bar) = thread_bar {
+                thread_bar.reset_elapsed();
+                thread_bar.set_message(format!("playing game {}", i + 1));
+            }
+
             let now = Instant::now();
-            self_play_single_game(size, n_in_a_row, n_mcts_simulations);
+            let stats = if opening_positions.is_empty() {
+                self_play_single_game(config, data_store.as_ref(), model_client.as_ref())
+            } else {
+                let position = &opening_positions[i % opening_positions.len()];
+                self_play_from_position(
+                    position,
+                    config,
+                    data_store.as_ref(),
+                    model_client.as_ref(),
+                )
+            };
+            game_stats.lock().unwrap().push(stats);
             let elapsed_s = now.elapsed().as_secs_f32();
-            println!("Seconds per game: {}", elapsed_s);
+
+            if let Some(thread_bar) = thread_bar {
+                thread_bar.set_message("idle");
+            }
+            games_bar.inc(1);
             elapsed_s
         })
         .sum();
 
+    for thread_bar in thread_bars {
+        thread_bar.finish_and_clear();
+    }
+    games_bar.finish();
+
     println!(
         "Average seconds per game: {}",
         total_elapsed_s / n_games as f32
-    )
+    );
+
+    let self_play_stats = SelfPlayStats::from_games(&game_stats.into_inner().unwrap());
+    self_play_stats.print();
+
+    // One line per run, so that the progress of training can be followed across runs.
+    let mut stats_file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(format!(
+            "{}/{}",
+            config.output_dir, SELF_PLAY_STATS_FILE_NAME
+        ))
+        .expect("Should be able to open the self-play statistics.");
+    write_json_line(&mut stats_file, &json!(self_play_stats));
 }
 
-pub fn ai_vs_ai_single(
+/// Shades of the policy heatmaps of `replay_game_from_json` and `watch_self_play`,
+/// from the lowest probability.
+const HEATMAP_SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+
+/// Replays a game written by `self_play_single_game` in the JSON format: each position is
+/// decoded from its state and shown, followed by the policy of the search as a heatmap,
+/// waiting `delay_ms` between moves.
+///
+/// Canonical states are shown from the perspective of the player to move, and of the
+/// samples augmented with symmetries, only the untransformed ones are shown.
+pub fn replay_game_from_json(path: &str, delay_ms: u64) -> Result<(), SampleError> {
+    let game_json: Value =
+        serde_json::from_str(&std::fs::read_to_string(path).map_err(SampleError::Io)?)
+            .map_err(|_| SampleError::Malformed("game"))?;
+    let samples = game_json.as_array().ok_or(SampleError::Malformed("game"))?;
+
+    for sample_json in samples {
+        let sample = TrainingSample::from_json(sample_json)?;
+        if !matches!(sample.metadata.symmetry, None | Some(Symmetry::Identity)) {
+            continue;
+        }
+
+        let board = decode_state(&sample)?;
+        show(&board);
+        println!("{}", policy_heatmap(&sample.policy, board.rows, board.cols));
+
+        thread::sleep(Duration::from_millis(delay_ms));
+    }
+
+    Ok(())
+}
+
+/// Removes the samples of the JSON training files in `dir` whose position, as fingerprinted
+/// by `Board::hash_stones`, was already seen in an earlier sample, rewriting the files
+/// that lost samples and deleting those left empty. The files are visited in name order.
+/// Samples whose state cannot be decoded, e.g. canonical states of White to move, are kept,
+/// while samples of another encoding version make it return an `InvalidData` error, as
+/// their positions cannot be compared. Returns the number of removed samples.
+pub fn deduplicate_training_data(dir: &str) -> io::Result<usize> {
+    let mut paths: Vec<_> = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    paths.retain(|path| {
+        path.extension()
+            .is_some_and(|extension| extension == "json")
+    });
+    paths.sort();
+
+    let mut seen_hashes = HashSet::new();
+    let mut n_removed = 0;
+    for path in paths {
+        let game_json: Value = match serde_json::from_str(&std::fs::read_to_string(&path)?) {
+            Ok(game_json) => game_json,
+            Err(_) => continue,
+        };
+        let Some(samples) = game_json.as_array() else {
+            continue;
+        };
+
+        let mut kept_samples: Vec<Value> = Vec::with_capacity(samples.len());
+        for sample_json in samples {
+            let hash = TrainingSample::from_json(sample_json)
+                .and_then(|sample| decode_state(&sample))
+                .map(|board| board.hash_stones());
+            let is_kept = match hash {
+                Ok(hash) => seen_hashes.insert(hash),
+                Err(error @ SampleError::VersionMismatch { .. }) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}: {error}", path.display()),
+                    ));
+                }
+                Err(_) => true,
+            };
+            if is_kept {
+                kept_samples.push(sample_json.clone());
+            }
+        }
+
+        if kept_samples.len() == samples.len() {
+            continue;
+        }
+        n_removed += samples.len() - kept_samples.len();
+        if kept_samples.is_empty() {
+            std::fs::remove_file(&path)?;
+        } else {
+            std::fs::write(&path, serde_json::to_string_pretty(&kept_samples).unwrap())?;
+        }
+    }
+
+    Ok(n_removed)
+}
+
+/// Plays a single self-play game with the model at `model_path`, like `self_play` but
+/// without writing it, showing each position followed by the policy of the search as a
+/// heatmap and the value of the chosen action for the player to move, and waiting
+/// `delay_ms` between moves.
+pub fn watch_self_play(
     size: usize,
     n_in_a_row: usize,
-    n_mcts_simulations: usize,
-    new_player: Player,
-) -> Outcome {
-    let old_model = get_torchjit_model("old.pt");
-    let new_model = get_torchjit_model("new.pt");
-    let mut board = Board::new(size, n_in_a_row);
+    simulations: usize,
+    model_path: &str,
+    delay_ms: u64,
+) {
+    let model = get_torchjit_model(model_path);
+    let mut board = Board::new_square(size, n_in_a_row);
+    show(&board);
 
     while !board.is_game_over() {
-        let mut mcts = MCTS::new(&board, n_mcts_simulations);
+        let mut mcts = MCTS::new(&board, simulations);
+        let temperature = tau_schedule(board.move_history().len());
+        let action = mcts.get_best_action_with_temperature(&model, temperature);
 
-        let action = if board.turn == new_player {
-            mcts.get_best_action(&new_model, false)
-        } else {
-            mcts.get_best_action(&old_model, false)
-        };
+        println!(
+            "{}",
+            policy_heatmap(&mcts.get_flat_policy_normalized(), board.rows, board.cols)
+        );
+        // Forced wins are played without searching, so the action may have no value.
+        match mcts.get_action_values().get(&action) {
+            Some(value) => println!(
+                "{:?} plays {} (value {:+.2})",
+                board.turn,
+                board.action_to_algebraic(action),
+                value
+            ),
+            None => println!(
+                "{:?} plays {}",
+                board.turn,
+                board.action_to_algebraic(action)
+            ),
+        }
 
-        board.make_action(action).ok();
+        board
+            .make_action(action)
+            .expect("Searches only return legal actions.");
+        println!("{}", board.display_with_last_move(Some(action)));
+        thread::sleep(Duration::from_millis(delay_ms));
     }
 
-    board.outcome.expect("Game over should have an outcome.")
+    println!("Outcome: {:?}", board.outcome.expect("The game is over."));
 }
 
-pub fn ai_vs_ai(size: usize, n_in_a_row: usize, n_mcts_simulations: usize) {
-    let n_games = 400;
+/// Decodes the position of a self-play sample of a square board, from either its flat
+/// or its history encoding. The samples do not record `n_in_a_row`, so the position
+/// is decoded as if it took a whole row to win.
+fn decode_state(sample: &TrainingSample) -> Result<Board, SampleError> {
+    let size = (sample.policy.len() as f32).sqrt().round() as usize;
+    let n_squares = size * size;
+    if n_squares != sample.policy.len() {
+        return Err(SampleError::Malformed("policy"));
+    }
 
-    let new_wins: Vec<f32> = (0..n_games)
-        .collect::<Vec<usize>>()
-        .par_iter()
-        .map(|i| {
-            let new_player = if i % 2 == 0 {
+    let t = sample.metadata.history_length;
+    let (black_stones, white_stones, turn) = if t == 0 {
+        if sample.state.len() != n_squares + 1 {
+            return Err(SampleError::Malformed("state"));
+        }
+        let stones = &sample.state[..n_squares];
+        (
+            stones.iter().map(|v| (*v == 1.0) as u8 as f32).collect(),
+            stones.iter().map(|v| (*v == -1.0) as u8 as f32).collect(),
+            sample.state[n_squares],
+        )
+    } else {
+        if sample.state.len() != (2 * t + 1) * n_squares {
+            return Err(SampleError::Malformed("state"));
+        }
+        (
+            sample.state[..n_squares].to_vec(),
+            sample.state[t * n_squares..(t + 1) * n_squares].to_vec(),
+            sample.state[2 * t * n_squares],
+        )
+    };
+
+    let to_plane = |values: Vec<f32>| -> Vec<Vec<f32>> {
+        values.chunks(size).map(|row| row.to_vec()).collect()
+    };
+    let planes = vec![
+        to_plane(black_stones),
+        to_plane(white_stones),
+        to_plane(vec![turn; n_squares]),
+    ];
+
+    Board::from_vec(planes, size).map_err(|_| SampleError::Malformed("state"))
+}
+
+/// Draws a flat policy as rows of shades, two characters per square
+/// so that they line up with the squares of the board display.
+fn policy_heatmap(policy: &[f32], rows: usize, cols: usize) -> String {
+    let max_probability = policy.iter().fold(0f32, |m, p| m.max(*p));
+    let mut heatmap = String::new();
+
+    for row in policy.chunks(cols).take(rows) {
+        heatmap.push_str("   ");
+        for probability in row {
+            let shade_index = if max_probability > 0.0 {
+                (probability / max_probability * (HEATMAP_SHADES.len() - 1) as f32).round() as usize
+            } else {
+                0
+            };
+            heatmap.push(HEATMAP_SHADES[shade_index]);
+            heatmap.push(HEATMAP_SHADES[shade_index]);
+        }
+        heatmap.push('\n');
+    }
+
+    heatmap
+}
+
+/// The results of `pit_models`, from the perspective of the new model.
+#[derive(Debug, Clone)]
+pub struct PitResult {
+    pub new_wins: usize,
+    pub old_wins: usize,
+    pub draws: usize,
+    /// The record of each game, with the color the new model played.
+    pub games: Vec<(Player, GameRecord)>,
+}
+
+impl PitResult {
+    pub fn n_games(&self) -> usize {
+        self.new_wins + self.old_wins + self.draws
+    }
+
+    /// The fraction of the games won by the new model, where draws count as losses.
+    pub fn new_win_rate(&self) -> f32 {
+        self.new_wins as f32 / self.n_games().max(1) as f32
+    }
+}
+
+/// Plays `n_games` games between the models at `new_path` and `old_path` on a `size` by
+/// `size` board, in parallel, with the new model playing Black in half of them.
+/// Both models search with `simulations` per move.
+pub fn pit_models(
+    new_path: &str,
+    old_path: &str,
+    n_games: usize,
+    size: usize,
+    n_in_a_row: usize,
+    simulations: usize,
+) -> PitResult {
+    let games: Vec<(Player, GameRecord)> = (0..n_games)
+        .into_par_iter()
+        .map(|game_index| {
+            let new_agent = Box::new(MctsAgent::new(new_path, simulations));
+            let old_agent = Box::new(MctsAgent::new(old_path, simulations));
+            let mut referee = Referee::new(new_agent, old_agent, size, n_in_a_row);
+            let new_player = if game_index % 2 == 0 {
                 Player::Black
             } else {
+                referee.swap_colors();
                 Player::White
             };
-            let outcome = ai_vs_ai_single(size, n_in_a_row, n_mcts_simulations, new_player);
 
-            if let Outcome::Winner(winner) = outcome {
-                if winner == new_player {
-                    1.0
-                } else {
-                    0.0
-                }
-            } else {
-                0.0
-            }
+            let game_record = referee.play().expect("Searches only return legal actions.");
+            (new_player, game_record)
         })
         .collect();
 
-    let n_games_played = new_wins.len(); // Sometimes par_iter gives less than n_games?
-    let new_wins_ratio: f32 = new_wins.iter().sum::<f32>() / n_games_played as f32;
+    let count = |new_score: f32| {
+        games
+            .iter()
+            .filter(|(new_player, game_record)| {
+                game_record.outcome.score_for(*new_player) == new_score
+            })
+            .count()
+    };
+
+    PitResult {
+        new_wins: count(1.0),
+        old_wins: count(-1.0),
+        draws: count(0.0),
+        games,
+    }
+}
+
+/// Rates the model at `new_model_path` against each of the earlier `checkpoint_paths`,
+/// playing `n_games_per_checkpoint` games with alternating colors against each, and
+/// copies it to `best_model_path` if its Elo then exceeds the prior best's.
+/// The ratings are loaded from and saved to `ratings_path`, with models named by path.
+/// Returns whether the new model was promoted.
+pub fn evaluate_checkpoint(
+    config: &Config,
+    new_model_path: &str,
+    checkpoint_paths: &[String],
+    n_games_per_checkpoint: usize,
+    ratings_path: &str,
+    best_model_path: &str,
+) -> io::Result<bool> {
+    let mut rater = EloRater::load(ratings_path)?;
+
+    for checkpoint_path in checkpoint_paths {
+        let mut referee = Referee::new(
+            Box::new(MctsAgent::from_config(new_model_path, config)),
+            Box::new(MctsAgent::from_config(checkpoint_path, config)),
+            config.size,
+            config.n_in_a_row,
+        );
+
+        for game_index in 0..n_games_per_checkpoint {
+            let new_player = if game_index % 2 == 0 {
+                Player::Black
+            } else {
+                Player::White
+            };
+            let outcome = referee
+                .play()
+                .expect("Searches only return legal actions.")
+                .outcome;
+            referee.swap_colors();
+
+            // Elo scores a loss as 0.0 rather than -1.0.
+            let new_score = (outcome.score_for(new_player) as f64 + 1.0) / 2.0;
+            rater.record_game(new_model_path, checkpoint_path, new_score);
+        }
+    }
+
+    println!(
+        "{new_model_path}: Elo {:.0}, best: {:.0}",
+        rater.rating(new_model_path),
+        rater.best_rating().unwrap_or(INITIAL_ELO)
+    );
+
+    let promoted = rater.promote_if_better(new_model_path);
+    if promoted {
+        std::fs::copy(new_model_path, best_model_path)?;
+        println!("Promoted {new_model_path} to {best_model_path}");
+    }
+    rater.save(ratings_path)?;
+
+    Ok(promoted)
+}
+
+pub fn ai_vs_ai(config: &Config) {
+    let pit_result = pit_models(
+        "new.pt",
+        "old.pt",
+        config.n_games,
+        config.size,
+        config.n_in_a_row,
+        config.n_mcts_simulations,
+    );
 
-    // println!("Old wins: {}", old_wins);
-    // println!("New wins: {}", new_wins);
-    // println!("Draws: {}", draws);
-    println!("New wins ratio: {}", new_wins_ratio);
+    for (new_player, game_record) in &pit_result.games {
+        println!(
+            "New model as {:?}: {:?}, moves: {}",
+            new_player,
+            game_record.outcome,
+            Board::moves_to_string(&game_record.moves, config.size)
+        );
+    }
+    println!(
+        "New wins: {}, old wins: {}, draws: {}",
+        pit_result.new_wins, pit_result.old_wins, pit_result.draws
+    );
+    println!("New wins ratio: {}", pit_result.new_win_rate());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_encoding_versions() {
+        let sample_json = |version: u32, history_length: usize| {
+            json!({
+                "state": [0.0],
+                "policy": [1.0],
+                "value": 0.0,
+                "metadata": {
+                    "version": version,
+                    "history_length": history_length,
+                    "canonical": false,
+                    "symmetry": null
+                }
+            })
+        };
+
+        assert!(TrainingSample::from_json(&sample_json(TENSOR_ENCODING_VERSION, 0)).is_ok());
+        assert!(TrainingSample::from_json(&sample_json(HISTORY_ENCODING_VERSION, 2)).is_ok());
+        // The flat encoding of version 1 cannot tell White's stones from the vacant squares.
+        assert!(matches!(
+            TrainingSample::from_json(&sample_json(1, 0)),
+            Err(SampleError::VersionMismatch {
+                found: 1,
+                expected: TENSOR_ENCODING_VERSION
+            })
+        ));
+        assert!(matches!(
+            replay_game_from_json("missing.json", 0),
+            Err(SampleError::Io(_))
+        ));
+    }
 }
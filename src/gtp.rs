@@ -0,0 +1,291 @@
+use std::io::{self, BufRead, Write};
+
+use crate::board::{Action, Board, Outcome, Player};
+use crate::config::Config;
+use crate::mcts::MCTS;
+use crate::utils::get_torchjit_model;
+
+/// GTP column letters, which skip "I" to avoid confusion with "J".
+const GTP_COL_NAMES: &str = "ABCDEFGHJKLMNOPQRSTUVWXYZ";
+
+const KNOWN_COMMANDS: [&str; 11] = [
+    "protocol_version",
+    "name",
+    "version",
+    "known_command",
+    "list_commands",
+    "boardsize",
+    "clear_board",
+    "play",
+    "genmove",
+    "showboard",
+    "final_score",
+];
+
+/// Plays through the Go Text Protocol, reading commands from stdin and writing
+/// responses to stdout, so that GTP controllers such as GoGui or Sabaki can drive the engine.
+///
+/// Vertices use the GTP convention, e.g. "J10", where the column letters skip "I".
+/// As the players must alternate, `play` rejects a stone of the player not to move.
+pub struct GtpServer {
+    board: Board,
+    n_mcts_simulations: usize,
+    model_path: String,
+    /// The model, loaded from `model_path` on the first `genmove`, so that the other
+    /// commands are answered right away.
+    model: Option<tch::CModule>,
+    /// The search parameters, or `None` for those suited to the size of the board.
+    config: Option<Config>,
+}
+
+impl GtpServer {
+    pub fn new(
+        size: usize,
+        n_in_a_row: usize,
+        n_mcts_simulations: usize,
+        model_path: &str,
+    ) -> Self {
+        Self {
+            board: Board::new_square(size, n_in_a_row),
+            n_mcts_simulations,
+            model_path: model_path.to_string(),
+            model: None,
+            config: None,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let mut server = Self::new(
+            config.size,
+            config.n_in_a_row,
+            config.n_mcts_simulations,
+            &config.model_path,
+        );
+        server.config = Some(config.clone());
+        server
+    }
+
+    /// Answers commands until `quit` is received or stdin is closed.
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+        let mut stdout = io::stdout();
+
+        for line in stdin.lock().lines() {
+            let line = line.expect("Failed to read line");
+            // Comments start with "#" and control characters other than tabs are ignored.
+            let line: String = line
+                .split('#')
+                .next()
+                .unwrap_or("")
+                .chars()
+                .filter(|c| !c.is_control() || *c == '\t')
+                .collect();
+
+            let mut words = line.split_whitespace().peekable();
+            let id = match words.peek() {
+                Some(word) if word.parse::<u32>().is_ok() => words.next(),
+                _ => None,
+            };
+            let command = match words.next() {
+                Some(command) => command,
+                None => continue,
+            };
+            let args: Vec<&str> = words.collect();
+
+            let response = self.handle_command(command, &args);
+            let (status, message) = match &response {
+                Ok(message) => ("=", message),
+                Err(message) => ("?", message),
+            };
+            write!(
+                stdout,
+                "{}{} {}\n\n",
+                status,
+                id.unwrap_or(""),
+                message.trim_end()
+            )
+            .expect("Failed to write response");
+            stdout.flush().expect("Failed to flush stdout");
+
+            if command == "quit" {
+                break;
+            }
+        }
+    }
+
+    /// Runs a single command, returning the text of the success or failure response.
+    fn handle_command(&mut self, command: &str, args: &[&str]) -> Result<String, String> {
+        match command {
+            "protocol_version" => Ok(String::from("2")),
+            "name" => Ok(String::from("Gomokrust")),
+            "version" => Ok(String::from(env!("CARGO_PKG_VERSION"))),
+            "known_command" => {
+                let known = args
+                    .first()
+                    .is_some_and(|arg| *arg == "quit" || KNOWN_COMMANDS.contains(arg));
+                Ok(known.to_string())
+            }
+            "list_commands" => Ok(KNOWN_COMMANDS
+                .iter()
+                .chain(["quit"].iter())
+                .copied()
+                .collect::<Vec<&str>>()
+                .join("\n")),
+            "quit" => Ok(String::new()),
+            "boardsize" => {
+                let size: usize = args
+                    .first()
+                    .and_then(|arg| arg.parse().ok())
+                    .ok_or("syntax error")?;
+                if size < self.board.n_in_a_row || size > GTP_COL_NAMES.len() {
+                    return Err(String::from("unacceptable size"));
+                }
+                self.board = Board::new_square(size, self.board.n_in_a_row);
+                Ok(String::new())
+            }
+            "clear_board" => {
+                self.board.reset();
+                Ok(String::new())
+            }
+            "play" => {
+                let (player, vertex) = match args {
+                    [color, vertex, ..] => (parse_color(color)?, *vertex),
+                    _ => return Err(String::from("syntax error")),
+                };
+                let action = self.parse_vertex(vertex)?;
+                if self.board.is_game_over() || player != self.board.turn {
+                    return Err(String::from("illegal move"));
+                }
+                self.board
+                    .make_action(action)
+                    .map_err(|_| String::from("illegal move"))?;
+                Ok(String::new())
+            }
+            "genmove" => {
+                let player = parse_color(args.first().ok_or("syntax error")?)?;
+                if self.board.is_game_over() {
+                    return Ok(String::from("pass"));
+                }
+                if player != self.board.turn {
+                    return Err(String::from("illegal move"));
+                }
+
+                let mut mcts = match &self.config {
+                    Some(config) => {
+                        MCTS::with_config(&self.board, &config.mcts_config(&self.board))
+                    }
+                    None => MCTS::new(&self.board, self.n_mcts_simulations),
+                };
+                let model = self
+                    .model
+                    .get_or_insert_with(|| get_torchjit_model(&self.model_path));
+                let action = mcts.get_best_action_with_temperature(model, 0.0);
+                self.board
+                    .make_action(action)
+                    .expect("The best action should be legal.");
+                Ok(self.action_to_vertex(action))
+            }
+            "showboard" => Ok(format!("\n{}", self.board)),
+            "final_score" => match self.board.outcome {
+                Some(Outcome::Winner(Player::Black)) => Ok(String::from("B+")),
+                Some(Outcome::Winner(Player::White)) => Ok(String::from("W+")),
+                Some(Outcome::Draw) => Ok(String::from("0")),
+                None => Err(String::from("cannot score")),
+            },
+            _ => Err(String::from("unknown command")),
+        }
+    }
+
+    /// Converts a GTP vertex, e.g. "J10", to an Action.
+    fn parse_vertex(&self, vertex: &str) -> Result<Action, String> {
+        let vertex = vertex.to_uppercase();
+        let mut chars = vertex.chars();
+        let col_index = chars
+            .next()
+            .and_then(|letter| GTP_COL_NAMES.find(letter))
+            .ok_or("invalid coordinate")?;
+        let row_number: usize = chars.as_str().parse().map_err(|_| "invalid coordinate")?;
+
+        if col_index >= self.board.cols || row_number == 0 || row_number > self.board.rows {
+            return Err(String::from("invalid coordinate"));
+        }
+
+        // Rows are numbered from the bottom up, while row indices go from the top down.
+        Ok([self.board.rows - row_number, col_index] as Action)
+    }
+
+    /// Converts an Action to a GTP vertex. This is the inverse of `parse_vertex`.
+    fn action_to_vertex(&self, action: Action) -> String {
+        let [row_index, col_index] = action;
+        let letter = GTP_COL_NAMES
+            .chars()
+            .nth(col_index)
+            .expect("Board sizes are limited to the GTP column letters.");
+        format!("{}{}", letter, self.board.rows - row_index)
+    }
+}
+
+fn parse_color(color: &str) -> Result<Player, String> {
+    match color.to_lowercase().as_str() {
+        "b" | "black" => Ok(Player::Black),
+        "w" | "white" => Ok(Player::White),
+        _ => Err(String::from("syntax error")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server whose model is never loaded, as long as it is not asked to `genmove`.
+    fn server(size: usize) -> GtpServer {
+        GtpServer::new(size, 5, 1, "missing.pt")
+    }
+
+    #[test]
+    fn test_vertices() {
+        let server = server(15);
+        assert_eq!(server.parse_vertex("A1"), Ok([14, 0]));
+        assert_eq!(server.parse_vertex("a15"), Ok([0, 0]));
+        // The column letters skip I, so J is the ninth column.
+        assert_eq!(server.parse_vertex("J10"), Ok([5, 8]));
+        assert_eq!(server.action_to_vertex([5, 8]), "J10");
+        for vertex in ["I5", "A0", "A16", "Q1", "", "H"] {
+            assert!(server.parse_vertex(vertex).is_err(), "{vertex}");
+        }
+        for row_index in 0..15 {
+            for col_index in 0..15 {
+                let action = [row_index, col_index];
+                assert_eq!(
+                    server.parse_vertex(&server.action_to_vertex(action)),
+                    Ok(action)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_play() {
+        let mut server = server(9);
+        assert_eq!(
+            server.handle_command("play", &["b", "E5"]),
+            Ok(String::new())
+        );
+        // Black cannot play twice in a row, and White cannot play on an occupied square.
+        assert!(server.handle_command("play", &["b", "D4"]).is_err());
+        assert!(server.handle_command("play", &["w", "E5"]).is_err());
+        assert_eq!(
+            server.handle_command("play", &["w", "D4"]),
+            Ok(String::new())
+        );
+        assert_eq!(server.board.move_history(), [[4, 4], [5, 3]]);
+        assert_eq!(
+            server.handle_command("known_command", &["play"]),
+            Ok(String::from("true"))
+        );
+        assert_eq!(
+            server.handle_command("known_command", &[]),
+            Ok(String::from("false"))
+        );
+    }
+}
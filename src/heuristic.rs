@@ -0,0 +1,347 @@
+use crate::board::{Action, Board, Outcome, Player, SquareState, ThreatsCount};
+use crate::game::Agent;
+use crate::utils::get_random_action;
+
+/// Number of non-critical actions searched at each node of `forced_win_depth`.
+const FORCED_WIN_BRANCHING: usize = 8;
+/// Number of plies searched by `HeuristicAgent::default`.
+const HEURISTIC_AGENT_DEPTH: usize = 4;
+/// Number of non-critical actions searched at each node by `HeuristicAgent::default`.
+const HEURISTIC_AGENT_BRANCHING: usize = 8;
+
+// The scores of the threats in `threat_score`. An open four cannot be stopped, while a
+// closed four only wins if it is not blocked right away.
+const OPEN_FOUR_SCORE: f32 = 100_000.0;
+const CLOSED_FOUR_SCORE: f32 = 10_000.0;
+const OPEN_THREE_SCORE: f32 = 5_000.0;
+const CLOSED_THREE_SCORE: f32 = 500.0;
+const OPEN_TWO_SCORE: f32 = 100.0;
+
+/// Counts the open-`n` patterns of `player`: windows of `n_in_a_row` squares along a
+/// row, column or diagonal holding exactly `n` stones of `player` and `n_in_a_row - n`
+/// vacant squares, whose neighbouring squares on both ends are vacant or off the board.
+///
+/// Each line is scanned once with a sliding window, updating the stone and vacancy
+/// counts as squares enter and leave the window, instead of re-counting every window.
+pub fn open_n_count(board: &Board, player: Player, n: usize) -> usize {
+    if n > board.n_in_a_row {
+        return 0;
+    }
+
+    let window_size = board.n_in_a_row;
+    let mut count = 0;
+
+    for line in board.lines() {
+        let squares: Vec<&SquareState> = line
+            .iter()
+            .map(|action| board.square_state(*action))
+            .collect();
+
+        let mut n_player_stones = 0;
+        let mut n_vacant = 0;
+
+        for (index, square) in squares.iter().enumerate() {
+            // Square entering the window
+            match square {
+                SquareState::Occupied(p) if *p == player => n_player_stones += 1,
+                SquareState::Vacant => n_vacant += 1,
+                _ => (),
+            }
+
+            // Square leaving the window
+            if index >= window_size {
+                match squares[index - window_size] {
+                    SquareState::Occupied(p) if *p == player => n_player_stones -= 1,
+                    SquareState::Vacant => n_vacant -= 1,
+                    _ => (),
+                }
+            }
+
+            if index + 1 < window_size || n_player_stones != n || n_vacant != window_size - n {
+                continue;
+            }
+
+            let start = index + 1 - window_size;
+            let is_open_before = start == 0 || *squares[start - 1] == SquareState::Vacant;
+            let is_open_after =
+                index + 1 == squares.len() || *squares[index + 1] == SquareState::Vacant;
+
+            if is_open_before && is_open_after {
+                count += 1;
+            }
+        }
+    }
+
+    count
+}
+
+/// Scores the position from the perspective of `player`, by weighing the difference
+/// between the open patterns of both players, exponentially in their number of stones.
+/// A won game scores infinity and a lost one negative infinity.
+pub fn evaluate(board: &Board, player: Player) -> f32 {
+    match board.outcome {
+        Some(Outcome::Winner(winner)) if winner == player => return f32::INFINITY,
+        Some(Outcome::Winner(_)) => return f32::NEG_INFINITY,
+        Some(Outcome::Draw) => return 0.0,
+        None => (),
+    }
+
+    (1..board.n_in_a_row)
+        .map(|n| {
+            let difference = open_n_count(board, player, n) as f32
+                - open_n_count(board, player.opposite(), n) as f32;
+            4f32.powi(n as i32) * difference
+        })
+        .sum()
+}
+
+/// Scores the threats of a player with the hand-crafted table of scores above.
+pub fn threat_score(threats: &ThreatsCount) -> f32 {
+    threats.open_four as f32 * OPEN_FOUR_SCORE
+        + threats.closed_four as f32 * CLOSED_FOUR_SCORE
+        + threats.open_three as f32 * OPEN_THREE_SCORE
+        + threats.closed_three as f32 * CLOSED_THREE_SCORE
+        + threats.open_two as f32 * OPEN_TWO_SCORE
+}
+
+/// Scores the position from the perspective of `player`, as the difference between the
+/// `threat_score` of both players. As in `evaluate`, a won game scores infinity and a
+/// lost one negative infinity, and so does a four of the player to move, who completes it.
+pub fn evaluate_threats(board: &Board, player: Player) -> f32 {
+    match board.outcome {
+        Some(Outcome::Winner(winner)) if winner == player => return f32::INFINITY,
+        Some(Outcome::Winner(_)) => return f32::NEG_INFINITY,
+        Some(Outcome::Draw) => return 0.0,
+        None => (),
+    }
+
+    let threats = board.count_threats(player);
+    let opponent_threats = board.count_threats(player.opposite());
+    let (to_move_threats, sign) = if board.turn == player {
+        (threats, 1.0)
+    } else {
+        (opponent_threats, -1.0)
+    };
+    if to_move_threats.open_four + to_move_threats.closed_four > 0 {
+        return sign * f32::INFINITY;
+    }
+
+    threat_score(&threats) - threat_score(&opponent_threats)
+}
+
+/// Plays the best action found by a negamax search with alpha-beta pruning, like
+/// `forced_win_depth`, scoring the positions at the leaves with `evaluate_threats`.
+/// It needs no model, so it can serve as a baseline for the MCTS agents.
+pub struct HeuristicAgent {
+    /// The number of plies searched.
+    pub depth: usize,
+    /// The number of non-critical actions searched at each node.
+    pub branching: usize,
+}
+
+impl Default for HeuristicAgent {
+    fn default() -> Self {
+        Self {
+            depth: HEURISTIC_AGENT_DEPTH,
+            branching: HEURISTIC_AGENT_BRANCHING,
+        }
+    }
+}
+
+impl Agent for HeuristicAgent {
+    fn get_action(&mut self, board: &Board) -> Action {
+        let (_, action) = negamax(
+            board,
+            self.depth.max(1),
+            f32::NEG_INFINITY,
+            f32::INFINITY,
+            self.branching,
+            evaluate_threats,
+        );
+
+        // Every critical action may be forbidden, in which case the game is lost anyway.
+        action.unwrap_or_else(|| get_random_action(board.legal_actions()))
+    }
+
+    fn name(&self) -> String {
+        format!("Heuristic (depth {})", self.depth)
+    }
+}
+
+/// Returns the number of plies of the shortest forced win of `player`
+/// found within `max_depth` plies, or `None` if there is none.
+///
+/// The search is a depth-limited negamax with alpha-beta pruning, which only expands the
+/// `critical_actions_for` the player to move when there are any, and otherwise the
+/// `FORCED_WIN_BRANCHING` most promising actions. Forced wins within 3 plies are exact,
+/// while deeper ones may miss defences outside of the most promising actions.
+pub fn forced_win_depth(board: &Board, player: Player, max_depth: usize) -> Option<usize> {
+    forced_win(board, player, max_depth).map(|(depth, _)| depth)
+}
+
+/// Returns the first action of the shortest forced win of `player` found by
+/// `forced_win_depth`, if `player` is the one to move.
+pub fn forced_win_action(board: &Board, player: Player, max_depth: usize) -> Option<Action> {
+    forced_win(board, player, max_depth).and_then(|(_, action)| action)
+}
+
+fn forced_win(board: &Board, player: Player, max_depth: usize) -> Option<(usize, Option<Action>)> {
+    if board.is_game_over() {
+        return match board.outcome {
+            Some(Outcome::Winner(winner)) if winner == player => Some((0, None)),
+            _ => None,
+        };
+    }
+
+    // Iterative deepening, so that the first win found is the shortest.
+    for depth in 1..=max_depth {
+        let (score, action) = negamax(
+            board,
+            depth,
+            f32::NEG_INFINITY,
+            f32::INFINITY,
+            FORCED_WIN_BRANCHING,
+            evaluate,
+        );
+        let player_score = if board.turn == player { score } else { -score };
+        if player_score == f32::INFINITY {
+            return Some((depth, action));
+        }
+    }
+
+    None
+}
+
+/// Returns the score of the position for the player to move, along with the best action.
+/// Only the critical actions are searched when there are any, and otherwise the `branching`
+/// most promising actions. The positions at the leaves are scored with `evaluate_leaf`.
+fn negamax(
+    board: &Board,
+    depth: usize,
+    mut alpha: f32,
+    beta: f32,
+    branching: usize,
+    evaluate_leaf: fn(&Board, Player) -> f32,
+) -> (f32, Option<Action>) {
+    match board.outcome {
+        // The game can only be won by the player who just moved.
+        Some(Outcome::Winner(_)) => return (f32::NEG_INFINITY, None),
+        Some(Outcome::Draw) => return (0.0, None),
+        None => (),
+    }
+    if depth == 0 {
+        return (evaluate_leaf(board, board.turn), None);
+    }
+
+    let mut actions = board.critical_actions_for(board.turn);
+    if actions.is_empty() {
+        actions = most_promising_actions(board, branching);
+    }
+    // A block may be forbidden, in which case the threat cannot be stopped.
+    actions.retain(|action| !board.is_forbidden(*action));
+
+    let mut best_score = f32::NEG_INFINITY;
+    let mut best_action = None;
+    for action in actions {
+        let child = board
+            .simulate_action(action)
+            .expect("Legal actions should not result in an error.");
+        let score = -negamax(&child, depth - 1, -beta, -alpha, branching, evaluate_leaf).0;
+
+        if best_action.is_none() || score > best_score {
+            best_score = score;
+            best_action = Some(action);
+        }
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
+    }
+
+    (best_score, best_action)
+}
+
+/// Returns up to `k` legal actions next to a stone, ranked by the `evaluate` score of the
+/// player to move after playing them. On an empty board, returns the most central action.
+fn most_promising_actions(board: &Board, k: usize) -> Vec<Action> {
+    if board.num_stones_placed == 0 {
+        return vec![[(board.rows - 1) / 2, (board.cols - 1) / 2]];
+    }
+
+    let mut scored_actions: Vec<(f32, Action)> = board
+        .legal_actions()
+        .iter()
+        .filter(|action| {
+            !board.is_forbidden(**action)
+                && neighbours(board, **action)
+                    .any(|neighbour| *board.square_state(neighbour) != SquareState::Vacant)
+        })
+        .map(|&action| {
+            let child = board
+                .simulate_action(action)
+                .expect("Legal actions should not result in an error.");
+            (evaluate(&child, board.turn), action)
+        })
+        .collect();
+
+    scored_actions.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored_actions
+        .into_iter()
+        .take(k)
+        .map(|(_, action)| action)
+        .collect()
+}
+
+/// Iterates over the squares surrounding `action`, within the board.
+fn neighbours(board: &Board, action: Action) -> impl Iterator<Item = Action> + '_ {
+    let [rows, cols] = [board.rows as i32, board.cols as i32];
+    let [row_index, col_index] = [action[0] as i32, action[1] as i32];
+
+    (-1..=1)
+        .flat_map(move |row_offset| (-1..=1).map(move |col_offset| (row_offset, col_offset)))
+        .filter(|offsets| *offsets != (0, 0))
+        .map(move |(row_offset, col_offset)| [row_index + row_offset, col_index + col_offset])
+        .filter(move |[r, c]| (0..rows).contains(r) && (0..cols).contains(c))
+        .map(|[r, c]| [r as usize, c as usize] as Action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heuristic_agent() {
+        let mut agent = HeuristicAgent::default();
+
+        // Black completes its four.
+        let board = Board::new_from_position(
+            9,
+            5,
+            &[[4, 2], [4, 3], [4, 4], [4, 5]],
+            &[[0, 0], [0, 2], [0, 4], [8, 8]],
+        )
+        .unwrap();
+        let action = agent.get_action(&board);
+        assert!(action == [4, 1] || action == [4, 6], "{:?}", action);
+
+        // White blocks the closed four of Black.
+        let board = Board::new_from_position(
+            9,
+            5,
+            &[[4, 2], [4, 3], [4, 4], [4, 5]],
+            &[[4, 1], [0, 0], [0, 8]],
+        )
+        .unwrap();
+        assert_eq!(agent.get_action(&board), [4, 6]);
+
+        // White blocks the open three of Black before it becomes an open four.
+        let board =
+            Board::new_from_position(9, 5, &[[4, 3], [4, 4], [4, 5]], &[[0, 0], [0, 8]]).unwrap();
+        let action = agent.get_action(&board);
+        assert!(
+            [[4, 1], [4, 2], [4, 6], [4, 7]].contains(&action),
+            "{:?}",
+            action
+        );
+    }
+}
@@ -1,22 +1,153 @@
 mod board;
+mod config;
 mod game;
+mod gtp;
+mod heuristic;
 mod mcts;
+mod model_server;
+mod opening_book;
+mod rating;
+mod render;
+mod renju;
+mod sgf;
+mod storage;
+mod tournament;
 mod utils;
 
+use clap::{Parser, Subcommand};
 use rand::prelude::*;
 use rand_distr::Dirichlet;
 
+use std::io;
+use std::process;
 use std::time::Instant;
 
+/// Command-line options, which take precedence over the configuration file.
+#[derive(Parser, Debug)]
+#[command(about = "A Gomoku engine trained by self-play")]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The TOML file of the hyperparameters, see `config.toml`.
+    #[arg(long, global = true, default_value = "config.toml")]
+    config: String,
+
+    /// The number of rows and columns of the board.
+    #[arg(long, global = true)]
+    size: Option<usize>,
+
+    /// The number of stones in a row needed to win.
+    #[arg(long, global = true)]
+    n_in_a_row: Option<usize>,
+
+    /// The number of MCTS simulations per move.
+    #[arg(long, global = true)]
+    simulations: Option<usize>,
+
+    /// The TorchScript model used by the search.
+    #[arg(long, global = true)]
+    model_path: Option<String>,
+
+    /// The number of games to play.
+    #[arg(long, global = true)]
+    games: Option<usize>,
+
+    /// Search for this many milliseconds per move against a human,
+    /// instead of a fixed number of simulations.
+    #[arg(long, global = true)]
+    time_limit_ms: Option<u64>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Two humans play against each other at the terminal.
+    PlayHuman,
+    /// A human plays White against the engine.
+    PlayAi,
+    /// The engine plays against itself to generate training games (the default).
+    SelfPlay,
+    /// "new.pt" plays against "old.pt", reporting the win rate of "new.pt".
+    AiVsAi,
+    /// Measures the speed of random games and of the search.
+    Benchmark,
+    /// Shows a single self-play game move by move, with the policy of each search.
+    WatchSelfPlay {
+        /// The delay between moves.
+        #[arg(long, default_value_t = 500)]
+        delay_ms: u64,
+    },
+}
+
+impl Args {
+    /// Overrides the values of `config` with those given on the command line.
+    fn apply_to(&self, config: &mut config::Config) {
+        if let Some(size) = self.size {
+            config.size = size;
+        }
+        if let Some(n_in_a_row) = self.n_in_a_row {
+            config.n_in_a_row = n_in_a_row;
+        }
+        if let Some(simulations) = self.simulations {
+            config.n_mcts_simulations = simulations;
+        }
+        if let Some(model_path) = &self.model_path {
+            config.model_path = model_path.clone();
+        }
+        if let Some(games) = self.games {
+            config.n_games = games;
+        }
+        if self.time_limit_ms.is_some() {
+            config.time_limit_ms = self.time_limit_ms;
+        }
+    }
+}
+
 fn main() {
+    let args = Args::parse();
+    let mut config = match config::Config::from_file(&args.config) {
+        Ok(config) => config,
+        Err(config::ConfigError::Io(error)) if error.kind() == io::ErrorKind::NotFound => {
+            println!(
+                "Using the default configuration, as {} was not found",
+                args.config
+            );
+            config::Config::default()
+        }
+        Err(error) => {
+            eprintln!("Cannot use {}: {error}", args.config);
+            process::exit(1);
+        }
+    };
+    args.apply_to(&mut config);
+    if let Err(error) = config.validate() {
+        eprintln!("{error}");
+        process::exit(1);
+    }
+
+    match args.command.unwrap_or(Command::SelfPlay) {
+        Command::PlayHuman => game::play_game_human_vs_human(config.size, config.n_in_a_row),
+        Command::PlayAi => game::play_game_against_mcts(&config),
+        Command::SelfPlay => game::self_play(config.n_games, &config.self_play_config()),
+        Command::AiVsAi => game::ai_vs_ai(&config),
+        Command::Benchmark => {
+            game::benchmark();
+            mcts::benchmark();
+        }
+        Command::WatchSelfPlay { delay_ms } => game::watch_self_play(
+            config.size,
+            config.n_in_a_row,
+            config.n_mcts_simulations,
+            &config.model_path,
+            delay_ms,
+        ),
+    }
+
     // game::play_random_game();
-    // game::benchmark();
-    // // game::play_game();
     // mcts::test_mcts_black_wins();
     // mcts::test_basics();
     // mcts::test_mcts_white_wins();
-    // mcts::benchmark();
-    // game::play_game_against_mcts();
+    // mcts::benchmark_parallel();
 
     // println!("Random vs MCTS");
     // game::random_against_mcts();
@@ -25,16 +156,17 @@ fn main() {
 
     // println!("Random vs Random");
     // game::random_against_random();
+    // game::compute_win_rates_by_opening(1_000, 7, 4);
+    // game::compute_draw_rate_vs_board_size(5, 15, 5, 1_000);
 
-    // game::self_play_single_game(5, 5, 400);
-    // let now = Instant::now();
-    game::self_play(5_000);
+    // game::evaluate_checkpoint(&config, "new.pt", &["old.pt".to_string()], 20, "ratings.json", "best.pt")
+    //     .expect("Should be able to rate the checkpoint");
 
-    // game::ai_vs_ai(8, 5, 100);
+    // gtp::GtpServer::from_config(&config).run();
 
     // let elapsed = now.elapsed().as_secs_f32();
     // println!("TOTAL {}s", elapsed);
-    // let board = board::Board::new(10, 5);
+    // let board = board::Board::new_square(10, 5);
     // let model = utils::get_torchjit_model();
     // utils::get_torchjit_policy_value(&model, &board.to_flat_tensor());
 
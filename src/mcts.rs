@@ -1,28 +1,123 @@
+use std::collections::HashMap;
 use std::iter::zip;
-use std::time::Instant;
+use std::ops::{Index, IndexMut, Range};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use indexmap::IndexSet;
 use tch;
 
 use rand::prelude::*;
 use rand_distr::Dirichlet;
+use serde_json::{json, Value};
 
-use crate::board::{show, Action, Board, Outcome, Player};
+use crate::board::{show, Action, Board, Outcome, Player, RulesVariant, SizeClass};
+use crate::heuristic::forced_win_action;
+use crate::model_server::ModelClient;
+use crate::opening_book::OpeningBook;
 use crate::utils::{
-    get_random_action, get_torchjit_model, get_torchjit_policy_value, sample_from_weights,
+    get_random_action, get_random_action_with_rng, get_torchjit_model, get_torchjit_policy_value,
+    get_torchjit_policy_value_batch, sample_from_weights_with_rng, top_k_actions,
 };
 
 const SQRT_TWO: f32 = 1.41421356237;
 const C_BASE: f32 = 19652.0;
 const C_INIT: f32 = 1.25;
-const DIRICHLET_ALPHA: f32 = 0.3;
+/// Numerator of the Dirichlet alpha scheduled by `dirichlet_alpha`, as in AlphaZero.
+const DIRICHLET_ALPHA_SCALE: f32 = 10.0;
+const MIN_DIRICHLET_ALPHA: f32 = 0.03;
+const MAX_DIRICHLET_ALPHA: f32 = 1.0;
 const DIRICHLET_EPSILON: f32 = 0.25;
+const VIRTUAL_LOSS: f32 = 1.0;
+const FORCED_WIN_SEARCH_DEPTH: usize = 3;
+const N_EXPLORATORY_MOVES: usize = 8;
+const FINAL_TEMPERATURE: f32 = 0.1;
+const DOT_TOP_K_CHILDREN: usize = 5;
+const MCTS_BATCH_SIZE: usize = 8;
+const N_ITERATIONS: usize = 400;
+/// The distance to the center over which the priors of `center_policies` fall by a factor e.
+const CENTER_PRIOR_SCALE: f32 = 2.0;
+/// Number of iterations between checks of the clock in `MCTS::get_best_action_timed`.
+const TIME_CHECK_INTERVAL: usize = 100;
 
+#[derive(Debug, Clone)]
+pub struct MctsConfig {
+    pub c_base: f32,
+    pub c_init: f32,
+    /// The concentration of the Dirichlet noise, or `None` to schedule it from the
+    /// number of children of the root with `dirichlet_alpha`.
+    pub dirichlet_alpha: Option<f32>,
+    /// The weight of the Dirichlet noise in the priors of the root.
+    pub dirichlet_epsilon: f32,
+    /// The temperature of the softmax of the policy logits of the model.
+    pub policy_temperature: f32,
+    /// The number of children added to each expanded node, those with the highest priors,
+    /// or `None` for a child per legal action.
+    pub max_children: Option<usize>,
+    /// The number of iterations of each search.
+    pub n_iterations: usize,
+    /// The number of leaves evaluated together by `get_best_action_with_temperature`,
+    /// at least 1.
+    pub batch_size: usize,
+    /// Whether the tree is kept whole after `get_best_action_with_temperature`, e.g. to
+    /// `advance_root` to another action than the one returned. Otherwise, the subtrees of
+    /// the other actions are pruned to free their memory.
+    pub tree_reuse: bool,
+    /// Moves played right away, without searching, in the positions they cover.
+    pub opening_book: Option<Arc<OpeningBook>>,
+}
+
+impl MctsConfig {
+    /// Picks the search parameters suited to the size of `board`.
+    /// Larger boards have more legal actions to explore, so the exploration term is made
+    /// larger. The Dirichlet alpha is left to `dirichlet_alpha` at each search.
+    pub fn default_for_board(board: &Board) -> Self {
+        let c_init = match board.size_class() {
+            SizeClass::Small => 1.0,
+            SizeClass::Medium => C_INIT,
+            SizeClass::Large | SizeClass::VeryLarge => 1.5,
+        };
+
+        Self {
+            c_base: C_BASE,
+            c_init,
+            dirichlet_alpha: None,
+            dirichlet_epsilon: DIRICHLET_EPSILON,
+            policy_temperature: 1.0,
+            max_children: None,
+            n_iterations: N_ITERATIONS,
+            batch_size: MCTS_BATCH_SIZE,
+            tree_reuse: true,
+            opening_book: None,
+        }
+    }
+}
+
+/// Returns the temperature to pick the action of move `move_number` with, starting from 0.
+/// The first `N_EXPLORATORY_MOVES` moves are sampled proportionally to the visit counts
+/// for varied openings, and the following ones almost always play the most visited action.
+pub fn tau_schedule(move_number: usize) -> f32 {
+    if move_number < N_EXPLORATORY_MOVES {
+        1.0
+    } else {
+        FINAL_TEMPERATURE
+    }
+}
+
+/// Plays random actions that are not forbidden until the game is over, and returns the outcome.
 pub fn rollout(board: &mut Board) -> Outcome {
+    if board.rules != RulesVariant::Standard {
+        return rollout_with_depth(board, usize::MAX, &mut thread_rng())
+            .expect("A rollout without a maximum depth should end the game.");
+    }
+
     while !board.is_game_over() {
         let random_action = get_random_action(&board.legal_actions());
-        board.make_action(random_action).expect(
-            "Randomly selected action from the legal actions should not result in an error.",
-        );
+        // SAFETY: The action is one of the legal actions, and the standard rules forbid none.
+        unsafe { board.make_action_unchecked(random_action) };
     }
 
     board
@@ -30,10 +125,79 @@ pub fn rollout(board: &mut Board) -> Outcome {
         .expect("The game is over and should have an outcome.")
 }
 
-#[derive(Debug)]
+/// Plays at most `max_depth` random actions that are not forbidden, and returns
+/// the outcome if the game is over by then.
+fn rollout_with_depth(board: &mut Board, max_depth: usize, rng: &mut impl Rng) -> Option<Outcome> {
+    for _ in 0..max_depth {
+        if board.is_game_over() {
+            break;
+        }
+
+        let actions: IndexSet<Action> = board
+            .legal_actions()
+            .iter()
+            .copied()
+            .filter(|&action| !board.is_forbidden(action))
+            .collect();
+        // Every remaining action is forbidden, so the game cannot go on.
+        if actions.is_empty() {
+            board.outcome = Some(Outcome::Draw);
+            return board.outcome;
+        }
+        board
+            .make_action(get_random_action_with_rng(&actions, rng))
+            .expect("Random actions are legal and not forbidden.");
+    }
+
+    board.outcome
+}
+
+/// A fixed-size cache of the network evaluations, indexed by the Zobrist hash of the
+/// evaluated position. Positions reached through different move orders share an entry.
+/// A colliding insertion replaces the previous entry.
+pub struct TranspositionTable {
+    entries: Vec<Option<(u64, Vec<f32>, f32)>>,
+    pub n_hits: usize,
+}
+
+impl TranspositionTable {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: vec![None; capacity.max(1)],
+            n_hits: 0,
+        }
+    }
+
+    /// Returns the cached flat policy and value of the position with `hash`, if any,
+    /// counting a hit.
+    pub fn get(&mut self, hash: u64) -> Option<(&[f32], f32)> {
+        if self.peek(hash).is_some() {
+            self.n_hits += 1;
+        }
+        self.peek(hash)
+    }
+
+    /// Same as `get`, without counting a hit, e.g. to inspect the table after a search.
+    pub fn peek(&self, hash: u64) -> Option<(&[f32], f32)> {
+        let index = (hash % self.entries.len() as u64) as usize;
+        match &self.entries[index] {
+            Some((entry_hash, policies, value)) if *entry_hash == hash => Some((policies, *value)),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, hash: u64, policies: Vec<f32>, value: f32) {
+        let index = (hash % self.entries.len() as u64) as usize;
+        self.entries[index] = Some((hash, policies, value));
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Node {
     action: Option<Action>,
-    children: Vec<Node>,
+    /// The indices of the children in the `NodeArena`, which are added all at once
+    /// and are therefore contiguous.
+    children: Range<usize>,
     total_value: f32,
     prior: f32,
     visit_count: usize,
@@ -46,7 +210,7 @@ impl Node {
             action,
             turn,
             prior,
-            children: Vec::new(),
+            children: 0..0,
             total_value: 0.0,
             visit_count: 0,
         }
@@ -60,9 +224,10 @@ impl Node {
         self.total_value / self.visit_count as f32
     }
 
-    pub fn ucb(&self, parent_visit_count: usize) -> f32 {
+    pub fn ucb(&self, parent_visit_count: usize, config: &MctsConfig) -> f32 {
         let Q_s = self.value();
-        let C_s = f32::log10((1.0 + parent_visit_count as f32 + C_BASE) / C_BASE) + C_INIT;
+        let C_s = f32::log10((1.0 + parent_visit_count as f32 + config.c_base) / config.c_base)
+            + config.c_init;
         let U_s =
             C_s * self.prior * f32::sqrt(parent_visit_count as f32) / (1 + self.visit_count) as f32;
 
@@ -79,173 +244,1386 @@ impl Node {
         self.visit_count += 1;
     }
 
-    pub fn get_best_child(&mut self) -> Option<&mut Node> {
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Counts a visit that lost, until `revert_virtual_loss` is called.
+    /// This steers the other selections of a batch away from the same path.
+    pub fn add_virtual_loss(&mut self) {
+        self.total_value -= VIRTUAL_LOSS;
+        self.visit_count += 1;
+    }
+
+    pub fn revert_virtual_loss(&mut self) {
+        self.total_value += VIRTUAL_LOSS;
+        self.visit_count -= 1;
+    }
+}
+
+/// The nodes of a search tree, allocated from a single buffer and referring to their
+/// children by index rather than owning them. The root is always at index `ROOT`.
+#[derive(Debug, Clone)]
+pub struct NodeArena {
+    nodes: Vec<Node>,
+}
+
+/// The index of the root in a `NodeArena`.
+pub const ROOT: usize = 0;
+
+impl NodeArena {
+    /// Creates a tree with a single root, reserving room for `capacity` nodes.
+    pub fn new(turn: Player, capacity: usize) -> Self {
+        let mut nodes = Vec::with_capacity(capacity.max(1));
+        nodes.push(Node::new(None, turn, 0.0));
+        Self { nodes }
+    }
+
+    /// Creates a tree for searching `board` with `n_iterations`, reserving room for the
+    /// children of the root and one node per iteration. Reserving for the worst case, a
+    /// child per legal action at each iteration, would take megabytes per search on large
+    /// boards, while the nodes refer to each other by index, so growing the buffer is cheap.
+    pub fn for_search(board: &Board, n_iterations: usize) -> Self {
+        let capacity = 1 + board.legal_actions().len() + n_iterations;
+        Self::new(board.turn, capacity)
+    }
+
+    pub fn root(&self) -> &Node {
+        &self.nodes[ROOT]
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns the number of bytes reserved for the nodes.
+    pub fn memory_usage(&self) -> usize {
+        self.nodes.capacity() * std::mem::size_of::<Node>()
+    }
+
+    /// Returns the children of the node at `index`.
+    pub fn children(&self, index: usize) -> &[Node] {
+        &self.nodes[self.nodes[index].children.clone()]
+    }
+
+    pub fn children_mut(&mut self, index: usize) -> &mut [Node] {
+        let children = self.nodes[index].children.clone();
+        &mut self.nodes[children]
+    }
+
+    /// Appends `children` to the arena as the children of the leaf at `index`.
+    fn set_children(&mut self, index: usize, children: impl IntoIterator<Item = Node>) {
+        debug_assert!(self.nodes[index].is_leaf(), "Only leaves can be expanded.");
+        let first_child_index = self.nodes.len();
+        self.nodes.extend(children);
+        self.nodes[index].children = first_child_index..self.nodes.len();
+    }
+
+    /// Returns the index of the child of the node at `index` with the highest UCB score.
+    pub fn get_best_child(&self, index: usize, config: &MctsConfig) -> Option<usize> {
+        let parent = &self.nodes[index];
         let mut best_score: f32 = f32::NEG_INFINITY;
-        let mut best_child: Option<&mut Node> = None;
+        let mut best_child_index: Option<usize> = None;
 
-        for child in &mut self.children {
-            let child_score = child.ucb(self.visit_count);
+        for child_index in parent.children.clone() {
+            let child_score = self.nodes[child_index].ucb(parent.visit_count, config);
             if child_score > best_score {
                 best_score = child_score;
-                best_child = Some(child);
+                best_child_index = Some(child_index);
             }
         }
 
-        best_child
+        best_child_index
     }
 
-    pub fn is_leaf(&self) -> bool {
-        self.children.is_empty()
+    /// Returns the number of nodes in the subtree rooted at `index`, including itself.
+    pub fn subtree_size(&self, index: usize) -> usize {
+        1 + self.nodes[index]
+            .children
+            .clone()
+            .map(|child_index| self.subtree_size(child_index))
+            .sum::<usize>()
+    }
+
+    /// Returns the number of edges on the longest path from the node at `index` to a leaf.
+    pub fn depth(&self, index: usize) -> usize {
+        self.nodes[index]
+            .children
+            .clone()
+            .map(|child_index| 1 + self.depth(child_index))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the mean depth below the root at which the iterations stopped, i.e. of the
+    /// leaves they expanded or the finished games they reached. Every visit of a node that
+    /// did not go on to one of its children stopped at it, so the depths are weighted by
+    /// the visit count of each node minus those of its children.
+    pub fn average_leaf_depth(&self) -> f64 {
+        let mut weighted_depth_sum = 0.0;
+        let mut n_iterations = 0;
+        let mut stack = vec![(ROOT, 0)];
+        while let Some((index, depth)) = stack.pop() {
+            let node = &self.nodes[index];
+            let children_visit_count: usize = self
+                .children(index)
+                .iter()
+                .map(|child| child.visit_count)
+                .sum();
+            let n_stopped = node.visit_count.saturating_sub(children_visit_count);
+            weighted_depth_sum += (depth * n_stopped) as f64;
+            n_iterations += n_stopped;
+            stack.extend(
+                node.children
+                    .clone()
+                    .map(|child_index| (child_index, depth + 1)),
+            );
+        }
+
+        if n_iterations == 0 {
+            return 0.0;
+        }
+        weighted_depth_sum / n_iterations as f64
+    }
+
+    /// Copies the subtree rooted at `index` into a new arena, where it is the root.
+    /// Nodes are copied breadth first, so the children of each node stay contiguous.
+    pub fn subtree(&self, index: usize) -> Self {
+        let mut nodes = Vec::with_capacity(self.subtree_size(index));
+        nodes.push(self.nodes[index].clone());
+
+        let mut next_index = 0;
+        while next_index < nodes.len() {
+            let children = nodes[next_index].children.clone();
+            let first_child_index = nodes.len();
+            nodes.extend_from_slice(&self.nodes[children]);
+            nodes[next_index].children = first_child_index..nodes.len();
+            next_index += 1;
+        }
+
+        Self { nodes }
+    }
+
+    /// Drops the subtrees below the children of the root, except the one below the child
+    /// of `keep_action`. The children themselves are kept, so that the policy of the root
+    /// can still be read, and the search can go on from the root or from the kept child.
+    pub fn prune_all_but(&mut self, keep_action: Action) {
+        let mut nodes = Vec::with_capacity(self.nodes.len());
+        nodes.push(self.nodes[ROOT].clone());
+
+        let mut next_index = 0;
+        while next_index < nodes.len() {
+            let children = nodes[next_index].children.clone();
+            let first_child_index = nodes.len();
+            nodes.extend_from_slice(&self.nodes[children]);
+            if next_index == ROOT {
+                for child in &mut nodes[first_child_index..] {
+                    if child.action != Some(keep_action) {
+                        child.children = 0..0;
+                    }
+                }
+            }
+            nodes[next_index].children = first_child_index..nodes.len();
+            next_index += 1;
+        }
+
+        self.nodes = nodes;
+    }
+}
+
+impl Index<usize> for NodeArena {
+    type Output = Node;
+
+    fn index(&self, index: usize) -> &Node {
+        &self.nodes[index]
+    }
+}
+
+impl IndexMut<usize> for NodeArena {
+    fn index_mut(&mut self, index: usize) -> &mut Node {
+        &mut self.nodes[index]
     }
 }
 
 pub struct MCTS {
-    pub root: Node,
+    pub tree: NodeArena,
     pub board: Board,
-    pub n_iterations: usize,
+    pub config: MctsConfig,
+    pub transposition_table: TranspositionTable,
+    /// The maximum number of random actions of each rollout of `get_best_action_with_rollouts`,
+    /// or `None` to play the rollouts out until the game is over.
+    pub rollout_depth: Option<usize>,
+    /// Whether to print the depth of the tree after each search.
+    pub verbose: bool,
+    /// The source of the exploration noise, the sampled actions and the rollouts,
+    /// seeded by `new_seeded` for reproducible searches.
+    rng: StdRng,
+}
+
+/// A leaf selected by `MCTS::select_and_store_leaf`, awaiting its evaluation.
+struct PendingLeaf {
+    /// The indices of the nodes from the root to the leaf, which all have virtual loss applied.
+    path: Vec<usize>,
+    /// The position of the leaf.
+    board: Board,
+}
+
+enum LeafSelection {
+    /// The leaf was backpropagated right away.
+    Completed,
+    /// The leaf needs to be evaluated by the model.
+    Pending(PendingLeaf),
+    /// The leaf is already awaiting evaluation.
+    AlreadyPending,
 }
 
 impl MCTS {
+    /// Creates a search of `n_iterations` with the default parameters for `board`,
+    /// see `MctsConfig::default_for_board`.
     pub fn new(board: &Board, n_iterations: usize) -> Self {
-        let root = Node::new(None, board.turn, 0.0);
-        let board = board.clone();
-        Self {
-            root,
+        Self::with_config(
             board,
-            n_iterations,
+            &MctsConfig {
+                n_iterations,
+                ..MctsConfig::default_for_board(board)
+            },
+        )
+    }
+
+    /// Creates a search with every parameter taken from `config`, e.g. from
+    /// `Config::mcts_config` to change them without recompiling.
+    pub fn with_config(board: &Board, config: &MctsConfig) -> Self {
+        Self {
+            tree: NodeArena::for_search(board, config.n_iterations),
+            board: board.clone(),
+            // An empty batch would never complete any iteration.
+            config: MctsConfig {
+                batch_size: config.batch_size.max(1),
+                ..config.clone()
+            },
+            transposition_table: TranspositionTable::new(config.n_iterations + 1),
+            rollout_depth: None,
+            verbose: false,
+            rng: StdRng::from_entropy(),
         }
     }
 
-    pub fn iteration(&mut self, board: &mut Board, model: &tch::CModule) {
-        let mut parents_pointers: Vec<*mut Node> = Vec::new();
+    /// Same as `new`, with every random choice of the search drawn from a generator seeded
+    /// by `seed`, so that searches with the same inputs return the same actions.
+    /// A `seed` of 0 seeds the generator from the system's entropy instead, like `new`.
+    pub fn new_seeded(board: &Board, n_iterations: usize, seed: u64) -> Self {
+        let rng = if seed == 0 {
+            StdRng::from_entropy()
+        } else {
+            StdRng::seed_from_u64(seed)
+        };
 
-        // Selection
-        let mut node = &mut self.root;
-        parents_pointers.push(node);
+        Self {
+            rng,
+            ..Self::new(board, n_iterations)
+        }
+    }
 
-        while !node.is_leaf() {
-            node = node.get_best_child().unwrap();
-            board.make_action(node.action.unwrap()).ok();
-            parents_pointers.push(node);
+    pub fn with_opening_book(mut self, opening_book: Arc<OpeningBook>) -> Self {
+        self.config.opening_book = Some(opening_book);
+        self
+    }
+
+    /// Overrides the exploration parameters of `config` used by `Node::ucb`,
+    /// e.g. to tune them without recompiling. The defaults are `C_INIT` and `C_BASE`,
+    /// adjusted to the size of the board as in `MctsConfig::default_for_board`.
+    pub fn set_exploration_constant(&mut self, c_init: f32, c_base: f32) -> &mut Self {
+        self.config.c_init = c_init;
+        self.config.c_base = c_base;
+        self
+    }
+
+    /// Creates a search that needs no neural network, for `get_best_action_with_rollouts`.
+    /// Leaves are expanded with priors favoring the center of the board, and valued by a
+    /// random playout of at most `rollout_depth` actions, where a playout that does not
+    /// finish the game counts as a draw.
+    pub fn new_rollout(board: &Board, n_iterations: usize, rollout_depth: usize) -> Self {
+        Self {
+            rollout_depth: Some(rollout_depth),
+            ..Self::new(board, n_iterations)
         }
+    }
+
+    /// Runs a single selection, expansion and backpropagation from `board`,
+    /// which must be the position of the root. The actions made on `board` during
+    /// selection are undone before returning, so the same board can be reused.
+    pub fn iteration(&mut self, board: &mut Board, model: &tch::CModule) {
+        // Selection
+        let (path, selected_actions) = self.select(board);
 
         // Expansion
-        let value = expand(&mut node, board, &model);
+        let value = expand(
+            &mut self.tree,
+            *path.last().unwrap(),
+            board,
+            &model,
+            &mut self.transposition_table,
+            self.config.policy_temperature,
+            self.config.max_children,
+        );
 
         // Backpropagate
-        for parent_pointer in parents_pointers.iter().rev() {
-            let parent = unsafe { parent_pointer.as_mut().unwrap() };
-            parent.update(value);
+        for &node_index in path.iter().rev() {
+            self.tree[node_index].update(value);
+        }
+
+        for action in selected_actions.into_iter().rev() {
+            board
+                .undo_action(action)
+                .expect("Actions are undone in the reverse order they were made.");
         }
     }
 
-    pub fn get_best_action(&mut self, model: &tch::CModule, exploratory_play: bool) -> Action {
-        let _ = expand(&mut self.root, &mut self.board.clone(), &model);
-        inject_exploration_noise(&mut self.root);
+    /// Same as `iteration`, with the leaf valued by a random playout instead of the model.
+    fn rollout_iteration(&mut self, board: &mut Board) {
+        // Selection
+        let (path, selected_actions) = self.select(board);
+
+        // Expansion and simulation
+        let value = match board.game_result_for(Player::Black) {
+            Some(value) => value,
+            None => {
+                add_children(
+                    &mut self.tree,
+                    *path.last().unwrap(),
+                    board,
+                    &center_policies(board),
+                    self.config.max_children,
+                );
+                let outcome = rollout_with_depth(
+                    &mut board.clone(),
+                    self.rollout_depth.unwrap_or(usize::MAX),
+                    &mut self.rng,
+                );
+                outcome.map_or(0.0, outcome_value)
+            }
+        };
 
-        for i in 0..self.n_iterations {
-            let mut board = self.board.clone();
-            self.iteration(&mut board, &model);
+        // Backpropagate
+        for &node_index in path.iter().rev() {
+            self.tree[node_index].update(value);
         }
 
-        let action = if exploratory_play {
-            // Sample
-            let children_probabilities = self
-                .root
-                .children
-                .iter()
-                .map(|c| c.visit_count as f32 / self.root.visit_count as f32)
-                .collect();
+        for action in selected_actions.into_iter().rev() {
+            board
+                .undo_action(action)
+                .expect("Actions are undone in the reverse order they were made.");
+        }
+    }
 
-            let child_index = sample_from_weights(&children_probabilities);
-            let chosen_child = &self.root.children[child_index];
-            chosen_child.action.expect("Child should have an action")
-        } else {
-            // Deterministic
-            let mut chosen_child = &self.root.children[0];
-            for child in &self.root.children {
-                // println!(
-                //     "{:?} -> {} {} {}",
-                //     child.action.unwrap(),
-                //     child.visit_count,
-                //     child.total_value,
-                //     child.ucb(self.root.visit_count),
-                // );
-                if child.visit_count > chosen_child.visit_count {
-                    chosen_child = child;
-                }
+    /// Follows the children with the highest UCB score from the root down to a leaf,
+    /// making their actions on `board`. Returns the indices of the nodes from the root
+    /// to the leaf, along with the actions made.
+    fn select(&self, board: &mut Board) -> (Vec<usize>, Vec<Action>) {
+        let mut path = vec![ROOT];
+        let mut selected_actions = Vec::new();
+
+        while let Some(child_index) = self
+            .tree
+            .get_best_child(*path.last().unwrap(), &self.config)
+        {
+            let action = self.tree[child_index].action.unwrap();
+            // SAFETY: Children are expanded from the legal actions of a position that is
+            // not over, and the selection replays them from the position they were expanded on.
+            unsafe { board.make_action_and_check_unchecked(action) };
+            selected_actions.push(action);
+            path.push(child_index);
+        }
+
+        (path, selected_actions)
+    }
+
+    /// Runs up to `batch_size` iterations, evaluating all of their leaves with a single
+    /// forward pass of the model, and returns the number of iterations completed.
+    ///
+    /// Virtual loss is applied along each selected path so that the following selections
+    /// spread over different leaves. Selecting a leaf that is already awaiting evaluation
+    /// ends the batch early. Leaves where the game is over are backpropagated right away.
+    pub fn run_iterations_batch(&mut self, model: &tch::CModule, batch_size: usize) -> usize {
+        let mut pending_leaves: Vec<PendingLeaf> = Vec::with_capacity(batch_size);
+        let mut n_completed = 0;
+
+        for _ in 0..batch_size {
+            match self.select_and_store_leaf(&pending_leaves) {
+                LeafSelection::Completed => n_completed += 1,
+                LeafSelection::Pending(leaf) => pending_leaves.push(leaf),
+                LeafSelection::AlreadyPending => break,
             }
-            chosen_child.action.expect("Child should have an action")
-        };
+        }
+
+        if pending_leaves.is_empty() {
+            return n_completed;
+        }
+
+        // Evaluation
+        let board_tensors: Vec<tch::Tensor> = pending_leaves
+            .iter()
+            .map(|leaf| leaf.board.to_flat_tensor())
+            .collect();
+        let legal_indices: Vec<Vec<usize>> = pending_leaves
+            .iter()
+            .map(|leaf| legal_flat_indices(&leaf.board))
+            .collect();
+        let policies_values = get_torchjit_policy_value_batch(
+            model,
+            &board_tensors,
+            &legal_indices,
+            self.config.policy_temperature,
+        );
+
+        n_completed += pending_leaves.len();
+        let evaluated_leaves = zip(pending_leaves, policies_values)
+            .map(|(leaf, (policies, value))| (leaf, value, policies))
+            .collect();
+        self.evaluate_and_backpropagate(evaluated_leaves);
+
+        n_completed
+    }
+
+    /// Selects a leaf from the root, applying virtual loss along its path, without
+    /// calling the model. Leaves where the game is over, or whose evaluation is in the
+    /// transposition table, are expanded and backpropagated right away.
+    ///
+    /// If the selected leaf is already among `pending_leaves`, the virtual loss
+    /// is reverted and nothing is stored.
+    fn select_and_store_leaf(&mut self, pending_leaves: &[PendingLeaf]) -> LeafSelection {
+        let mut board = self.board.clone();
+
+        // Selection
+        let (path, _) = select_with_virtual_loss(&mut self.tree, &mut board, &self.config);
+        let leaf_index = *path.last().unwrap();
+
+        if let Some(value) = board.game_result_for(Player::Black) {
+            backpropagate_with_virtual_loss(&mut self.tree, &path, value);
+            LeafSelection::Completed
+        } else if let Some((policies, value)) = self.transposition_table.get(board.hash()) {
+            add_children(
+                &mut self.tree,
+                leaf_index,
+                &board,
+                policies,
+                self.config.max_children,
+            );
+            backpropagate_with_virtual_loss(&mut self.tree, &path, value);
+            LeafSelection::Completed
+        } else if pending_leaves
+            .iter()
+            .any(|leaf| leaf.path.last() == Some(&leaf_index))
+        {
+            for &node_index in &path {
+                self.tree[node_index].revert_virtual_loss();
+            }
+            LeafSelection::AlreadyPending
+        } else {
+            LeafSelection::Pending(PendingLeaf { path, board })
+        }
+    }
+
+    /// Expands each leaf with its policy and backpropagates its value,
+    /// removing the virtual loss applied by `select_and_store_leaf`.
+    fn evaluate_and_backpropagate(&mut self, evaluated_leaves: Vec<(PendingLeaf, f32, Vec<f32>)>) {
+        for (leaf, value, policies) in evaluated_leaves {
+            add_children(
+                &mut self.tree,
+                *leaf.path.last().unwrap(),
+                &leaf.board,
+                &policies,
+                self.config.max_children,
+            );
+            backpropagate_with_virtual_loss(&mut self.tree, &leaf.path, value);
+            self.transposition_table
+                .insert(leaf.board.hash(), policies, value);
+        }
+    }
+
+    /// Searches from the root and returns the action to play, sampled with probability
+    /// proportional to `visit_count^(1 / temperature)`.
+    /// A temperature of 1.0 samples proportionally to the visit counts, and a temperature
+    /// of 0.0 deterministically plays the most visited action, or a forced win if there is one.
+    pub fn get_best_action_with_temperature(
+        &mut self,
+        model: &tch::CModule,
+        temperature: f32,
+    ) -> Action {
+        if let Some(action) = self.prepare_search(model, temperature) {
+            return action;
+        }
+
+        let mut n_completed = 0;
+        while n_completed < self.config.n_iterations {
+            let batch_size = self
+                .config
+                .batch_size
+                .min(self.config.n_iterations - n_completed);
+            n_completed += self.run_iterations_batch(&model, batch_size);
+        }
+        if self.verbose {
+            self.print_search_depth();
+        }
 
-        // println!("ROOT STATS:");
-        // println!("{} {}", self.root.visit_count, self.root.total_value);
+        let action = choose_action(&self.tree, temperature, &mut self.rng);
+        if !self.config.tree_reuse {
+            self.tree.prune_all_but(action);
+        }
 
         action
     }
 
+    /// Same as `get_best_action_with_temperature` with a temperature of 0.0, searching
+    /// until `time_limit_ms` milliseconds have passed instead of for `n_iterations`.
+    /// The clock is checked every `TIME_CHECK_INTERVAL` iterations, so the search
+    /// may run a little over the limit.
+    pub fn get_best_action_timed(&mut self, model: &tch::CModule, time_limit_ms: u64) -> Action {
+        let now = Instant::now();
+        let time_limit = Duration::from_millis(time_limit_ms);
+        if let Some(action) = self.prepare_search(model, 0.0) {
+            return action;
+        }
+
+        loop {
+            let mut n_completed = 0;
+            while n_completed < TIME_CHECK_INTERVAL {
+                let batch_size = self
+                    .config
+                    .batch_size
+                    .min(TIME_CHECK_INTERVAL - n_completed);
+                n_completed += self.run_iterations_batch(&model, batch_size);
+            }
+
+            if now.elapsed() >= time_limit {
+                break;
+            }
+        }
+        if self.verbose {
+            self.print_search_depth();
+        }
+
+        choose_action(&self.tree, 0.0, &mut self.rng)
+    }
+
+    /// Expands the root and adds exploration noise to it, unless the action to play
+    /// is known without searching, i.e. from the opening book or a forced win.
+    fn prepare_search(&mut self, model: &tch::CModule, temperature: f32) -> Option<Action> {
+        if let Some(action) = self.book_action() {
+            return Some(action);
+        }
+
+        if let Some(action) = decided_action(&self.board, temperature) {
+            return Some(action);
+        }
+
+        // The root is already expanded when it was kept by `advance_root`.
+        if self.tree.root().is_leaf() {
+            let _ = expand(
+                &mut self.tree,
+                ROOT,
+                &mut self.board.clone(),
+                &model,
+                &mut self.transposition_table,
+                self.config.policy_temperature,
+                self.config.max_children,
+            );
+        }
+        inject_exploration_noise(
+            &mut self.tree,
+            self.config.dirichlet_alpha,
+            self.config.dirichlet_epsilon,
+            &mut self.rng,
+        );
+
+        None
+    }
+
+    /// Same as `get_best_action_with_temperature`, valuing the leaves with random playouts
+    /// of at most `rollout_depth` actions instead of a neural network.
+    pub fn get_best_action_with_rollouts(&mut self, temperature: f32) -> Action {
+        if let Some(action) = self.book_action() {
+            return action;
+        }
+
+        if let Some(action) = decided_action(&self.board, temperature) {
+            return action;
+        }
+
+        if self.tree.root().is_leaf() && !self.board.is_game_over() {
+            add_children(
+                &mut self.tree,
+                ROOT,
+                &self.board,
+                &center_policies(&self.board),
+                self.config.max_children,
+            );
+        }
+        inject_exploration_noise(
+            &mut self.tree,
+            self.config.dirichlet_alpha,
+            self.config.dirichlet_epsilon,
+            &mut self.rng,
+        );
+
+        let mut board = self.board.clone();
+        for _ in 0..self.config.n_iterations {
+            self.rollout_iteration(&mut board);
+        }
+
+        choose_action(&self.tree, temperature, &mut self.rng)
+    }
+
+    /// Returns the mean depth at which the iterations of the search stopped,
+    /// as in `NodeArena::average_leaf_depth`. A high average depth with few iterations
+    /// may mean that the priors are too concentrated for the search to explore.
+    pub fn average_search_depth(&self) -> f64 {
+        self.tree.average_leaf_depth()
+    }
+
+    fn print_search_depth(&self) {
+        println!(
+            "Search depth: {} max, {:.2} average",
+            self.tree.depth(ROOT),
+            self.average_search_depth()
+        );
+    }
+
+    /// Returns the move of the opening book for the position of the root, if any.
+    fn book_action(&self) -> Option<Action> {
+        self.config
+            .opening_book
+            .as_ref()
+            .and_then(|opening_book| opening_book.get_book_action(&self.board))
+    }
+
+    #[deprecated(
+        note = "Use `get_best_action_with_temperature` with a temperature of 1.0 for exploratory play, and 0.0 otherwise."
+    )]
+    pub fn get_best_action(&mut self, model: &tch::CModule, exploratory_play: bool) -> Action {
+        let temperature = if exploratory_play { 1.0 } else { 0.0 };
+        self.get_best_action_with_temperature(model, temperature)
+    }
+
+    /// Returns the line of play the search considers best, by following the most
+    /// visited child from the root until a leaf or a child that was never visited.
+    pub fn get_principal_variation(&self) -> Vec<Action> {
+        let mut principal_variation = Vec::new();
+
+        let mut node_index = ROOT;
+        while let Some(child_index) = self.tree[node_index]
+            .children
+            .clone()
+            .max_by_key(|&child_index| self.tree[child_index].visit_count)
+        {
+            let child = &self.tree[child_index];
+            if child.visit_count == 0 {
+                break;
+            }
+
+            principal_variation.push(child.action.expect("Child nodes should have an action."));
+            node_index = child_index;
+        }
+
+        principal_variation
+    }
+
+    /// Returns the mean value of each child of the root, from the perspective
+    /// of the player to move at the root.
+    pub fn get_action_values(&self) -> HashMap<Action, f32> {
+        self.tree
+            .children(ROOT)
+            .iter()
+            .map(|child| {
+                let action = child.action.expect("Child nodes should have an action.");
+                (action, child.value())
+            })
+            .collect()
+    }
+
+    /// Returns the number of visits of each child of the root.
+    pub fn get_action_visit_counts(&self) -> HashMap<Action, usize> {
+        self.tree
+            .children(ROOT)
+            .iter()
+            .map(|child| {
+                let action = child.action.expect("Child nodes should have an action.");
+                (action, child.visit_count)
+            })
+            .collect()
+    }
+
+    /// Returns the search tree as a Graphviz DOT graph, down to `max_depth` levels below
+    /// the root and only following the `DOT_TOP_K_CHILDREN` most visited children of
+    /// each node. Nodes are labelled with their action, visit count, mean value and UCB
+    /// score, and edges are drawn thicker for the more visited children.
+    pub fn export_tree_dot(&self, max_depth: usize) -> String {
+        let mut dot = String::from("digraph MCTS {\n    node [shape=box];\n");
+        dot.push_str(&format!(
+            "    n0 [label=\"root\\nN={}\\nQ={:.3}\"];\n",
+            self.tree.root().visit_count,
+            self.tree.root().value()
+        ));
+
+        let mut n_nodes = 1;
+        self.write_dot_children(ROOT, 0, max_depth, &mut n_nodes, &mut dot);
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Returns the statistics of every child of the root as a JSON array, from the most
+    /// to the least visited, e.g. to find out why a move was played. Values are from the
+    /// perspective of the player to move at the root, as in `get_action_values`.
+    pub fn export_statistics_json(&self) -> String {
+        let root_visit_count = self.tree.root().visit_count;
+        let mut children: Vec<&Node> = self.tree.children(ROOT).iter().collect();
+        children.sort_by(|a, b| b.visit_count.cmp(&a.visit_count));
+
+        let statistics: Vec<Value> = children
+            .into_iter()
+            .map(|child| {
+                let action = child.action.expect("Child nodes should have an action.");
+                json!({
+                    "action": self.board.action_to_algebraic(action),
+                    "visit_count": child.visit_count,
+                    "total_value": child.total_value,
+                    "mean_value": child.value(),
+                    "prior": child.prior,
+                    "ucb": child.ucb(root_visit_count, &self.config),
+                })
+            })
+            .collect();
+
+        serde_json::to_string_pretty(&statistics).expect("The statistics are plain numbers.")
+    }
+
+    fn write_dot_children(
+        &self,
+        parent_index: usize,
+        parent_id: usize,
+        depth_left: usize,
+        n_nodes: &mut usize,
+        dot: &mut String,
+    ) {
+        if depth_left == 0 {
+            return;
+        }
+
+        let parent = &self.tree[parent_index];
+        let mut children_indices: Vec<usize> = parent.children.clone().collect();
+        children_indices.sort_by(|&a, &b| self.tree[b].visit_count.cmp(&self.tree[a].visit_count));
+
+        for child_index in children_indices.into_iter().take(DOT_TOP_K_CHILDREN) {
+            let child = &self.tree[child_index];
+            let child_id = *n_nodes;
+            *n_nodes += 1;
+
+            let action = child.action.expect("Child nodes should have an action.");
+            dot.push_str(&format!(
+                "    n{} [label=\"{}\\nN={}\\nQ={:.3}\\nUCB={:.3}\"];\n",
+                child_id,
+                self.board.action_to_algebraic(action),
+                child.visit_count,
+                child.value(),
+                child.ucb(parent.visit_count, &self.config),
+            ));
+
+            let visit_ratio = child.visit_count as f32 / parent.visit_count.max(1) as f32;
+            dot.push_str(&format!(
+                "    n{} -> n{} [penwidth={:.2}];\n",
+                parent_id,
+                child_id,
+                1.0 + 4.0 * visit_ratio
+            ));
+
+            self.write_dot_children(child_index, child_id, depth_left - 1, n_nodes, dot);
+        }
+    }
+
+    /// Makes `action` on `self.board` and promotes the corresponding child to root,
+    /// keeping the simulations already spent on its subtree and discarding its siblings.
+    ///
+    /// Returns `Err` if `action` is not among the children of the root, e.g. when it was
+    /// never explored, in which case the caller should build a new MCTS instead.
+    pub fn advance_root(&mut self, action: Action) -> Result<(), ()> {
+        let child_index = self.tree[ROOT]
+            .children
+            .clone()
+            .find(|&child_index| self.tree[child_index].action == Some(action))
+            .ok_or(())?;
+
+        self.board.make_action(action).map_err(|_| ())?;
+        self.tree = self.tree.subtree(child_index);
+
+        Ok(())
+    }
+
+    /// Moves the root to the position of `board`, to reuse the tree across moves.
+    /// If `board` follows the root by one action, e.g. the move of the opponent, the
+    /// child of that action is promoted to root as in `advance_root`. Otherwise, or if
+    /// the action was never explored, the search starts over from a new root.
+    pub fn set_root_from_board(&mut self, board: &Board) {
+        let root_history = self.board.move_history();
+        let history = board.move_history();
+        if history.len() == root_history.len() + 1 && history.starts_with(root_history) {
+            let action = *history.last().unwrap();
+            if self.advance_root(action).is_ok() {
+                return;
+            }
+        }
+
+        self.board = board.clone();
+        self.tree = NodeArena::for_search(board, self.config.n_iterations);
+    }
+
+    /// Estimates the memory used by the nodes of the tree, unlike `NodeArena::memory_usage`
+    /// which counts the memory reserved for them.
+    pub fn memory_usage_bytes(&self) -> usize {
+        self.tree.subtree_size(ROOT) * std::mem::size_of::<Node>()
+    }
+
+    /// Returns the number of visits of the root, including those of a tree kept
+    /// by `advance_root`.
+    pub fn root_visit_count(&self) -> usize {
+        self.tree.root().visit_count
+    }
+
+    /// Returns the number of visits of the children of the root, which is
+    /// `n_iterations` on a new tree and more on a tree kept by `advance_root`.
+    fn children_visit_count(&self) -> usize {
+        self.tree.children(ROOT).iter().map(|c| c.visit_count).sum()
+    }
+
     pub fn get_policy(&self) -> Vec<Vec<f32>> {
-        let mut policy = vec![vec![0f32; self.board.size]; self.board.size];
+        let mut policy = vec![vec![0f32; self.board.cols]; self.board.rows];
 
-        for child in &self.root.children {
+        let children_visit_count = self.children_visit_count();
+        for child in self.tree.children(ROOT) {
             let [row_index, col_index] = child.action.expect("Child nodes should have an action.");
-            let p = child.visit_count as f32 / self.n_iterations as f32;
+            let p = child.visit_count as f32 / children_visit_count as f32;
             policy[row_index][col_index] = p;
         }
 
         policy
     }
 
+    #[deprecated(note = "Use `get_flat_policy_normalized`.")]
     pub fn get_flat_policy(&self) -> Vec<f32> {
-        let mut flat_policy = vec![0f32; self.board.size * self.board.size];
+        self.get_flat_policy_normalized()
+    }
 
-        for child in &self.root.children {
+    /// Returns the visit counts of the children of the root as a flat probability
+    /// distribution over the squares. The counts are divided by their sum rather than
+    /// by `n_iterations` or the visits of the root, which also count the expansion of the
+    /// root and the iterations of a tree kept by `advance_root`.
+    pub fn get_flat_policy_normalized(&self) -> Vec<f32> {
+        let mut flat_policy = vec![0f32; self.board.rows * self.board.cols];
+
+        let children_visit_count = self.children_visit_count();
+        for child in self.tree.children(ROOT) {
             let [row_index, col_index] = child.action.expect("Child nodes should have an action.");
-            let p = child.visit_count as f32 / self.n_iterations as f32;
-            flat_policy[row_index * self.board.size + col_index] = p;
+            let p = child.visit_count as f32 / children_visit_count as f32;
+            flat_policy[self.board.action_to_flat_index(&[row_index, col_index])] = p;
+        }
+
+        debug_assert!(
+            children_visit_count == 0 || (flat_policy.iter().sum::<f32>() - 1.0).abs() < 1e-3,
+            "The policy should sum to 1."
+        );
+
+        flat_policy
+    }
+}
+
+/// A search tree shared by `n_threads` workers, which select leaves concurrently and
+/// have them evaluated together, in batches of up to `n_threads` positions.
+///
+/// Workers apply virtual loss along the path they select, so that the other workers
+/// are steered towards different leaves while the evaluation is pending.
+/// The model is only used from the calling thread, which batches the positions
+/// sent by the workers.
+pub struct ParallelMCTS {
+    pub tree: Arc<Mutex<NodeArena>>,
+    pub board: Board,
+    pub n_iterations: usize,
+    pub n_threads: usize,
+    pub config: MctsConfig,
+}
+
+/// A message from a worker to the thread running the model.
+enum EvaluationRequest {
+    /// Evaluate the flat tensor of a leaf, with the flat indices of its legal actions,
+    /// and send back its policy and value.
+    Evaluate(tch::Tensor, Vec<usize>, Sender<(Vec<f32>, f32)>),
+    /// The worker has no iterations left to run.
+    Done,
+}
+
+impl ParallelMCTS {
+    pub fn new(board: &Board, n_iterations: usize, n_threads: usize) -> Self {
+        Self {
+            tree: Arc::new(Mutex::new(NodeArena::for_search(board, n_iterations))),
+            board: board.clone(),
+            n_iterations,
+            n_threads: n_threads.max(1),
+            config: MctsConfig::default_for_board(board),
+        }
+    }
+
+    /// Same as `MCTS::get_best_action_with_temperature`, with the iterations
+    /// spread over `n_threads` workers.
+    pub fn get_best_action_with_temperature(
+        &mut self,
+        model: &tch::CModule,
+        temperature: f32,
+    ) -> Action {
+        if let Some(action) = decided_action(&self.board, temperature) {
+            return action;
+        }
+
+        self.prepare_root(|board_tensor, legal_indices| {
+            get_torchjit_policy_value(
+                model,
+                &board_tensor,
+                &legal_indices,
+                self.config.policy_temperature,
+            )
+        });
+
+        let this = &*self;
+        let n_started = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            let (request_sender, request_receiver) = mpsc::channel();
+            for _ in 0..this.n_threads {
+                let request_sender: Sender<EvaluationRequest> = request_sender.clone();
+                let n_started = &n_started;
+                scope.spawn(move || {
+                    let (response_sender, response_receiver) = mpsc::channel();
+                    this.run_worker(n_started, |board_tensor, legal_indices| {
+                        request_sender
+                            .send(EvaluationRequest::Evaluate(
+                                board_tensor,
+                                legal_indices,
+                                response_sender.clone(),
+                            ))
+                            .expect("The evaluations are served until every worker is done.");
+                        response_receiver
+                            .recv()
+                            .expect("Every evaluation request is answered.")
+                    });
+                    request_sender
+                        .send(EvaluationRequest::Done)
+                        .expect("The evaluations are served until every worker is done.");
+                });
+            }
+            drop(request_sender);
+
+            serve_evaluations(
+                model,
+                request_receiver,
+                this.n_threads,
+                this.config.policy_temperature,
+            );
+        });
+
+        let tree = self.tree.lock().unwrap();
+        choose_action(&tree, temperature, &mut rand::thread_rng())
+    }
+
+    /// Same as `get_best_action_with_temperature`, with the leaves evaluated by the
+    /// `ModelServer` of `client`, which may be shared with other searches.
+    /// The policy temperature of the server is used instead of the one of `config`.
+    pub fn get_best_action_from_server(
+        &mut self,
+        client: &ModelClient,
+        temperature: f32,
+    ) -> Action {
+        if let Some(action) = decided_action(&self.board, temperature) {
+            return action;
+        }
+
+        self.prepare_root(|board_tensor, legal_indices| {
+            client.evaluate(board_tensor, legal_indices)
+        });
+
+        let this = &*self;
+        let n_started = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..this.n_threads {
+                let n_started = &n_started;
+                scope.spawn(move || {
+                    this.run_worker(n_started, |board_tensor, legal_indices| {
+                        client.evaluate(board_tensor, legal_indices)
+                    })
+                });
+            }
+        });
+
+        let tree = self.tree.lock().unwrap();
+        choose_action(&tree, temperature, &mut rand::thread_rng())
+    }
+
+    /// Expands the root with the policy returned by `evaluate` if needed,
+    /// then adds exploration noise to the priors of its children.
+    fn prepare_root(&self, evaluate: impl FnOnce(tch::Tensor, Vec<usize>) -> (Vec<f32>, f32)) {
+        let mut tree = self.tree.lock().unwrap();
+        if tree.root().is_leaf() && !self.board.is_game_over() {
+            let (policies, _) =
+                evaluate(self.board.to_flat_tensor(), legal_flat_indices(&self.board));
+            add_children(
+                &mut tree,
+                ROOT,
+                &self.board,
+                &policies,
+                self.config.max_children,
+            );
+        }
+        inject_exploration_noise(
+            &mut tree,
+            self.config.dirichlet_alpha,
+            self.config.dirichlet_epsilon,
+            &mut rand::thread_rng(),
+        );
+    }
+
+    /// Runs iterations until `n_iterations` have been started across all workers.
+    /// Leaves where the game is not over are evaluated with `evaluate`, which is given
+    /// their flat tensor and the flat indices of their legal actions, before expanding
+    /// and backpropagating.
+    fn run_worker(
+        &self,
+        n_started: &AtomicUsize,
+        evaluate: impl Fn(tch::Tensor, Vec<usize>) -> (Vec<f32>, f32),
+    ) {
+        let mut board = self.board.clone();
+
+        while n_started.fetch_add(1, Ordering::SeqCst) < self.n_iterations {
+            let (path, selected_actions) = {
+                let mut tree = self.tree.lock().unwrap();
+                select_with_virtual_loss(&mut tree, &mut board, &self.config)
+            };
+
+            let value = match board.game_result_for(Player::Black) {
+                Some(value) => value,
+                None => {
+                    let (policies, value) =
+                        evaluate(board.to_flat_tensor(), legal_flat_indices(&board));
+
+                    let mut tree = self.tree.lock().unwrap();
+                    let leaf_index = *path.last().unwrap();
+                    // Another worker may have expanded the same leaf in the meantime.
+                    if tree[leaf_index].is_leaf() {
+                        add_children(
+                            &mut tree,
+                            leaf_index,
+                            &board,
+                            &policies,
+                            self.config.max_children,
+                        );
+                    }
+                    value
+                }
+            };
+
+            {
+                let mut tree = self.tree.lock().unwrap();
+                backpropagate_with_virtual_loss(&mut tree, &path, value);
+            }
+
+            for action in selected_actions.into_iter().rev() {
+                board
+                    .undo_action(action)
+                    .expect("Actions are undone in the reverse order they were made.");
+            }
+        }
+    }
+
+    /// Same as `MCTS::root_visit_count`.
+    pub fn root_visit_count(&self) -> usize {
+        self.tree.lock().unwrap().root().visit_count
+    }
+
+    pub fn get_flat_policy(&self) -> Vec<f32> {
+        let tree = self.tree.lock().unwrap();
+        let mut flat_policy = vec![0f32; self.board.rows * self.board.cols];
+
+        let children_visit_count: usize = tree.children(ROOT).iter().map(|c| c.visit_count).sum();
+        for child in tree.children(ROOT) {
+            let action = child.action.expect("Child nodes should have an action.");
+            let p = child.visit_count as f32 / children_visit_count as f32;
+            flat_policy[self.board.action_to_flat_index(&action)] = p;
         }
 
         flat_policy
     }
 }
 
-pub fn expand(node: &mut Node, board: &mut Board, model: &tch::CModule) -> f32 {
-    let value = if !board.is_game_over() {
-        let (policies, value) = get_torchjit_policy_value(&model, &board.to_flat_tensor());
-        let legal_actions = board.legal_actions();
-        for &action in legal_actions {
-            let prior = policies[board.action_to_flat_index(&action)];
-            let child = Node::new(Some(action), node.turn.opposite(), prior);
-            node.children.push(child);
+/// Evaluates the leaves sent by `n_workers` workers until they are all done.
+/// A batch is evaluated once every worker that is still running is waiting on it.
+fn serve_evaluations(
+    model: &tch::CModule,
+    requests: Receiver<EvaluationRequest>,
+    n_workers: usize,
+    policy_temperature: f32,
+) {
+    let mut n_running = n_workers;
+    let mut batch: Vec<(tch::Tensor, Vec<usize>, Sender<(Vec<f32>, f32)>)> =
+        Vec::with_capacity(n_workers);
+
+    while n_running > 0 {
+        match requests
+            .recv()
+            .expect("Workers send Done before hanging up.")
+        {
+            EvaluationRequest::Evaluate(board_tensor, legal_indices, response_sender) => {
+                batch.push((board_tensor, legal_indices, response_sender))
+            }
+            EvaluationRequest::Done => n_running -= 1,
+        }
+
+        if !batch.is_empty() && batch.len() == n_running {
+            let mut board_tensors = Vec::with_capacity(batch.len());
+            let mut legal_indices = Vec::with_capacity(batch.len());
+            let mut response_senders = Vec::with_capacity(batch.len());
+            for (board_tensor, indices, response_sender) in batch.drain(..) {
+                board_tensors.push(board_tensor);
+                legal_indices.push(indices);
+                response_senders.push(response_sender);
+            }
+            let policies_values = get_torchjit_policy_value_batch(
+                model,
+                &board_tensors,
+                &legal_indices,
+                policy_temperature,
+            );
+            for (response_sender, policy_value) in zip(response_senders, policies_values) {
+                response_sender
+                    .send(policy_value)
+                    .expect("The worker waits for its evaluation.");
+            }
         }
+    }
+}
+
+/// Selects a leaf from the root of `tree`, adding virtual loss to every node along the way
+/// and making the selected actions on `board`. Returns the indices of the nodes from the
+/// root to the leaf, along with the corresponding actions.
+fn select_with_virtual_loss(
+    tree: &mut NodeArena,
+    board: &mut Board,
+    config: &MctsConfig,
+) -> (Vec<usize>, Vec<Action>) {
+    let mut path = vec![ROOT];
+    let mut selected_actions = Vec::new();
+
+    tree[ROOT].add_virtual_loss();
+    while let Some(child_index) = tree.get_best_child(*path.last().unwrap(), config) {
+        let action = tree[child_index].action.unwrap();
+        // SAFETY: Same as in `MCTS::select`.
+        unsafe { board.make_action_and_check_unchecked(action) };
+        tree[child_index].add_virtual_loss();
+        path.push(child_index);
+        selected_actions.push(action);
+    }
+
+    (path, selected_actions)
+}
+
+/// Picks the action of a child of the root of the searched `tree`, sampled with probability
+/// proportional to `visit_count^(1 / temperature)`, or the most visited one
+/// if `temperature` is 0.0.
+fn choose_action(tree: &NodeArena, temperature: f32, rng: &mut impl Rng) -> Action {
+    let children = tree.children(ROOT);
+    let chosen_child = if temperature == 0.0 {
+        let mut chosen_child = &children[0];
+        for child in children {
+            if child.visit_count > chosen_child.visit_count {
+                chosen_child = child;
+            }
+        }
+        chosen_child
+    } else {
+        // Normalize by the largest visit count first, so that small temperatures
+        // do not overflow.
+        let max_visit_count = children
+            .iter()
+            .map(|c| c.visit_count)
+            .max()
+            .expect("The root should have children.") as f32;
+        let children_weights: Vec<f32> = children
+            .iter()
+            .map(|c| (c.visit_count as f32 / max_visit_count).powf(1.0 / temperature))
+            .collect();
+
+        &children[sample_from_weights_with_rng(&children_weights, rng)]
+    };
+
+    chosen_child.action.expect("Child should have an action")
+}
+
+/// Adds the children of the node at `node_index` and returns the value of `board`, reusing
+/// the network evaluation cached in `transposition_table` when the position has been seen before.
+pub fn expand(
+    tree: &mut NodeArena,
+    node_index: usize,
+    board: &mut Board,
+    model: &tch::CModule,
+    transposition_table: &mut TranspositionTable,
+    policy_temperature: f32,
+    max_children: Option<usize>,
+) -> f32 {
+    if let Some(value) = board.game_result_for(Player::Black) {
+        return value;
+    }
+
+    if let Some((policies, value)) = transposition_table.get(board.hash()) {
+        add_children(tree, node_index, board, policies, max_children);
         value
     } else {
-        match board.outcome.expect("Just checked is_some().") {
-            Outcome::Winner(Player::Black) => 1.0,
-            Outcome::Winner(Player::White) => -1.0,
-            Outcome::Draw => 0.0,
+        let (policies, value) = get_torchjit_policy_value(
+            &model,
+            &board.to_flat_tensor(),
+            &legal_flat_indices(board),
+            policy_temperature,
+        );
+        add_children(tree, node_index, board, &policies, max_children);
+        transposition_table.insert(board.hash(), policies, value);
+        value
+    }
+}
+
+/// Adds a child to the node at `node_index` for every legal action of `board` that is
+/// not forbidden, with its prior taken from the flat `policies`, ordered by descending
+/// prior so that ties in `get_best_child`, e.g. between unvisited children, go to the
+/// children the model prefers. With `max_children`, only the children with the highest
+/// priors are added (progressive widening), focusing the search on the promising actions.
+pub fn add_children(
+    tree: &mut NodeArena,
+    node_index: usize,
+    board: &Board,
+    policies: &[f32],
+    max_children: Option<usize>,
+) {
+    let child_turn = tree[node_index].turn.opposite();
+    let mut actions: IndexSet<Action> = IndexSet::with_capacity(board.legal_actions().len());
+    let mut priors: Vec<f32> = Vec::with_capacity(board.legal_actions().len());
+    for (&action, &flat_index) in zip(board.legal_actions(), board.legal_actions_as_flat_indices())
+    {
+        if !board.is_forbidden(action) {
+            actions.insert(action);
+            priors.push(policies[flat_index]);
         }
-    };
+    }
+
+    let children = top_k_actions(&priors, max_children.unwrap_or(actions.len()), &actions)
+        .into_iter()
+        .map(|(action, prior)| Node::new(Some(action), child_turn, prior));
+    tree.set_children(node_index, children);
+}
 
-    value
+/// Returns the action to play without searching, i.e. an `immediate_action` or the first
+/// move of a forced win. Play with a temperature keeps searching, so that the resulting
+/// policy can be used as a training target.
+fn decided_action(board: &Board, temperature: f32) -> Option<Action> {
+    if temperature != 0.0 {
+        return None;
+    }
+
+    immediate_action(board)
+        .or_else(|| forced_win_action(board, board.turn, FORCED_WIN_SEARCH_DEPTH))
+}
+
+/// Returns the action to play without searching when the game can be decided on the
+/// next move: an immediate win for the player to move, or else the square blocking the
+/// opponent's.
+fn immediate_action(board: &Board) -> Option<Action> {
+    board
+        .check_immediate_win(board.turn)
+        .or_else(|| board.check_immediate_win(board.turn.opposite()))
+}
+
+/// A flat policy over every legal action that is not forbidden, for searches without a
+/// model. The prior of each action falls with its `Board::distance_to_center`, as moves
+/// near the center are usually stronger.
+fn center_policies(board: &Board) -> Vec<f32> {
+    let mut policies = vec![0f32; board.rows * board.cols];
+    let mut total = 0.0;
+    for (&action, &flat_index) in zip(board.legal_actions(), board.legal_actions_as_flat_indices())
+    {
+        if !board.is_forbidden(action) {
+            let weight = (-board.distance_to_center(action) / CENTER_PRIOR_SCALE).exp();
+            policies[flat_index] = weight;
+            total += weight;
+        }
+    }
+    for prior in &mut policies {
+        *prior /= total;
+    }
+
+    policies
 }
 
-pub fn inject_exploration_noise(root: &mut Node) {
-    if root.children.len() < 2 {
+/// Returns the flat indices of the legal actions of `board` that are not forbidden,
+/// i.e. the actions that `add_children` adds a child for.
+fn legal_flat_indices(board: &Board) -> Vec<usize> {
+    zip(board.legal_actions(), board.legal_actions_as_flat_indices())
+        .filter(|(&action, _)| !board.is_forbidden(action))
+        .map(|(_, &flat_index)| flat_index)
+        .collect()
+}
+
+/// The value of a finished game, from Black's perspective like the neural network.
+pub fn outcome_value(outcome: Outcome) -> f32 {
+    outcome.score_for(Player::Black)
+}
+
+/// Removes the virtual loss from every node along the path of node indices,
+/// then updates it with `value`.
+fn backpropagate_with_virtual_loss(tree: &mut NodeArena, path: &[usize], value: f32) {
+    for &node_index in path.iter().rev() {
+        let node = &mut tree[node_index];
+        node.revert_virtual_loss();
+        node.update(value);
+    }
+}
+
+/// Returns the Dirichlet alpha of the noise spread over `n_legal_moves`, i.e.
+/// `10 / n_legal_moves` as in AlphaZero, clamped to `[0.03, 1.0]`. The more moves,
+/// the sparser the noise, so that it still singles out a few of them.
+pub fn dirichlet_alpha(n_legal_moves: usize) -> f32 {
+    (DIRICHLET_ALPHA_SCALE / n_legal_moves as f32).clamp(MIN_DIRICHLET_ALPHA, MAX_DIRICHLET_ALPHA)
+}
+
+/// Mixes Dirichlet noise into the priors of the children of the root. Without a fixed
+/// `alpha`, it is scheduled from the number of children with `dirichlet_alpha`.
+pub fn inject_exploration_noise(
+    tree: &mut NodeArena,
+    alpha: Option<f32>,
+    dirichlet_epsilon: f32,
+    rng: &mut impl Rng,
+) {
+    let children = tree.children_mut(ROOT);
+    if children.len() < 2 {
         return;
     }
 
-    let dirichlet = Dirichlet::new(&vec![DIRICHLET_ALPHA; root.children.len()]).unwrap();
-    let samples = dirichlet.sample(&mut rand::thread_rng());
+    let alpha = alpha.unwrap_or_else(|| dirichlet_alpha(children.len()));
+    let dirichlet = Dirichlet::new(&vec![alpha; children.len()]).unwrap();
+    let samples = dirichlet.sample(rng);
 
-    for (child, noise) in zip(&mut root.children, samples) {
-        child.prior = (1.0 - DIRICHLET_EPSILON) * child.prior + DIRICHLET_EPSILON * noise;
+    for (child, noise) in zip(children, samples) {
+        child.prior = (1.0 - dirichlet_epsilon) * child.prior + dirichlet_epsilon * noise;
     }
 }
 
 pub fn test_basics() {
     let model = get_torchjit_model("old.pt");
-    let mut board = Board::new(3, 3);
+    let mut board = Board::new_square(3, 3);
 
     for move_string in vec!["B2", "A2", "C3", "A1", "A3", "B3", "C1"].iter() {
         let action = board
@@ -270,18 +1648,13 @@ pub fn test_mcts_black_wins() {
           A B C
     */
     let model = get_torchjit_model("old.pt");
-    let mut board = Board::new(3, 3);
-
-    for move_string in vec!["B2", "A2", "C3", "A1", "A3", "B3"].iter() {
-        let action = board
-            .parse_string_to_action(&String::from(*move_string))
+    let mut board =
+        Board::new_from_position(3, 3, &[[0, 0], [1, 1], [0, 2]], &[[0, 1], [1, 0], [2, 0]])
             .unwrap();
-        board.make_action(action).ok();
-    }
     show(&board);
 
     let mut mcts = MCTS::new(&board, 1_000);
-    let best_action = mcts.get_best_action(&model, false);
+    let best_action = mcts.get_best_action_with_temperature(&model, 0.0);
 
     dbg!(&best_action);
     board.make_action(best_action).ok();
@@ -295,19 +1668,13 @@ pub fn test_mcts_white_wins() {
        1 X O X
          A B C
     */
-    let mut board = Board::new(3, 3);
+    let mut board =
+        Board::new_from_position(3, 3, &[[2, 0], [1, 0], [2, 2]], &[[2, 1], [1, 1]]).unwrap();
     let model = get_torchjit_model("old.pt");
-
-    for move_string in vec!["A1", "B1", "A2", "B2", "C1"].iter() {
-        let action = board
-            .parse_string_to_action(&String::from(*move_string))
-            .unwrap();
-        board.make_action(action).ok();
-    }
     show(&board);
 
     let mut mcts = MCTS::new(&board, 1_000);
-    let best_action = mcts.get_best_action(&model, false);
+    let best_action = mcts.get_best_action_with_temperature(&model, 0.0);
 
     dbg!(&best_action);
     board.make_action(best_action).ok();
@@ -316,12 +1683,12 @@ pub fn test_mcts_white_wins() {
 
 pub fn benchmark() {
     let n_iterations = 400;
-    let board = Board::new(3, 3);
+    let board = Board::new_square(3, 3);
     let model = get_torchjit_model("old.pt");
     let mut mcts = MCTS::new(&board, n_iterations);
     let now = Instant::now();
 
-    mcts.get_best_action(&model, false);
+    mcts.get_best_action_with_temperature(&model, 0.0);
 
     let elapsed_s = now.elapsed().as_secs_f32();
     println!(
@@ -329,4 +1696,223 @@ pub fn benchmark() {
         (n_iterations as f32 / elapsed_s) as usize
     );
     println!("{} seconds per {} iterations", elapsed_s, n_iterations);
+    println!(
+        "Transposition table hits: {}",
+        mcts.transposition_table.n_hits
+    );
+    println!(
+        "Tree nodes: {} ({} KB reserved)",
+        mcts.tree.len(),
+        mcts.tree.memory_usage() / 1024
+    );
+
+    let n_rollouts = 10_000;
+    let board = Board::new_square(15, 5);
+    let now = Instant::now();
+    for _ in 0..n_rollouts {
+        rollout(&mut board.clone());
+    }
+
+    let elapsed_s = now.elapsed().as_secs_f32();
+    println!(
+        "Rollouts per second: {}",
+        (n_rollouts as f32 / elapsed_s) as usize
+    );
+}
+
+pub fn benchmark_parallel() {
+    let n_iterations = 400;
+    let n_threads = 8;
+    let board = Board::new_square(8, 5);
+    let model = get_torchjit_model("old.pt");
+    let mut mcts = ParallelMCTS::new(&board, n_iterations, n_threads);
+    let now = Instant::now();
+
+    mcts.get_best_action_with_temperature(&model, 1.0);
+
+    let elapsed_s = now.elapsed().as_secs_f32();
+    println!(
+        "Iterations per second with {} threads: {}",
+        n_threads,
+        (n_iterations as f32 / elapsed_s) as usize
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune_all_but() {
+        let board = Board::new_square(5, 4);
+        let mut mcts = MCTS::new_rollout(&board, 500, 10);
+        let action = mcts.get_best_action_with_rollouts(0.0);
+        let policy = mcts.get_flat_policy_normalized();
+        let kept_index = mcts
+            .tree
+            .root()
+            .children
+            .clone()
+            .find(|&child_index| mcts.tree[child_index].action == Some(action))
+            .unwrap();
+        let kept_subtree_size = mcts.tree.subtree_size(kept_index);
+        let memory_usage_bytes = mcts.memory_usage_bytes();
+
+        mcts.tree.prune_all_but(action);
+        let n_children = mcts.tree.children(ROOT).len();
+        assert_eq!(mcts.tree.len(), 1 + n_children + kept_subtree_size - 1);
+        assert_eq!(
+            mcts.memory_usage_bytes(),
+            mcts.tree.len() * std::mem::size_of::<Node>()
+        );
+        assert!(mcts.memory_usage_bytes() < memory_usage_bytes);
+        assert_eq!(mcts.get_flat_policy_normalized(), policy);
+        assert!(mcts.advance_root(action).is_ok());
+        assert_eq!(mcts.tree.len(), kept_subtree_size);
+    }
+
+    #[test]
+    fn test_node_arena_capacity() {
+        let board = Board::new_square(15, 5);
+        let tree = NodeArena::for_search(&board, 400);
+        assert_eq!(tree.len(), 1);
+        assert!(!tree.is_empty());
+        assert_eq!(
+            tree.memory_usage(),
+            (1 + 225 + 400) * std::mem::size_of::<Node>()
+        );
+
+        let mut mcts = MCTS::new_rollout(&Board::new_square(5, 4), 300, 10);
+        mcts.get_best_action_with_rollouts(0.0);
+        let action = mcts.tree.children(ROOT)[0].action.unwrap();
+        assert!(mcts.advance_root(action).is_ok());
+        assert_eq!(mcts.tree.memory_usage(), mcts.memory_usage_bytes());
+    }
+
+    #[test]
+    fn test_transposition_table() {
+        let mut transposition_table = TranspositionTable::new(4);
+        transposition_table.insert(5, vec![0.25; 4], 0.5);
+
+        assert_eq!(transposition_table.peek(5), Some((&[0.25; 4][..], 0.5)));
+        assert_eq!(transposition_table.peek(1), None);
+        assert_eq!(transposition_table.n_hits, 0);
+        assert!(transposition_table.get(5).is_some());
+        assert!(transposition_table.get(1).is_none());
+        assert_eq!(transposition_table.n_hits, 1);
+    }
+
+    #[test]
+    fn test_dirichlet_alpha() {
+        assert_eq!(dirichlet_alpha(1), 1.0);
+        assert_eq!(dirichlet_alpha(9), 1.0);
+        assert_eq!(dirichlet_alpha(100), 0.1);
+        assert_eq!(dirichlet_alpha(19 * 19), 0.03);
+        assert!(dirichlet_alpha(225) < dirichlet_alpha(64));
+    }
+
+    #[test]
+    fn test_max_children() {
+        let board = Board::from_actions(5, 5, 4, &[[2, 2], [1, 1]]).unwrap();
+        let mut mcts = MCTS::new_rollout(&board, 200, 10);
+        mcts.config.max_children = Some(3);
+        mcts.get_best_action_with_rollouts(0.0);
+
+        assert_eq!(mcts.tree.children(ROOT).len(), 3);
+        for index in 0..mcts.tree.len() {
+            assert!(mcts.tree.children(index).len() <= 3);
+        }
+    }
+
+    #[test]
+    fn test_rollout_plays_no_forbidden_actions() {
+        let board = Board::new_square(9, 5).with_rules(RulesVariant::Renju);
+        let mut rolled_out_board = board.clone();
+        rollout(&mut rolled_out_board);
+        assert!(rolled_out_board.is_game_over());
+
+        let mut replayed_board = board;
+        for &action in rolled_out_board.move_history() {
+            assert!(!replayed_board.is_forbidden(action));
+            replayed_board.make_action(action).unwrap();
+        }
+    }
+
+    /// Without a model, the priors alone should lead an empty board to be opened at its center.
+    #[test]
+    fn test_rollout_opens_at_center() {
+        let board = Board::new_square(15, 5);
+        let mut mcts = MCTS::new_rollout(&board, 1_000, 20);
+        mcts.config.dirichlet_epsilon = 0.0;
+        let action = mcts.get_best_action_with_rollouts(0.0);
+
+        let highest_prior_child = mcts
+            .tree
+            .children(ROOT)
+            .iter()
+            .max_by(|a, b| a.prior.total_cmp(&b.prior))
+            .unwrap();
+        assert_eq!(highest_prior_child.action, Some([7, 7]));
+        // The playouts are random, so a neighbor of the center may end up visited most.
+        assert!(board.distance_to_center(action) <= SQRT_TWO);
+    }
+
+    #[test]
+    fn test_export_statistics_json() {
+        let board = Board::from_actions(5, 5, 4, &[[2, 2], [1, 1]]).unwrap();
+        let mut mcts = MCTS::new_rollout(&board, 200, 10);
+        mcts.get_best_action_with_rollouts(0.0);
+
+        let statistics: Vec<Value> =
+            serde_json::from_str(&mcts.export_statistics_json()).expect("Should be valid JSON.");
+        assert_eq!(statistics.len(), board.legal_actions().len());
+        let visit_counts: Vec<u64> = statistics
+            .iter()
+            .map(|child| child["visit_count"].as_u64().unwrap())
+            .collect();
+        assert!(visit_counts.windows(2).all(|pair| pair[0] >= pair[1]));
+        let most_visited = board
+            .algebraic_to_action(statistics[0]["action"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(
+            visit_counts[0] as usize,
+            mcts.get_action_visit_counts()[&most_visited]
+        );
+    }
+
+    #[test]
+    fn test_with_config_clamps_batch_size() {
+        let board = Board::new_square(5, 4);
+        let config = MctsConfig {
+            batch_size: 0,
+            ..MctsConfig::default_for_board(&board)
+        };
+        assert_eq!(MCTS::with_config(&board, &config).config.batch_size, 1);
+    }
+
+    #[test]
+    fn test_config_n_iterations() {
+        let board = Board::new_square(5, 4);
+        let mut mcts = MCTS::new_rollout(&board, 10, 10);
+        mcts.config.n_iterations = 50;
+        mcts.get_best_action_with_rollouts(0.0);
+        assert!(mcts.root_visit_count() >= 50);
+    }
+
+    #[test]
+    fn test_immediate_action_skips_search() {
+        // Black threatens to complete four in a row at C3 on a 5x5 board.
+        let board =
+            Board::from_actions(5, 5, 4, &[[2, 0], [0, 0], [2, 1], [0, 4], [2, 2]]).unwrap();
+        assert_eq!(board.check_immediate_win(Player::Black), Some([2, 3]));
+        assert_eq!(board.check_immediate_win(Player::White), None);
+
+        let mut mcts = MCTS::new_rollout(&board, 1, 10);
+        assert_eq!(mcts.get_best_action_with_rollouts(0.0), [2, 3]);
+
+        let mut winning_board = board.clone();
+        winning_board.make_action([1, 1]).unwrap();
+        let mut mcts = MCTS::new_rollout(&winning_board, 1, 10);
+        assert_eq!(mcts.get_best_action_with_rollouts(0.0), [2, 3]);
+    }
 }
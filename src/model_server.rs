@@ -0,0 +1,189 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::utils::get_torchjit_policy_value_batch;
+
+/// A board to evaluate, with the flat indices of its legal actions,
+/// and where to send its policy and value.
+struct InferenceRequest {
+    board_tensor: tch::Tensor,
+    legal_indices: Vec<usize>,
+    response_sender: Sender<(Vec<f32>, f32)>,
+}
+
+/// Owns a model in a dedicated thread, evaluating the boards sent by any number of
+/// `ModelClient`s in batches. A batch is evaluated once it holds `batch_size` boards,
+/// or `timeout_us` microseconds after its first board arrived, whichever comes first.
+/// Searches sharing a server thus share its forward passes, instead of each
+/// waiting on its own.
+pub struct ModelServer {
+    request_sender: Option<Sender<InferenceRequest>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// A handle to send boards to a `ModelServer`. Clone it to share the server between threads.
+#[derive(Clone)]
+pub struct ModelClient {
+    request_sender: Sender<InferenceRequest>,
+}
+
+impl ModelServer {
+    pub fn spawn(
+        model: tch::CModule,
+        batch_size: usize,
+        timeout_us: u64,
+        policy_temperature: f32,
+    ) -> Self {
+        let (request_sender, request_receiver) = mpsc::channel();
+        let batch_size = batch_size.max(1);
+        let timeout = Duration::from_micros(timeout_us);
+        let handle = thread::spawn(move || {
+            serve(
+                &model,
+                request_receiver,
+                batch_size,
+                timeout,
+                policy_temperature,
+            )
+        });
+
+        Self {
+            request_sender: Some(request_sender),
+            handle: Some(handle),
+        }
+    }
+
+    pub fn client(&self) -> ModelClient {
+        ModelClient {
+            request_sender: self
+                .request_sender
+                .clone()
+                .expect("The sender is only taken when dropped."),
+        }
+    }
+}
+
+impl Drop for ModelServer {
+    /// Stops the server once every client has been dropped and every request answered.
+    fn drop(&mut self) {
+        drop(self.request_sender.take());
+        if let Some(handle) = self.handle.take() {
+            handle.join().expect("The server thread should not panic.");
+        }
+    }
+}
+
+impl ModelClient {
+    /// Same as `get_torchjit_policy_value`, blocking until the server has evaluated
+    /// the batch containing `board_tensor`.
+    pub fn evaluate(
+        &self,
+        board_tensor: tch::Tensor,
+        legal_indices: Vec<usize>,
+    ) -> (Vec<f32>, f32) {
+        let (response_sender, response_receiver) = mpsc::channel();
+        self.request_sender
+            .send(InferenceRequest {
+                board_tensor,
+                legal_indices,
+                response_sender,
+            })
+            .expect("The server runs until every client is dropped.");
+
+        response_receiver
+            .recv()
+            .expect("Every inference request is answered.")
+    }
+}
+
+/// Evaluates batches of requests until every sender has hung up.
+fn serve(
+    model: &tch::CModule,
+    requests: Receiver<InferenceRequest>,
+    batch_size: usize,
+    timeout: Duration,
+    policy_temperature: f32,
+) {
+    // Block until the first request of a batch, then wait at most `timeout` for the rest.
+    while let Ok(first_request) = requests.recv() {
+        let deadline = Instant::now() + timeout;
+        let mut batch = vec![first_request];
+        while batch.len() < batch_size {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match requests.recv_timeout(remaining) {
+                Ok(request) => batch.push(request),
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        let mut board_tensors = Vec::with_capacity(batch.len());
+        let mut legal_indices = Vec::with_capacity(batch.len());
+        let mut response_senders = Vec::with_capacity(batch.len());
+        for request in batch {
+            board_tensors.push(request.board_tensor);
+            legal_indices.push(request.legal_indices);
+            response_senders.push(request.response_sender);
+        }
+        let policies_values = get_torchjit_policy_value_batch(
+            model,
+            &board_tensors,
+            &legal_indices,
+            policy_temperature,
+        );
+        for (response_sender, policy_value) in response_senders.into_iter().zip(policies_values) {
+            // The client may have given up on the response, which is fine.
+            let _ = response_sender.send(policy_value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+    use crate::utils::{get_torchjit_model, get_torchjit_policy_value};
+
+    #[test]
+    #[ignore = "needs the TorchScript model old.pt"]
+    fn test_model_server() {
+        let model_path = "old.pt";
+        let model = get_torchjit_model(model_path);
+        let server = ModelServer::spawn(get_torchjit_model(model_path), 4, 1000, 1.0);
+
+        let mut boards = Vec::new();
+        let mut board = Board::new_square(9, 5);
+        for action in [[4, 4], [3, 3], [4, 5], [2, 2], [4, 3], [5, 5]] {
+            board.make_action(action).unwrap();
+            boards.push(board.clone());
+        }
+
+        thread::scope(|scope| {
+            for board in &boards {
+                let client = server.client();
+                let model = &model;
+                scope.spawn(move || {
+                    let legal_indices: Vec<usize> = board
+                        .legal_actions()
+                        .iter()
+                        .map(|action| board.action_to_flat_index(action))
+                        .collect();
+                    let (policy, value) =
+                        client.evaluate(board.to_flat_tensor(), legal_indices.clone());
+                    let (expected_policy, expected_value) = get_torchjit_policy_value(
+                        model,
+                        &board.to_flat_tensor(),
+                        &legal_indices,
+                        1.0,
+                    );
+
+                    assert_eq!(policy.len(), expected_policy.len());
+                    for (p, expected_p) in policy.iter().zip(&expected_policy) {
+                        assert!((p - expected_p).abs() < 1e-4);
+                    }
+                    assert!((value - expected_value).abs() < 1e-4);
+                });
+            }
+        });
+    }
+}
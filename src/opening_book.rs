@@ -0,0 +1,94 @@
+use std::fs;
+use std::sync::Arc;
+
+use indexmap::IndexMap;
+use serde::de::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::board::{Action, Board};
+use crate::utils::sample_from_weights;
+
+/// Book moves for the first `max_moves` moves of a game, keyed by the moves leading to
+/// a position in the notation of `Board::serialize_action_history`, e.g. "H8 J9".
+/// The empty string is the empty board.
+///
+/// The JSON file maps each position to the weights of its responses, e.g.
+/// `{"max_moves": 2, "entries": {"": {"H8": 1.0}, "H8": {"J9": 0.5, "H9": 0.5}}}`.
+/// The weights are finite and non-negative, and at least one per position is positive.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct OpeningBook {
+    pub max_moves: usize,
+    entries: IndexMap<String, IndexMap<String, f32>>,
+}
+
+impl OpeningBook {
+    pub fn from_json(s: &str) -> Result<Self, serde_json::Error> {
+        let opening_book: Self = serde_json::from_str(s)?;
+        for (position, responses) in &opening_book.entries {
+            let weights = responses.values();
+            if weights
+                .clone()
+                .any(|weight| !weight.is_finite() || *weight < 0.0)
+                || (!responses.is_empty() && weights.sum::<f32>() <= 0.0)
+            {
+                return Err(serde_json::Error::custom(format!(
+                    "the weights of the responses to \"{position}\" should be non-negative, \
+                     and not all zero"
+                )));
+            }
+        }
+
+        Ok(opening_book)
+    }
+
+    pub fn load(path: &str) -> Arc<Self> {
+        let json = fs::read_to_string(path).expect("Should be able to read the opening book");
+        Arc::new(Self::from_json(&json).expect("The opening book should be valid"))
+    }
+
+    /// Samples a response to the position of `board` proportionally to its weight.
+    /// Returns `None` past `max_moves` moves, when the position is not in the book,
+    /// or when the sampled response cannot be played.
+    pub fn get_book_action(&self, board: &Board) -> Option<Action> {
+        if board.move_history().len() >= self.max_moves || board.is_game_over() {
+            return None;
+        }
+
+        let responses = self.entries.get(&board.serialize_action_history())?;
+        if responses.is_empty() {
+            return None;
+        }
+
+        let weights: Vec<f32> = responses.values().copied().collect();
+        let (notation, _) = responses.get_index(sample_from_weights(&weights))?;
+        let action = board.parse_string_to_action(notation).ok()?;
+
+        if board.is_action_legal(action) && !board.is_forbidden(action) {
+            Some(action)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_json() {
+        let opening_book =
+            OpeningBook::from_json(r#"{"max_moves": 1, "entries": {"": {"C3": 0.0, "B2": 1.0}}}"#)
+                .unwrap();
+        let board = Board::new_square(5, 4);
+        assert_eq!(
+            opening_book.get_book_action(&board),
+            board.algebraic_to_action("B2").ok()
+        );
+
+        for invalid_weights in [r#"{"C3": 0.0}"#, r#"{"C3": -1.0, "B2": 2.0}"#] {
+            let json = format!(r#"{{"max_moves": 1, "entries": {{"": {invalid_weights}}}}}"#);
+            assert!(OpeningBook::from_json(&json).is_err());
+        }
+    }
+}
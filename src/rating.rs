@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+pub const INITIAL_ELO: f64 = 1500.0;
+pub const DEFAULT_K_FACTOR: f64 = 32.0;
+
+/// Tracks the Elo ratings of named players, such as model checkpoints, across runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EloRater {
+    pub k_factor: f64,
+    ratings: HashMap<String, f64>,
+    /// The name of the player promoted to "best", if any.
+    pub best: Option<String>,
+}
+
+impl Default for EloRater {
+    fn default() -> Self {
+        Self::new(DEFAULT_K_FACTOR)
+    }
+}
+
+impl EloRater {
+    pub fn new(k_factor: f64) -> Self {
+        Self {
+            k_factor,
+            ratings: HashMap::new(),
+            best: None,
+        }
+    }
+
+    /// Returns the rating of `name`, or the initial rating if it has not played yet.
+    pub fn rating(&self, name: &str) -> f64 {
+        self.ratings.get(name).copied().unwrap_or(INITIAL_ELO)
+    }
+
+    pub fn ratings(&self) -> &HashMap<String, f64> {
+        &self.ratings
+    }
+
+    /// The expected score of `a` against `b`, between 0.0 and 1.0.
+    pub fn expected_score(&self, a: &str, b: &str) -> f64 {
+        1.0 / (1.0 + 10f64.powf((self.rating(b) - self.rating(a)) / 400.0))
+    }
+
+    /// Records a game between `a` and `b`, where `score` is 1.0 if `a` won,
+    /// 0.0 if `b` won and 0.5 for a draw, and updates both of their ratings.
+    pub fn record_game(&mut self, a: &str, b: &str, score: f64) {
+        let rating_change = self.k_factor * (score - self.expected_score(a, b));
+        let rating_a = self.rating(a) + rating_change;
+        let rating_b = self.rating(b) - rating_change;
+        self.ratings.insert(a.to_string(), rating_a);
+        self.ratings.insert(b.to_string(), rating_b);
+    }
+
+    /// Returns the rating of the best player, if one has been promoted.
+    pub fn best_rating(&self) -> Option<f64> {
+        self.best.as_deref().map(|name| self.rating(name))
+    }
+
+    /// Promotes `name` to best if there is no best yet or if its rating exceeds the
+    /// best's rating. Returns whether `name` was promoted.
+    pub fn promote_if_better(&mut self, name: &str) -> bool {
+        let is_better = match self.best_rating() {
+            Some(best_rating) => self.rating(name) > best_rating,
+            None => true,
+        };
+        if is_better {
+            self.best = Some(name.to_string());
+        }
+
+        is_better
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Ratings should serialize to JSON.")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Loads the ratings saved at `path`, starting from no ratings if the file does not exist.
+    pub fn load(path: &str) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(json) => Self::from_json(&json).map_err(io::Error::from),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elo_rater() {
+        let mut rater = EloRater::new(32.0);
+        rater.record_game("a", "b", 1.0);
+        assert_eq!(rater.rating("a"), INITIAL_ELO + 16.0);
+        assert_eq!(rater.rating("b"), INITIAL_ELO - 16.0);
+        assert_eq!(rater.rating("c"), INITIAL_ELO);
+
+        assert!(rater.promote_if_better("b"));
+        assert!(rater.promote_if_better("a"));
+        assert!(!rater.promote_if_better("c"));
+        assert_eq!(rater.best.as_deref(), Some("a"));
+
+        let loaded = EloRater::from_json(&rater.to_json()).unwrap();
+        assert_eq!(loaded.rating("a"), rater.rating("a"));
+        assert_eq!(loaded.best, rater.best);
+    }
+}
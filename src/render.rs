@@ -0,0 +1,248 @@
+use image::{ColorType, ImageResult, Rgb, RgbImage};
+
+use crate::board::{Action, Board, Player, SquareState};
+
+const WOOD: Rgb<u8> = Rgb([220, 179, 92]);
+const GRID: Rgb<u8> = Rgb([60, 40, 20]);
+const BLACK_STONE: Rgb<u8> = Rgb([20, 20, 20]);
+const WHITE_STONE: Rgb<u8> = Rgb([235, 235, 230]);
+const LAST_MOVE: Rgb<u8> = Rgb([220, 30, 30]);
+
+/// Radii relative to the cell size.
+const STONE_RADIUS: f32 = 0.46;
+const LAST_MOVE_RADIUS: f32 = 0.12;
+
+/// A 3x5 pixel font for the labels of the rows and columns.
+/// Each row of a glyph is 3 bits, from the left column in the most significant bit.
+const GLYPHS: [(char, [u8; 5]); 36] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+];
+
+impl Board {
+    /// Renders the board as an image, with the stones on the intersections of a grid
+    /// spaced by `cell_size` pixels, the names of the rows and columns in the margins,
+    /// and a red dot on the last move.
+    pub fn to_image(&self, cell_size: u32) -> RgbImage {
+        let cell_size = cell_size.max(8);
+        let margin = cell_size;
+        let width = 2 * margin + (self.cols as u32 - 1) * cell_size;
+        let height = 2 * margin + (self.rows as u32 - 1) * cell_size;
+        let mut image = RgbImage::from_pixel(width, height, WOOD);
+        let center = |action: Action| {
+            (
+                (margin + action[1] as u32 * cell_size) as f32,
+                (margin + action[0] as u32 * cell_size) as f32,
+            )
+        };
+
+        // Grid
+        let line_width = (cell_size / 16).max(1);
+        let (first_x, first_y) = center([0, 0]);
+        let (last_x, last_y) = center([self.rows - 1, self.cols - 1]);
+        for row_index in 0..self.rows {
+            let (_, y) = center([row_index, 0]);
+            fill_rect(
+                &mut image,
+                first_x as u32,
+                y as u32,
+                last_x as u32 + line_width,
+                y as u32 + line_width,
+                GRID,
+            );
+        }
+        for col_index in 0..self.cols {
+            let (x, _) = center([0, col_index]);
+            fill_rect(
+                &mut image,
+                x as u32,
+                first_y as u32,
+                x as u32 + line_width,
+                last_y as u32 + line_width,
+                GRID,
+            );
+        }
+
+        // Labels, with the rows named from the bottom up like in `action_to_algebraic`.
+        let pixel_size = (cell_size / 12).max(1);
+        for row_index in 0..self.rows {
+            let name = &self.action_to_algebraic([row_index, 0])[1..];
+            let (_, y) = center([row_index, 0]);
+            draw_text_centered(&mut image, name, margin / 2, y as u32, pixel_size);
+        }
+        for col_index in 0..self.cols {
+            let name = &self.action_to_algebraic([0, col_index])[..1];
+            let (x, _) = center([0, col_index]);
+            draw_text_centered(&mut image, name, x as u32, height - margin / 2, pixel_size);
+        }
+
+        // Stones
+        let stone_radius = STONE_RADIUS * cell_size as f32;
+        for row_index in 0..self.rows {
+            for col_index in 0..self.cols {
+                if let SquareState::Occupied(player) = self.square_state([row_index, col_index]) {
+                    let (x, y) = center([row_index, col_index]);
+                    draw_stone(&mut image, x, y, stone_radius, *player);
+                }
+            }
+        }
+
+        if let Some(last_action) = self.last_action() {
+            let (x, y) = center(last_action);
+            fill_circle(
+                &mut image,
+                x,
+                y,
+                LAST_MOVE_RADIUS * cell_size as f32,
+                LAST_MOVE,
+            );
+        }
+
+        image
+    }
+
+    /// Saves `to_image` to `path`, in the format given by its extension, e.g. PNG.
+    pub fn save_image(&self, path: &str, cell_size: u32) -> ImageResult<()> {
+        let image = self.to_image(cell_size);
+        image::save_buffer(path, &image, image.width(), image.height(), ColorType::Rgb8)
+    }
+}
+
+/// Fills the pixels from (x0, y0) included to (x1, y1) excluded, clipped to the image.
+fn fill_rect(image: &mut RgbImage, x0: u32, y0: u32, x1: u32, y1: u32, color: Rgb<u8>) {
+    for y in y0..y1.min(image.height()) {
+        for x in x0..x1.min(image.width()) {
+            image.put_pixel(x, y, color);
+        }
+    }
+}
+
+/// Calls `shade` with the offset from the center of every pixel within `radius`,
+/// setting the pixel to the color it returns.
+fn shade_circle(
+    image: &mut RgbImage,
+    center_x: f32,
+    center_y: f32,
+    radius: f32,
+    shade: impl Fn(f32, f32) -> Rgb<u8>,
+) {
+    let x_range = (center_x - radius).max(0.0) as u32..=(center_x + radius) as u32;
+    for y in (center_y - radius).max(0.0) as u32..=(center_y + radius) as u32 {
+        for x in x_range.clone() {
+            let (dx, dy) = (x as f32 - center_x, y as f32 - center_y);
+            if dx * dx + dy * dy <= radius * radius && x < image.width() && y < image.height() {
+                image.put_pixel(x, y, shade(dx, dy));
+            }
+        }
+    }
+}
+
+fn fill_circle(image: &mut RgbImage, x: f32, y: f32, radius: f32, color: Rgb<u8>) {
+    shade_circle(image, x, y, radius, |_, _| color);
+}
+
+/// Draws a stone lit from the top left: a specular highlight fades into the color of
+/// the stone, which darkens towards its edge.
+fn draw_stone(image: &mut RgbImage, x: f32, y: f32, radius: f32, player: Player) {
+    let (base, highlight_strength) = match player {
+        Player::Black => (BLACK_STONE, 0.35),
+        Player::White => (WHITE_STONE, 0.9),
+    };
+    let (highlight_dx, highlight_dy) = (-0.35 * radius, -0.35 * radius);
+
+    shade_circle(image, x, y, radius, |dx, dy| {
+        let highlight_distance =
+            ((dx - highlight_dx).powi(2) + (dy - highlight_dy).powi(2)).sqrt() / radius;
+        let highlight = highlight_strength * (1.0 - highlight_distance).max(0.0).powi(2);
+        let edge_shadow = 0.25 * ((dx * dx + dy * dy).sqrt() / radius).powi(4);
+
+        Rgb(base.0.map(|channel| {
+            let lit = channel as f32 + (255.0 - channel as f32) * highlight;
+            (lit * (1.0 - edge_shadow)).round() as u8
+        }))
+    });
+}
+
+/// Draws `text` with the 3x5 font, scaled by `pixel_size` and centered on (x, y).
+fn draw_text_centered(image: &mut RgbImage, text: &str, x: u32, y: u32, pixel_size: u32) {
+    let n_chars = text.chars().count() as u32;
+    let text_width = (4 * n_chars - 1) * pixel_size;
+    let text_height = 5 * pixel_size;
+    let left = x.saturating_sub(text_width / 2);
+    let top = y.saturating_sub(text_height / 2);
+
+    for (char_index, character) in text.chars().enumerate() {
+        let Some((_, glyph)) = GLYPHS.iter().find(|(c, _)| *c == character) else {
+            continue;
+        };
+        let char_left = left + 4 * char_index as u32 * pixel_size;
+        for (glyph_row, bits) in glyph.iter().enumerate() {
+            for glyph_col in 0..3 {
+                if bits & (0b100 >> glyph_col) != 0 {
+                    let px = char_left + glyph_col * pixel_size;
+                    let py = top + glyph_row as u32 * pixel_size;
+                    fill_rect(image, px, py, px + pixel_size, py + pixel_size, GRID);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_board_image() {
+        let cell_size = 24;
+        let board = Board::from_actions(9, 9, 5, &[[4, 4], [4, 5], [3, 3]]).unwrap();
+        let image = board.to_image(cell_size);
+        assert_eq!(image.dimensions(), (10 * cell_size, 10 * cell_size));
+
+        // The centers of the stones, offset from the last move's red dot and the highlight.
+        let pixel_at = |action: Action, offset: u32| {
+            *image.get_pixel(
+                cell_size + action[1] as u32 * cell_size + offset,
+                cell_size + action[0] as u32 * cell_size + offset,
+            )
+        };
+        assert!(pixel_at([4, 4], 4).0.iter().all(|&channel| channel < 100));
+        assert!(pixel_at([4, 5], 4).0.iter().all(|&channel| channel > 150));
+        assert_eq!(pixel_at([3, 3], 0), LAST_MOVE);
+        assert_eq!(pixel_at([0, 0], cell_size / 2), WOOD);
+    }
+}
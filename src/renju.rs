@@ -0,0 +1,249 @@
+use crate::board::{Action, Board, Player, SquareState};
+
+/// The directions of the rows, columns and both diagonals through a square.
+const DIRECTIONS: [[i32; 2]; 4] = [[0, 1], [1, 0], [1, 1], [1, -1]];
+
+/// A square of a line, as seen by Black.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Cell {
+    Black,
+    Vacant,
+    /// A White stone or the edge of the board.
+    Blocked,
+}
+
+/// Checks whether Black playing `action` makes an overline, two fours or two open threes,
+/// which are forbidden under Renju rules. Making exactly `n_in_a_row` is never forbidden,
+/// as it wins the game.
+///
+/// Open threes are not checked for whether the straight four they lead to
+/// would itself be forbidden.
+pub fn is_forbidden_for_black(board: &Board, action: Action) -> bool {
+    let n_in_a_row = board.n_in_a_row;
+    // The action is at the center of each line.
+    let center = n_in_a_row + 1;
+    let lines: Vec<Vec<Cell>> = DIRECTIONS
+        .iter()
+        .map(|direction| line_through(board, action, *direction, center))
+        .collect();
+
+    let run_lengths: Vec<usize> = lines
+        .iter()
+        .map(|line| {
+            let (start, end) = run_bounds(line, center);
+            end - start + 1
+        })
+        .collect();
+    if run_lengths.contains(&n_in_a_row) {
+        return false;
+    }
+    if run_lengths
+        .iter()
+        .any(|&run_length| run_length > n_in_a_row)
+    {
+        return true;
+    }
+
+    let fours_per_line: Vec<usize> = lines
+        .iter()
+        .map(|line| count_fours(line, center, n_in_a_row))
+        .collect();
+    if fours_per_line.iter().sum::<usize>() >= 2 {
+        return true;
+    }
+
+    let n_open_threes = lines
+        .iter()
+        .zip(fours_per_line)
+        .filter(|(line, n_fours)| *n_fours == 0 && has_open_three(line, center, n_in_a_row))
+        .count();
+    n_open_threes >= 2
+}
+
+/// Returns the `2 * radius + 1` cells of the line through `action` along `direction`,
+/// with a Black stone on `action` itself.
+fn line_through(board: &Board, action: Action, direction: [i32; 2], radius: usize) -> Vec<Cell> {
+    let radius = radius as i32;
+    let [row_step, col_step] = direction;
+
+    (-radius..=radius)
+        .map(|offset| {
+            if offset == 0 {
+                return Cell::Black;
+            }
+
+            let row_index = action[0] as i32 + offset * row_step;
+            let col_index = action[1] as i32 + offset * col_step;
+            if !(0..board.rows as i32).contains(&row_index)
+                || !(0..board.cols as i32).contains(&col_index)
+            {
+                return Cell::Blocked;
+            }
+
+            match board.square_state([row_index as usize, col_index as usize]) {
+                SquareState::Occupied(Player::Black) => Cell::Black,
+                SquareState::Occupied(Player::White) => Cell::Blocked,
+                SquareState::Vacant => Cell::Vacant,
+            }
+        })
+        .collect()
+}
+
+/// Returns the first and last indices of the run of Black stones through `center`.
+fn run_bounds(line: &[Cell], center: usize) -> (usize, usize) {
+    let mut start = center;
+    while start > 0 && line[start - 1] == Cell::Black {
+        start -= 1;
+    }
+
+    let mut end = center;
+    while end + 1 < line.len() && line[end + 1] == Cell::Black {
+        end += 1;
+    }
+
+    (start, end)
+}
+
+/// Returns the vacant squares of `line` that would make exactly `n_in_a_row`
+/// through `center` if Black played them.
+fn five_points(line: &[Cell], center: usize, n_in_a_row: usize) -> Vec<usize> {
+    let mut line = line.to_vec();
+
+    (0..line.len())
+        .filter(|&index| {
+            if line[index] != Cell::Vacant {
+                return false;
+            }
+
+            line[index] = Cell::Black;
+            let (start, end) = run_bounds(&line, center);
+            line[index] = Cell::Vacant;
+            end - start + 1 == n_in_a_row
+        })
+        .collect()
+}
+
+/// Counts the fours through `center`, i.e. the shapes that one more stone turns into
+/// exactly `n_in_a_row`. A straight four, which can be completed on either end,
+/// counts as a single four.
+fn count_fours(line: &[Cell], center: usize, n_in_a_row: usize) -> usize {
+    let points = five_points(line, center, n_in_a_row);
+
+    if points.len() == 2 && points[1] - points[0] == n_in_a_row {
+        1
+    } else {
+        points.len()
+    }
+}
+
+/// Checks whether one more stone can turn the stones through `center` into
+/// a straight four, i.e. a four that can be completed on either end.
+fn has_open_three(line: &[Cell], center: usize, n_in_a_row: usize) -> bool {
+    let mut line = line.to_vec();
+
+    (0..line.len()).any(|index| {
+        if line[index] != Cell::Vacant {
+            return false;
+        }
+
+        line[index] = Cell::Black;
+        let (start, end) = run_bounds(&line, center);
+        let is_straight_four = (start..=end).contains(&index)
+            && end - start + 1 == n_in_a_row - 1
+            && five_points(&line, center, n_in_a_row).len() == 2;
+        line[index] = Cell::Vacant;
+        is_straight_four
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Black's move in every position, at the center of a 15x15 board.
+    const ACTION: Action = [7, 7];
+
+    /// Returns the 15x15 position with Black to move, padded with White stones on the
+    /// bottom row, out of reach of the lines through `ACTION`.
+    fn position(black_stones: &[Action], white_stones: &[Action]) -> Board {
+        let padding = (0..15)
+            .step_by(2)
+            .map(|col_index| [14, col_index])
+            .take(black_stones.len() - white_stones.len());
+        let white_stones: Vec<Action> = white_stones.iter().copied().chain(padding).collect();
+
+        Board::new_from_position(15, 5, black_stones, &white_stones)
+            .expect("The position should be reachable.")
+    }
+
+    #[test]
+    fn test_double_three() {
+        let board = position(&[[7, 5], [7, 6], [5, 7], [6, 7]], &[]);
+        assert!(is_forbidden_for_black(&board, ACTION));
+
+        let board = position(&[[7, 5], [7, 6]], &[]);
+        assert!(!is_forbidden_for_black(&board, ACTION));
+    }
+
+    #[test]
+    fn test_double_four() {
+        let board = position(
+            &[[7, 4], [7, 5], [7, 6], [4, 7], [5, 7], [6, 7]],
+            &[[7, 3], [3, 7]],
+        );
+        assert!(is_forbidden_for_black(&board, ACTION));
+
+        // Both X X X _ A and A _ X X X are fours, on a single line.
+        let board = position(&[[7, 3], [7, 4], [7, 5], [7, 9], [7, 10], [7, 11]], &[]);
+        assert!(is_forbidden_for_black(&board, ACTION));
+    }
+
+    #[test]
+    fn test_overline() {
+        let board = position(&[[7, 3], [7, 4], [7, 5], [7, 6], [7, 9]], &[]);
+        // Exactly five wins, even though filling the square next to it would make six.
+        assert!(!is_forbidden_for_black(&board, ACTION));
+
+        let board = position(&[[7, 3], [7, 4], [7, 5], [7, 6], [7, 8]], &[]);
+        assert!(is_forbidden_for_black(&board, ACTION));
+    }
+
+    #[test]
+    fn test_blocked_threes_are_not_open() {
+        // The horizontal three is blocked by White.
+        let board = position(&[[7, 5], [7, 6], [5, 7], [6, 7]], &[[7, 4]]);
+        assert!(!is_forbidden_for_black(&board, ACTION));
+
+        // The horizontal three is blocked by the edge of the board.
+        let board = position(&[[7, 0], [7, 1], [5, 2], [6, 2]], &[]);
+        assert!(!is_forbidden_for_black(&board, [7, 2]));
+    }
+
+    #[test]
+    fn test_straight_four_is_a_single_four() {
+        let center = 6;
+        let line = [
+            Cell::Blocked,
+            Cell::Vacant,
+            Cell::Vacant,
+            Cell::Black,
+            Cell::Black,
+            Cell::Black,
+            Cell::Black,
+            Cell::Vacant,
+            Cell::Vacant,
+            Cell::Vacant,
+            Cell::Vacant,
+            Cell::Vacant,
+            Cell::Vacant,
+        ];
+        assert_eq!(five_points(&line, center, 5), vec![2, 7]);
+        assert_eq!(count_fours(&line, center, 5), 1);
+
+        // A straight four along with an open three is allowed, but not with another four.
+        let board = position(&[[7, 4], [7, 5], [7, 6], [5, 7], [6, 7]], &[]);
+        assert!(!is_forbidden_for_black(&board, ACTION));
+        let board = position(&[[7, 4], [7, 5], [7, 6], [4, 7], [5, 7], [6, 7]], &[[3, 7]]);
+        assert!(is_forbidden_for_black(&board, ACTION));
+    }
+}
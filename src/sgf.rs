@@ -0,0 +1,186 @@
+use crate::board::{Action, Player};
+
+/// The SGF game number of Gomoku.
+const SGF_GOMOKU: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SgfError {
+    /// The record does not start with a game tree, i.e. "(;".
+    MissingGameTree,
+    /// The record ends inside a property value or before the game tree is closed.
+    UnexpectedEnd,
+    /// The `GM` property is not Gomoku.
+    UnsupportedGame(String),
+    /// The `SZ` property is not a valid size.
+    InvalidSize(String),
+    /// A move is not two letters, or is outside of the board.
+    InvalidCoordinate(String),
+    /// A move is played by the player not to move.
+    OutOfTurn(Player),
+}
+
+/// Writes the `moves` of a game, Black first, as an SGF record.
+/// SGF has no property for the number of aligned stones needed to win,
+/// so `n_in_a_row` is recorded in the rules property, e.g. `RU[5-in-a-row]`.
+pub fn export_sgf(moves: &[Action], size: usize, n_in_a_row: usize) -> String {
+    let mut sgf = format!(
+        "(;FF[4]GM[{}]SZ[{}]RU[{}-in-a-row]AP[Gomokrust]",
+        SGF_GOMOKU, size, n_in_a_row
+    );
+
+    let mut player = Player::Black;
+    for action in moves {
+        let color = match player {
+            Player::Black => "B",
+            Player::White => "W",
+        };
+        sgf.push_str(&format!(
+            ";{}[{}]",
+            color,
+            action_to_sgf_coordinate(*action)
+        ));
+        player = player.opposite();
+    }
+
+    sgf.push(')');
+    sgf
+}
+
+/// Reads the moves of the main line of an SGF record, i.e. the first variation at every branch.
+/// The moves must alternate, Black first, and be within `SZ` when it is given.
+pub fn import_sgf(sgf: &str) -> Result<Vec<Action>, SgfError> {
+    let sgf = sgf.trim_start();
+    if !sgf.starts_with('(') || !sgf[1..].trim_start().starts_with(';') {
+        return Err(SgfError::MissingGameTree);
+    }
+
+    let mut actions = Vec::new();
+    let mut size: Option<usize> = None;
+    let mut player = Player::Black;
+    let mut chars = sgf.chars();
+    // The identifier applies to all of the values that follow it, e.g. "AB[aa][bb]".
+    let mut property_identifier = String::new();
+    let mut is_reading_identifier = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            // The main line ends with its first variation.
+            ')' => return Ok(actions),
+            '[' => {
+                is_reading_identifier = false;
+                let value = read_property_value(&mut chars)?;
+                match property_identifier.as_str() {
+                    "GM" if value.trim() != SGF_GOMOKU.to_string() => {
+                        return Err(SgfError::UnsupportedGame(value));
+                    }
+                    "SZ" => {
+                        size = Some(
+                            value
+                                .trim()
+                                .parse()
+                                .map_err(|_| SgfError::InvalidSize(value.clone()))?,
+                        )
+                    }
+                    "B" | "W" => {
+                        let move_player = if property_identifier == "B" {
+                            Player::Black
+                        } else {
+                            Player::White
+                        };
+                        if move_player != player {
+                            return Err(SgfError::OutOfTurn(move_player));
+                        }
+
+                        let action = sgf_coordinate_to_action(&value)
+                            .filter(|action| {
+                                size.is_none_or(|size| action.iter().all(|&i| i < size))
+                            })
+                            .ok_or(SgfError::InvalidCoordinate(value))?;
+                        actions.push(action);
+                        player = player.opposite();
+                    }
+                    _ => (),
+                }
+            }
+            c if c.is_ascii_uppercase() => {
+                if !is_reading_identifier {
+                    property_identifier.clear();
+                    is_reading_identifier = true;
+                }
+                property_identifier.push(c);
+            }
+            _ => is_reading_identifier = false,
+        }
+    }
+
+    Err(SgfError::UnexpectedEnd)
+}
+
+/// Reads a property value up to its closing bracket, unescaping "\]" and "\\".
+fn read_property_value(chars: &mut impl Iterator<Item = char>) -> Result<String, SgfError> {
+    let mut value = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            ']' => return Ok(value),
+            '\\' => value.push(chars.next().ok_or(SgfError::UnexpectedEnd)?),
+            c => value.push(c),
+        }
+    }
+
+    Err(SgfError::UnexpectedEnd)
+}
+
+/// Converts an Action to SGF coordinates, i.e. the column then the row letter,
+/// where "aa" is the top left square.
+fn action_to_sgf_coordinate(action: Action) -> String {
+    let [row_index, col_index] = action;
+    [col_index, row_index]
+        .iter()
+        .map(|&index| (b'a' + index as u8) as char)
+        .collect()
+}
+
+fn sgf_coordinate_to_action(coordinate: &str) -> Option<Action> {
+    match coordinate.as_bytes() {
+        [col @ b'a'..=b'z', row @ b'a'..=b'z'] => {
+            Some([(row - b'a') as usize, (col - b'a') as usize])
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_sgf() {
+        let moves = [[7, 7], [6, 8], [0, 14]];
+        assert_eq!(import_sgf(&export_sgf(&moves, 15, 5)), Ok(moves.to_vec()));
+        // The comment holds an escaped bracket, which does not end its value.
+        assert_eq!(
+            import_sgf(r"(;GM[4]SZ[15]C[a \] b];B[hh](;W[ig])(;W[aa]))"),
+            Ok(vec![[7, 7], [6, 8]])
+        );
+
+        assert_eq!(import_sgf(";B[hh])"), Err(SgfError::MissingGameTree));
+        assert_eq!(import_sgf("(;B[hh]"), Err(SgfError::UnexpectedEnd));
+        assert_eq!(import_sgf(r"(;C[a \]"), Err(SgfError::UnexpectedEnd));
+        assert_eq!(
+            import_sgf("(;GM[1];B[hh])"),
+            Err(SgfError::UnsupportedGame(String::from("1")))
+        );
+        assert_eq!(
+            import_sgf("(;SZ[big];B[hh])"),
+            Err(SgfError::InvalidSize(String::from("big")))
+        );
+        assert_eq!(
+            import_sgf("(;B[hh];B[ii])"),
+            Err(SgfError::OutOfTurn(Player::Black))
+        );
+        assert_eq!(
+            import_sgf("(;SZ[9];B[ee];W[ej])"),
+            Err(SgfError::InvalidCoordinate(String::from("ej")))
+        );
+    }
+}
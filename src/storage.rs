@@ -0,0 +1,129 @@
+use rusqlite::{params, Connection};
+
+/// One position of a self-play game, as stored in a `DataStore`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameStep {
+    /// The UUID of the game the position was played in.
+    pub game_id: String,
+    pub state: Vec<f32>,
+    pub policy: Vec<f32>,
+    pub value: f32,
+    /// The number of moves played before the position, starting from 0.
+    pub move_number: usize,
+}
+
+/// Self-play positions stored in a SQLite database, as an alternative to
+/// writing one file per game.
+pub struct DataStore {
+    connection: Connection,
+}
+
+impl DataStore {
+    /// Opens the database at `path`, creating it and its `games` table if needed.
+    /// A `path` of ":memory:" opens a database that is discarded when dropped.
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS games (
+                id INTEGER PRIMARY KEY,
+                game_id TEXT NOT NULL,
+                state BLOB NOT NULL,
+                policy BLOB NOT NULL,
+                value REAL NOT NULL,
+                move_number INTEGER NOT NULL,
+                timestamp TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+
+        Ok(Self { connection })
+    }
+
+    /// Inserts all the positions of a game in a single transaction.
+    pub fn insert_game(&mut self, steps: &[GameStep]) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT INTO games (game_id, state, policy, value, move_number)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for step in steps {
+                statement.execute(params![
+                    step.game_id,
+                    floats_to_blob(&step.state),
+                    floats_to_blob(&step.policy),
+                    step.value,
+                    step.move_number,
+                ])?;
+            }
+        }
+
+        transaction.commit()
+    }
+
+    /// Returns up to `n` positions picked uniformly at random among all the games.
+    pub fn sample_batch(&self, n: usize) -> rusqlite::Result<Vec<GameStep>> {
+        let mut statement = self.connection.prepare(
+            "SELECT game_id, state, policy, value, move_number
+             FROM games ORDER BY RANDOM() LIMIT ?1",
+        )?;
+        let steps = statement.query_map([n], |row| {
+            Ok(GameStep {
+                game_id: row.get(0)?,
+                state: blob_to_floats(&row.get::<_, Vec<u8>>(1)?),
+                policy: blob_to_floats(&row.get::<_, Vec<u8>>(2)?),
+                value: row.get(3)?,
+                move_number: row.get(4)?,
+            })
+        })?;
+
+        steps.collect()
+    }
+
+    /// Returns the number of positions stored.
+    pub fn count(&self) -> rusqlite::Result<usize> {
+        self.connection
+            .query_row("SELECT COUNT(*) FROM games", [], |row| row.get(0))
+    }
+}
+
+/// Encodes the floats as little-endian bytes.
+fn floats_to_blob(floats: &[f32]) -> Vec<u8> {
+    floats
+        .iter()
+        .flat_map(|float| float.to_le_bytes())
+        .collect()
+}
+
+fn blob_to_floats(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_store() {
+        let mut data_store =
+            DataStore::open(":memory:").expect("Should open an in-memory database.");
+        let steps: Vec<GameStep> = (0..3)
+            .map(|move_number| GameStep {
+                game_id: String::from("game"),
+                state: vec![move_number as f32, -1.0, 0.5],
+                policy: vec![0.25, 0.75],
+                value: -1.0,
+                move_number,
+            })
+            .collect();
+        data_store.insert_game(&steps).unwrap();
+        assert_eq!(data_store.count().unwrap(), 3);
+
+        let mut sampled_steps = data_store.sample_batch(10).unwrap();
+        sampled_steps.sort_by_key(|step| step.move_number);
+        assert_eq!(sampled_steps, steps);
+        assert_eq!(data_store.sample_batch(2).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::board::{Outcome, Player};
+use crate::game::{Agent, Referee};
+use crate::rating::EloRater;
+
+/// The results of an agent against one or more opponents.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+}
+
+impl Record {
+    fn add(&mut self, other: &Record) {
+        self.wins += other.wins;
+        self.losses += other.losses;
+        self.draws += other.draws;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub rating: f64,
+    pub record: Record,
+}
+
+/// Runs round-robin tournaments between agents, where every pair of agents plays
+/// `n_games_per_pair` games with alternating colors, and rates the agents with Elo.
+/// Agents are rated by name, so agents should have distinct names.
+pub struct TournamentManager {
+    agents: Vec<Box<dyn Agent>>,
+    pub size: usize,
+    pub n_in_a_row: usize,
+    pub n_games_per_pair: usize,
+    /// `records[i][j]` is the record of agent `i` against agent `j`.
+    records: Vec<Vec<Record>>,
+    pub rater: EloRater,
+}
+
+impl TournamentManager {
+    pub fn new(
+        agents: Vec<Box<dyn Agent>>,
+        size: usize,
+        n_in_a_row: usize,
+        n_games_per_pair: usize,
+    ) -> Self {
+        let n_agents = agents.len();
+        Self {
+            agents,
+            size,
+            n_in_a_row,
+            n_games_per_pair,
+            records: vec![vec![Record::default(); n_agents]; n_agents],
+            rater: EloRater::default(),
+        }
+    }
+
+    /// Starts the ratings from those of `rater`, e.g. ratings saved by an earlier run.
+    pub fn with_rater(mut self, rater: EloRater) -> Self {
+        self.rater = rater;
+        self
+    }
+
+    /// Plays every pair of agents against each other, updating the records and ratings
+    /// after each game.
+    pub fn run(&mut self) {
+        let n_agents = self.agents.len();
+        for first_index in 0..n_agents {
+            for second_index in first_index + 1..n_agents {
+                for game_index in 0..self.n_games_per_pair {
+                    let (black_index, white_index) = if game_index % 2 == 0 {
+                        (first_index, second_index)
+                    } else {
+                        (second_index, first_index)
+                    };
+
+                    let outcome = self.play_game(black_index, white_index);
+                    // Elo scores a loss as 0.0 rather than -1.0.
+                    let black_score = (outcome.score_for(Player::Black) as f64 + 1.0) / 2.0;
+                    self.record_result(black_index, white_index, black_score);
+                }
+            }
+        }
+    }
+
+    /// Plays a single game between the agents at `black_index` and `white_index`.
+    fn play_game(&mut self, black_index: usize, white_index: usize) -> Outcome {
+        // Borrow both agents mutably, through the halves on either side of the later index.
+        let (black, white) = if black_index < white_index {
+            let (left, right) = self.agents.split_at_mut(white_index);
+            (&mut left[black_index], &mut right[0])
+        } else {
+            let (left, right) = self.agents.split_at_mut(black_index);
+            (&mut right[0], &mut left[white_index])
+        };
+
+        Referee::play_between(
+            black.as_mut(),
+            white.as_mut(),
+            self.size,
+            self.n_in_a_row,
+            false,
+        )
+        .expect("Agents should only return legal actions.")
+        .outcome
+    }
+
+    /// Records a game between agents `a` and `b`, where `score` is 1.0 if `a` won,
+    /// 0.0 if `b` won and 0.5 for a draw, and updates both of their Elo ratings.
+    fn record_result(&mut self, a: usize, b: usize, score: f64) {
+        if score == 1.0 {
+            self.records[a][b].wins += 1;
+            self.records[b][a].losses += 1;
+        } else if score == 0.0 {
+            self.records[a][b].losses += 1;
+            self.records[b][a].wins += 1;
+        } else {
+            self.records[a][b].draws += 1;
+            self.records[b][a].draws += 1;
+        }
+
+        let (name_a, name_b) = (self.agents[a].name(), self.agents[b].name());
+        self.rater.record_game(&name_a, &name_b, score);
+    }
+
+    /// Returns the record of the agent at `index` against the agent at `opponent_index`.
+    pub fn record_between(&self, index: usize, opponent_index: usize) -> Record {
+        self.records[index][opponent_index]
+    }
+
+    /// Returns every agent with its rating and overall record, from the highest rating.
+    pub fn leaderboard(&self) -> Vec<LeaderboardEntry> {
+        let mut leaderboard: Vec<LeaderboardEntry> = self
+            .agents
+            .iter()
+            .enumerate()
+            .map(|(index, agent)| {
+                let mut record = Record::default();
+                for opponent_record in &self.records[index] {
+                    record.add(opponent_record);
+                }
+
+                let name = agent.name();
+                LeaderboardEntry {
+                    rating: self.rater.rating(&name),
+                    name,
+                    record,
+                }
+            })
+            .collect();
+
+        leaderboard.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+        leaderboard
+    }
+
+    pub fn print_leaderboard(&self) {
+        println!("Rank  Elo   W-L-D  Agent");
+        for (rank, entry) in self.leaderboard().iter().enumerate() {
+            println!(
+                "{:>4}  {:>4.0}  {}-{}-{}  {}",
+                rank + 1,
+                entry.rating,
+                entry.record.wins,
+                entry.record.losses,
+                entry.record.draws,
+                entry.name
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{Action, Board};
+
+    /// Fills its own row from the left, so whoever plays Black wins the race.
+    struct RowAgent {
+        row: usize,
+    }
+
+    impl Agent for RowAgent {
+        fn get_action(&mut self, board: &Board) -> Action {
+            *board
+                .legal_actions()
+                .iter()
+                .filter(|action| action[0] == self.row)
+                .min()
+                .expect("The row should not fill up before the game is over.")
+        }
+
+        fn name(&self) -> String {
+            format!("Row {}", self.row)
+        }
+    }
+
+    #[test]
+    fn test_tournament() {
+        let agents: Vec<Box<dyn Agent>> =
+            vec![Box::new(RowAgent { row: 0 }), Box::new(RowAgent { row: 2 })];
+        let mut tournament = TournamentManager::new(agents, 5, 3, 3);
+        tournament.run();
+
+        // Colors alternate, so the first agent is Black, and wins, in two of the three games.
+        let first_record = Record {
+            wins: 2,
+            losses: 1,
+            draws: 0,
+        };
+        let second_record = Record {
+            wins: 1,
+            losses: 2,
+            draws: 0,
+        };
+        assert_eq!(tournament.record_between(0, 1), first_record);
+        assert_eq!(tournament.record_between(1, 0), second_record);
+        assert_eq!(tournament.record_between(0, 0), Record::default());
+
+        let leaderboard = tournament.leaderboard();
+        let names: Vec<&str> = leaderboard
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(names, ["Row 0", "Row 2"]);
+        assert!(leaderboard[0].rating > leaderboard[1].rating);
+        assert_eq!(leaderboard[0].record, first_record);
+        assert_eq!(leaderboard[1].record, second_record);
+    }
+}
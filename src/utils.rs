@@ -1,3 +1,5 @@
+use std::iter::zip;
+
 use indexmap::IndexSet;
 use rand::distributions::WeightedIndex;
 use rand::prelude::*;
@@ -8,7 +10,12 @@ extern crate tch;
 use crate::board::Action;
 
 pub fn get_random_action(legal_moves: &IndexSet<Action>) -> Action {
-    let random_index = rand::thread_rng().gen_range(0..legal_moves.len());
+    get_random_action_with_rng(legal_moves, &mut rand::thread_rng())
+}
+
+/// Same as `get_random_action`, drawing from `rng`, e.g. for reproducible searches.
+pub fn get_random_action_with_rng(legal_moves: &IndexSet<Action>, rng: &mut impl Rng) -> Action {
+    let random_index = rng.gen_range(0..legal_moves.len());
 
     *legal_moves
         .get_index(random_index)
@@ -19,9 +26,14 @@ pub fn get_torchjit_model(path: &str) -> tch::CModule {
     tch::CModule::load(path).expect("Should be able to load the model")
 }
 
+/// Evaluates a board tensor, returning a policy over the `legal_indices` only,
+/// i.e. the flat indices of the legal actions, and the value.
+/// The logits of the policy are scaled by `temperature`, as in `temperature_scaled_softmax`.
 pub fn get_torchjit_policy_value(
     model: &tch::CModule,
     board_tensor: &tch::Tensor,
+    legal_indices: &[usize],
+    temperature: f32,
 ) -> (Vec<f32>, f32) {
     let outputs = model
         .forward_ts(&[board_tensor])
@@ -29,8 +41,40 @@ pub fn get_torchjit_policy_value(
 
     let outputs: Vec<f32> = outputs.get(0).into();
 
-    let policy_logits = outputs[0..outputs.len() - 1].to_vec();
-    let policies = softmax(policy_logits);
+    split_policy_value(outputs, legal_indices, temperature)
+}
+
+/// Evaluates a batch of board tensors with a single forward pass,
+/// returning the policy and value of each board in order.
+/// The policy of each board is masked by its own `legal_indices`.
+pub fn get_torchjit_policy_value_batch(
+    model: &tch::CModule,
+    board_tensors: &[tch::Tensor],
+    legal_indices: &[Vec<usize>],
+    temperature: f32,
+) -> Vec<(Vec<f32>, f32)> {
+    let batch_tensor = tch::Tensor::cat(board_tensors, 0);
+    let outputs = model
+        .forward_ts(&[batch_tensor])
+        .expect("Should return a tensor");
+
+    (0..board_tensors.len())
+        .map(|i| {
+            let outputs: Vec<f32> = outputs.get(i as i64).into();
+            split_policy_value(outputs, &legal_indices[i], temperature)
+        })
+        .collect()
+}
+
+/// Splits the outputs of the model into the policy, i.e. the softmax of the logits
+/// of the legal actions, and the value, which is the last output.
+fn split_policy_value(
+    outputs: Vec<f32>,
+    legal_indices: &[usize],
+    temperature: f32,
+) -> (Vec<f32>, f32) {
+    let policy_logits = &outputs[0..outputs.len() - 1];
+    let policies = masked_temperature_scaled_softmax(policy_logits, legal_indices, temperature);
     let value = outputs[outputs.len() - 1];
 
     (policies, value)
@@ -45,8 +89,67 @@ pub fn softmax(logits: Vec<f32>) -> Vec<f32> {
     softmax
 }
 
+/// Same as `softmax`, with the logits divided by `temperature`, which must be positive.
+/// Temperatures above 1.0 flatten the distribution and temperatures below 1.0 sharpen it,
+/// down to almost always picking the largest logit as the temperature approaches 0.0.
+pub fn temperature_scaled_softmax(logits: Vec<f32>, temperature: f32) -> Vec<f32> {
+    softmax(logits.iter().map(|logit| logit / temperature).collect())
+}
+
+/// Turns the raw policy logits of the model, one per square, into a policy in which
+/// occupied and otherwise illegal squares get no probability: every logit outside of
+/// `legal_indices` is set to negative infinity before the softmax, so that the
+/// probabilities sum to 1 over the legal indices only.
+pub fn masked_policy(raw_policy: &[f32], legal_indices: &[usize]) -> Vec<f32> {
+    masked_temperature_scaled_softmax(raw_policy, legal_indices, 1.0)
+}
+
+fn masked_temperature_scaled_softmax(
+    logits: &[f32],
+    legal_indices: &[usize],
+    temperature: f32,
+) -> Vec<f32> {
+    // Without any legal index, every probability would be NaN.
+    if legal_indices.is_empty() {
+        return vec![0.0; logits.len()];
+    }
+
+    let mut masked_logits = vec![f32::NEG_INFINITY; logits.len()];
+    for &index in legal_indices {
+        masked_logits[index] = logits[index];
+    }
+
+    temperature_scaled_softmax(masked_logits, temperature)
+}
+
+/// Returns the `k` actions of `legal` with the highest probabilities, sorted by descending
+/// probability, where `policy[i]` is the probability of the `i`-th action of `legal`.
+/// Actions of equal probability keep their order in `legal`.
+pub fn top_k_actions(policy: &[f32], k: usize, legal: &IndexSet<Action>) -> Vec<(Action, f32)> {
+    let mut actions: Vec<(Action, f32)> =
+        zip(legal.iter().copied(), policy.iter().copied()).collect();
+    actions.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    actions.truncate(k);
+
+    actions
+}
+
+/// The Shannon entropy of a policy, in nats, where 0·log(0) = 0. High when the policy
+/// is uncertain, and 0 when all of the probability is on a single action.
+pub fn policy_entropy(policy: &[f32]) -> f32 {
+    -policy
+        .iter()
+        .filter(|&&p| p > 0.0)
+        .map(|&p| p * p.ln())
+        .sum::<f32>()
+}
+
 pub fn sample_from_weights(weights: &Vec<f32>) -> usize {
+    sample_from_weights_with_rng(weights, &mut thread_rng())
+}
+
+/// Same as `sample_from_weights`, drawing from `rng`, e.g. for reproducible searches.
+pub fn sample_from_weights_with_rng(weights: &[f32], rng: &mut impl Rng) -> usize {
     let dist = WeightedIndex::new(weights).unwrap();
-    let mut rng = thread_rng();
-    dist.sample(&mut rng)
+    dist.sample(rng)
 }